@@ -0,0 +1,419 @@
+use crate::durable;
+use crate::sapper_core::hash_reader;
+use serde::{ Deserialize, Serialize };
+use std::collections::{ BTreeMap, BTreeSet };
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use walkdir::WalkDir;
+
+/// Which files of an import's attachments folder are links into the blob store
+pub const MANIFEST_FILE: &str = "blobs.json";
+/// Number of imports referencing each blob
+const REFCOUNTS_FILE: &str = "refcounts.json";
+
+/// Imports run on their own SapperCore instances, so refcount updates are serialized here
+static REFCOUNT_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobManifest {
+    pub files: BTreeMap<String, String>, // Path relative to attachments -> blob name
+}
+
+/// What moving an import's media into the store saved
+#[derive(Debug, Default)]
+pub struct AdoptOutcome {
+    pub files_deduplicated: usize,
+    pub bytes_saved: u64,
+}
+
+/// SHA-256 content-addressed media shared by all imports, under .sapper/blobs. Each
+/// import keeps its attachments folder layout, with files hard-linked to their blob,
+/// so media used by several imports is stored once.
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new(sapper_dir: &Path) -> Self {
+        Self { root: sapper_dir.join("blobs") }
+    }
+
+    /// Blobs keep the file extension so viewers can tell images and videos apart
+    pub fn blob_path(&self, blob: &str) -> PathBuf {
+        self.root.join(blob.get(..2).unwrap_or_default()).join(blob)
+    }
+
+    pub fn load_manifest(import_dir: &Path) -> io::Result<BlobManifest> {
+        let path = import_dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(BlobManifest::default());
+        }
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn save_manifest(import_dir: &Path, manifest: &BlobManifest) -> io::Result<()> {
+        write_json(&import_dir.join(MANIFEST_FILE), manifest)
+    }
+
+    fn load_refcounts(&self) -> io::Result<BTreeMap<String, u64>> {
+        let path = self.root.join(REFCOUNTS_FILE);
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Move the files of an import's attachments folder into the store, leaving hard links
+    /// behind. Files already adopted are skipped, so this can run again after a resume.
+    pub fn adopt_import(&self, import_dir: &Path) -> io::Result<AdoptOutcome> {
+        let attachments_dir = import_dir.join("attachments");
+        let mut manifest = Self::load_manifest(import_dir)?;
+        let referenced_before: BTreeSet<String> = manifest.files.values().cloned().collect();
+        let mut outcome = AdoptOutcome::default();
+
+        let files: Vec<PathBuf> = WalkDir::new(&attachments_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+
+        for path in files {
+            let Ok(relative) = path.strip_prefix(&attachments_dir) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            if manifest.files.contains_key(&relative) {
+                continue;
+            }
+
+            let blob = blob_name(&hash_reader(fs::File::open(&path)?)?, &path);
+            let blob_path = self.blob_path(&blob);
+            if let Some(parent) = blob_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            // The first copy of some content becomes the blob itself
+            let linked = match fs::hard_link(&path, &blob_path) {
+                Ok(()) => true,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let size = fs::metadata(&path)?.len();
+                    let linked = replace_with_link(&blob_path, &path);
+                    if linked {
+                        outcome.files_deduplicated += 1;
+                        outcome.bytes_saved += size;
+                    }
+                    linked
+                }
+                // Hard links don't work on every filesystem; the import keeps its own copy
+                Err(e) => {
+                    tracing::warn!("Could not add {} to the blob store: {}", relative, e);
+                    false
+                }
+            };
+            if linked {
+                manifest.files.insert(relative, blob);
+            }
+        }
+
+        Self::save_manifest(import_dir, &manifest)?;
+
+        let _guard = REFCOUNT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut refcounts = self.load_refcounts()?;
+        for blob in manifest.files.values().collect::<BTreeSet<_>>() {
+            if !referenced_before.contains(blob) {
+                *refcounts.entry(blob.clone()).or_default() += 1;
+            }
+        }
+        write_json(&self.root.join(REFCOUNTS_FILE), &refcounts)?;
+
+        Ok(outcome)
+    }
+
+    /// Drop an import's references, deleting blobs nothing else uses. Returns the bytes freed.
+    pub fn release_import(&self, import_dir: &Path) -> io::Result<u64> {
+        let manifest = Self::load_manifest(import_dir)?;
+        if manifest.files.is_empty() {
+            return Ok(0);
+        }
+        self.release_blobs(manifest.files.values().collect())
+    }
+
+    /// Delete files of an import's attachments folder, dropping the import's reference to
+    /// blobs it no longer links. Returns the bytes freed.
+    pub fn remove_files(&self, import_dir: &Path, relatives: &[String]) -> io::Result<u64> {
+        let attachments_dir = import_dir.join("attachments");
+        let mut manifest = Self::load_manifest(import_dir)?;
+        let mut unlinked = BTreeSet::new();
+        let mut freed = 0;
+        for relative in relatives {
+            let path = attachments_dir.join(relative);
+            match manifest.files.remove(relative) {
+                Some(blob) => {
+                    unlinked.insert(blob);
+                }
+                None => {
+                    freed += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                }
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+        }
+        if unlinked.is_empty() {
+            return Ok(freed);
+        }
+
+        Self::save_manifest(import_dir, &manifest)?;
+        // Another file of the import may link the same blob
+        let still_linked: BTreeSet<&String> = manifest.files.values().collect();
+        freed += self.release_blobs(unlinked.iter().filter(|blob| !still_linked.contains(blob)).collect())?;
+        Ok(freed)
+    }
+
+    /// What `remove_files` would free, without changing anything
+    pub fn removable_bytes(&self, import_dir: &Path, relatives: &[String]) -> io::Result<u64> {
+        let attachments_dir = import_dir.join("attachments");
+        let mut manifest = Self::load_manifest(import_dir)?;
+        let refcounts = self.load_refcounts()?;
+        let mut unlinked = BTreeSet::new();
+        let mut bytes = 0;
+        for relative in relatives {
+            match manifest.files.remove(relative) {
+                Some(blob) => {
+                    unlinked.insert(blob);
+                }
+                None => {
+                    bytes += fs::metadata(attachments_dir.join(relative)).map(|m| m.len()).unwrap_or(0);
+                }
+            }
+        }
+
+        let still_linked: BTreeSet<&String> = manifest.files.values().collect();
+        for blob in unlinked.iter().filter(|blob| !still_linked.contains(blob)) {
+            if refcounts.get(blob).copied().unwrap_or_default() <= 1 {
+                bytes += fs::metadata(self.blob_path(blob)).map(|m| m.len()).unwrap_or(0);
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Drop one reference to each of `blobs`, deleting those left unused
+    fn release_blobs(&self, blobs: BTreeSet<&String>) -> io::Result<u64> {
+        let _guard = REFCOUNT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut refcounts = self.load_refcounts()?;
+        let mut freed = 0;
+        for blob in blobs {
+            let count = refcounts.entry(blob.clone()).or_default();
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                refcounts.remove(blob);
+                let blob_path = self.blob_path(blob);
+                freed += fs::metadata(&blob_path).map(|m| m.len()).unwrap_or(0);
+                if let Err(e) = fs::remove_file(&blob_path) {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        write_json(&self.root.join(REFCOUNTS_FILE), &refcounts)?;
+
+        Ok(freed)
+    }
+
+    /// Bytes of an import's attachments that live in the store rather than in the import
+    pub fn linked_bytes(import_dir: &Path) -> u64 {
+        let attachments_dir = import_dir.join("attachments");
+        Self::load_manifest(import_dir)
+            .map(|manifest| {
+                manifest.files
+                    .keys()
+                    .filter_map(|relative| fs::metadata(attachments_dir.join(relative)).ok())
+                    .map(|m| m.len())
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// Blob files and refcounts that disagree with the import manifests
+#[derive(Debug, Default)]
+pub struct BlobAudit {
+    pub orphan_blobs: Vec<String>,
+    pub orphan_bytes: u64,
+    pub wrong_refcounts: usize,
+}
+
+impl BlobStore {
+    /// Compare the store with the manifests of `import_dirs`, which must be every import
+    /// folder still in use
+    pub fn audit(&self, import_dirs: &[PathBuf]) -> io::Result<BlobAudit> {
+        let expected = counted_refs(import_dirs)?;
+        let stored = self.load_refcounts()?;
+        let mut audit = BlobAudit {
+            wrong_refcounts: expected
+                .keys()
+                .chain(stored.keys())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .filter(|blob| expected.get(*blob) != stored.get(*blob))
+                .count(),
+            ..Default::default()
+        };
+
+        for entry in WalkDir::new(&self.root).min_depth(2).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            // Anything else was put there by hand or left by an interrupted write
+            let blob = entry.file_name().to_string_lossy().to_string();
+            if !is_blob_name(&blob) {
+                continue;
+            }
+            if !expected.contains_key(&blob) {
+                audit.orphan_bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+                audit.orphan_blobs.push(blob);
+            }
+        }
+        Ok(audit)
+    }
+
+    /// Rewrite the refcounts from the manifests of `import_dirs` and delete blobs none of
+    /// them use. Returns the bytes freed.
+    pub fn repair(&self, import_dirs: &[PathBuf]) -> io::Result<u64> {
+        let _guard = REFCOUNT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let audit = self.audit(import_dirs)?;
+        for blob in &audit.orphan_blobs {
+            fs::remove_file(self.blob_path(blob))?;
+        }
+        write_json(&self.root.join(REFCOUNTS_FILE), &counted_refs(import_dirs)?)?;
+        Ok(audit.orphan_bytes)
+    }
+}
+
+/// Refcounts as the manifests say they should be
+fn counted_refs(import_dirs: &[PathBuf]) -> io::Result<BTreeMap<String, u64>> {
+    let mut refcounts = BTreeMap::new();
+    for import_dir in import_dirs {
+        let manifest = BlobStore::load_manifest(import_dir)?;
+        for blob in manifest.files.into_values().collect::<BTreeSet<_>>() {
+            *refcounts.entry(blob).or_default() += 1;
+        }
+    }
+    Ok(refcounts)
+}
+
+/// "<sha256>.<ext>", lowercased; odd extensions are left off
+fn blob_name(hash: &str, path: &Path) -> String {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .filter(|e| e.len() <= 8 && e.chars().all(|c| c.is_ascii_alphanumeric()));
+    match extension {
+        Some(extension) => format!("{}.{}", hash, extension.to_lowercase()),
+        None => hash.to_string(),
+    }
+}
+
+/// Whether `name` has the form `blob_name` gives: a hex SHA-256, maybe with an extension
+fn is_blob_name(name: &str) -> bool {
+    let hash = name.split_once('.').map_or(name, |(hash, _)| hash);
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Swap `path` for a hard link to `blob_path` without ever leaving it missing
+fn replace_with_link(blob_path: &Path, path: &Path) -> bool {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".blob-link");
+    let temp = PathBuf::from(temp);
+
+    let replaced = fs::hard_link(blob_path, &temp).and_then(|_| fs::rename(&temp, path));
+    if let Err(e) = &replaced {
+        tracing::warn!("Could not link {:?} to its blob: {}", path.file_name(), e);
+        fs::remove_file(&temp).ok();
+    }
+    replaced.is_ok()
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    durable::write_json_atomic(path, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn import_with(library: &Path, name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let import_dir = library.join("imports").join(name);
+        for (relative, contents) in files {
+            let path = import_dir.join("attachments").join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        import_dir
+    }
+
+    #[test]
+    fn test_shared_blob_outlives_one_import() {
+        let library = temp_dir("blobs");
+        let store = BlobStore::new(&library);
+        let first = import_with(&library, "a", &[("media/cat.png", b"cat"), ("media/dog.png", b"dog")]);
+        let second = import_with(&library, "b", &[("cat copy.png", b"cat")]);
+
+        store.adopt_import(&first).unwrap();
+        let outcome = store.adopt_import(&second).unwrap();
+        assert_eq!((outcome.files_deduplicated, outcome.bytes_saved), (1, 3));
+        // Adopting again after a resume changes nothing
+        store.adopt_import(&second).unwrap();
+
+        let cat = BlobStore::load_manifest(&second).unwrap().files["cat copy.png"].clone();
+        let dog = BlobStore::load_manifest(&first).unwrap().files["media/dog.png"].clone();
+        assert_eq!(BlobStore::load_manifest(&first).unwrap().files["media/cat.png"], cat);
+        assert_eq!(store.load_refcounts().unwrap()[&cat], 2);
+
+        assert_eq!(store.release_import(&first).unwrap(), 3);
+        let refcounts = store.load_refcounts().unwrap();
+        assert_eq!(refcounts.get(&cat), Some(&1));
+        assert_eq!(refcounts.get(&dog), None);
+        assert!(store.blob_path(&cat).exists() && !store.blob_path(&dog).exists());
+        assert_eq!(fs::read(second.join("attachments/cat copy.png")).unwrap(), b"cat");
+    }
+
+    #[test]
+    fn test_repair_skips_stray_files() {
+        let library = temp_dir("blobs");
+        let store = BlobStore::new(&library);
+        let import_dir = import_with(&library, "a", &[("cat.png", b"cat")]);
+        store.adopt_import(&import_dir).unwrap();
+        let cat = BlobStore::load_manifest(&import_dir).unwrap().files["cat.png"].clone();
+
+        // An unused blob, a stray file with a short name and a wrong refcount
+        let unused = format!("{}.png", "ab".repeat(32));
+        fs::create_dir_all(library.join("blobs/ab")).unwrap();
+        fs::write(store.blob_path(&unused), b"old").unwrap();
+        fs::write(library.join("blobs/ab/x"), b"?").unwrap();
+        write_json(&library.join("blobs").join(REFCOUNTS_FILE), &BTreeMap::from([(cat.clone(), 5u64)])).unwrap();
+
+        let audit = store.audit(std::slice::from_ref(&import_dir)).unwrap();
+        assert_eq!(audit.orphan_blobs, vec![unused.clone()]);
+        assert_eq!(audit.wrong_refcounts, 1);
+
+        assert_eq!(store.repair(std::slice::from_ref(&import_dir)).unwrap(), 3);
+        assert!(!store.blob_path(&unused).exists() && library.join("blobs/ab/x").exists());
+        assert_eq!(store.load_refcounts().unwrap()[&cat], 1);
+        let audit = store.audit(&[import_dir]).unwrap();
+        assert!(audit.orphan_blobs.is_empty() && audit.wrong_refcounts == 0);
+    }
+}
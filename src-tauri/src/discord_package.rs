@@ -0,0 +1,349 @@
+use crate::models::*;
+use crate::package_reader::PackageReader;
+use crate::sapper_core::{ hash_bytes, ConvertedExport };
+use serde::Deserialize;
+use std::collections::{ BTreeMap, HashMap };
+use std::io;
+use tracing::{ instrument, warn };
+
+#[derive(Deserialize)]
+struct PackageUser {
+    id: String,
+    username: String,
+    #[serde(default)]
+    discriminator: serde_json::Value,
+    #[serde(default)]
+    global_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PackageGuild {
+    id: String,
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct PackageChannelInfo {
+    id: String,
+    #[serde(rename = "type", default)]
+    channel_type: serde_json::Value,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    guild: Option<PackageGuild>,
+}
+
+/// A row of messages.json / messages.csv; older packages write numeric IDs
+#[derive(Deserialize)]
+struct PackageMessage {
+    #[serde(rename = "ID")]
+    id: serde_json::Value,
+    #[serde(rename = "Timestamp", default)]
+    timestamp: String,
+    #[serde(rename = "Contents", default)]
+    contents: String,
+    #[serde(rename = "Attachments", default)]
+    attachments: String,
+}
+
+/// Read a "Request My Data" package (folder or zip) into one export per channel.
+/// The package only contains our own messages, so every message has the same author.
+#[instrument(skip_all)]
+pub fn read_discord_package(reader: &PackageReader) -> io::Result<Vec<ConvertedExport>> {
+    let file_names = reader.file_names();
+
+    let find = |suffix: &str| {
+        file_names
+            .iter()
+            .find(|n| {
+                let lower = n.to_lowercase();
+                lower == suffix || lower.ends_with(&format!("/{}", suffix))
+            })
+            .cloned()
+    };
+
+    let user_path = find("account/user.json").ok_or_else(||
+        io::Error::new(io::ErrorKind::InvalidData, "Not a Discord data package: account/user.json is missing")
+    )?;
+    let user: PackageUser = serde_json
+        ::from_str(&reader.read_to_string(&user_path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let author = package_author(&user);
+
+    let channel_names: HashMap<String, Option<String>> = match find("messages/index.json") {
+        Some(index_path) =>
+            serde_json
+                ::from_str(&reader.read_to_string(&index_path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        None => HashMap::new(),
+    };
+
+    // Group the files of each channel folder: messages/c<id>/{channel.json, messages.json|csv}
+    let mut channel_dirs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for name in &file_names {
+        let parts: Vec<&str> = name.split('/').collect();
+        if parts.len() < 3 {
+            continue;
+        }
+        if !parts[parts.len() - 3].eq_ignore_ascii_case("messages") {
+            continue;
+        }
+        channel_dirs
+            .entry(parts[..parts.len() - 1].join("/"))
+            .or_default()
+            .push(name.clone());
+    }
+
+    let mut channels = Vec::new();
+    for (dir, files) in channel_dirs {
+        let file_named = |wanted: &str| {
+            files
+                .iter()
+                .find(|f| f.to_lowercase().ends_with(&format!("/{}", wanted)))
+                .cloned()
+        };
+
+        let Some(channel_path) = file_named("channel.json") else {
+            continue;
+        };
+        let info: PackageChannelInfo = match
+            serde_json::from_str(&reader.read_to_string(&channel_path)?)
+        {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Skipping {}: unreadable channel.json: {}", dir, e);
+                continue;
+            }
+        };
+
+        let (raw, rows) = if let Some(json_path) = file_named("messages.json") {
+            let raw = reader.read_bytes(&json_path)?;
+            let rows: Vec<PackageMessage> = serde_json
+                ::from_slice(&raw)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            (raw, rows)
+        } else if let Some(csv_path) = file_named("messages.csv") {
+            let raw = reader.read_bytes(&csv_path)?;
+            let rows = parse_messages_csv(&raw)?;
+            (raw, rows)
+        } else {
+            continue;
+        };
+
+        if rows.is_empty() {
+            continue;
+        }
+
+        let index_name = channel_names.get(&info.id).cloned().flatten();
+        channels.push(ConvertedExport {
+            export: build_export(&info, index_name.as_deref(), &author, rows),
+            file_hash: hash_bytes(&raw),
+            // Attachments in packages are CDN links only
+            media_files: Vec::new(),
+        });
+    }
+
+    Ok(channels)
+}
+
+fn package_author(user: &PackageUser) -> Author {
+    let discriminator = match &user.discriminator {
+        serde_json::Value::Number(n) => format!("{:04}", n.as_u64().unwrap_or(0)),
+        serde_json::Value::String(s) => s.clone(),
+        _ => "0000".to_string(),
+    };
+
+    Author {
+        id: user.id.clone(),
+        name: user.username.clone(),
+        discriminator,
+        nickname: user.global_name.clone().unwrap_or_else(|| user.username.clone()),
+        color: None,
+        is_bot: false,
+        roles: Vec::new(),
+        avatar_url: String::new(),
+    }
+}
+
+fn parse_messages_csv(raw: &[u8]) -> io::Result<Vec<PackageMessage>> {
+    let mut reader = csv::Reader::from_reader(raw);
+    reader
+        .deserialize()
+        .collect::<Result<Vec<PackageMessage>, _>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn build_export(
+    info: &PackageChannelInfo,
+    index_name: Option<&str>,
+    author: &Author,
+    rows: Vec<PackageMessage>
+) -> DiscordExport {
+    let (guild, channel_name) = match &info.guild {
+        Some(guild) => {
+            // index.json names guild channels "<channel> in <guild>"
+            let name = info.name.clone().unwrap_or_else(|| {
+                index_name
+                    .and_then(|n| n.strip_suffix(&format!(" in {}", guild.name)))
+                    .unwrap_or(index_name.unwrap_or(&info.id))
+                    .to_string()
+            });
+            (
+                Guild { id: guild.id.clone(), name: guild.name.clone(), icon_url: None },
+                name,
+            )
+        }
+        None => {
+            let name = index_name
+                .map(|n| n.strip_prefix("Direct Message with ").unwrap_or(n))
+                .filter(|n| *n != "None")
+                .map(|n| n.to_string())
+                .or_else(|| info.name.clone())
+                .unwrap_or_else(|| info.id.clone());
+            (
+                Guild { id: "0".to_string(), name: "Direct Messages".to_string(), icon_url: None },
+                name,
+            )
+        }
+    };
+
+    let mut messages: Vec<Message> = rows
+        .into_iter()
+        .map(|row| package_message(row, author))
+        .collect();
+    // Packages list newest first
+    messages.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+
+    DiscordExport {
+        guild,
+        channel: Channel {
+            id: info.id.clone(),
+            channel_type: channel_type_name(&info.channel_type),
+            category_id: None,
+            category: None,
+            name: channel_name,
+            topic: None,
+        },
+        date_range: DateRange::default(),
+        exported_at: String::new(),
+        messages,
+    }
+}
+
+fn package_message(row: PackageMessage, author: &Author) -> Message {
+    let id = match row.id {
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+    };
+
+    let attachments = row.attachments
+        .split_whitespace()
+        .map(|url| {
+            let path = url.split('?').next().unwrap_or(url);
+            let mut segments = path.rsplit('/');
+            let file_name = segments.next().unwrap_or_default().to_string();
+            let attachment_id = segments.next().unwrap_or_default().to_string();
+            Attachment {
+                id: attachment_id,
+                url: url.to_string(),
+                file_name,
+                file_size_bytes: 0,
+            }
+        })
+        .collect();
+
+    Message {
+        id,
+        message_type: "Default".to_string(),
+        timestamp: normalize_timestamp(&row.timestamp),
+        content: row.contents,
+        author: author.clone(),
+        attachments,
+        ..Default::default()
+    }
+}
+
+/// Packages have used "2020-01-01 12:00:00.123000+00:00", "2023-06-01 12:34:56" and RFC 3339
+pub fn normalize_timestamp(raw: &str) -> String {
+    let raw = raw.trim();
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(raw) {
+        return dt.to_rfc3339();
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f%:z", "%Y-%m-%d %H:%M:%S%.f%z"] {
+        if let Ok(dt) = chrono::DateTime::parse_from_str(raw, format) {
+            return dt.to_rfc3339();
+        }
+    }
+    if let Ok(naive) = chrono::NaiveDateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S%.f") {
+        return naive.and_utc().to_rfc3339();
+    }
+    raw.to_string()
+}
+
+/// Map Discord's numeric or upper-case channel types to the names DCE uses
+fn channel_type_name(value: &serde_json::Value) -> String {
+    let name = match value {
+        serde_json::Value::Number(n) =>
+            match n.as_u64() {
+                Some(0) => "GuildTextChat",
+                Some(1) => "DirectTextChat",
+                Some(2) => "GuildVoiceChat",
+                Some(3) => "DirectGroupTextChat",
+                Some(5) => "GuildNews",
+                Some(10) => "GuildNewsThread",
+                Some(11) => "GuildPublicThread",
+                Some(12) => "GuildPrivateThread",
+                Some(15) => "GuildForum",
+                _ => "GuildTextChat",
+            }
+        serde_json::Value::String(s) =>
+            match s.as_str() {
+                "DM" => "DirectTextChat",
+                "GROUP_DM" => "DirectGroupTextChat",
+                "GUILD_TEXT" => "GuildTextChat",
+                "GUILD_VOICE" => "GuildVoiceChat",
+                "GUILD_NEWS" | "GUILD_ANNOUNCEMENT" => "GuildNews",
+                "PUBLIC_THREAD" => "GuildPublicThread",
+                "PRIVATE_THREAD" => "GuildPrivateThread",
+                other => return other.to_string(),
+            }
+        _ => "GuildTextChat",
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_timestamp_formats() {
+        assert_eq!(normalize_timestamp("2020-01-01 12:00:00.123000+00:00"), "2020-01-01T12:00:00.123+00:00");
+        assert_eq!(normalize_timestamp("2023-06-01 12:34:56"), "2023-06-01T12:34:56+00:00");
+        assert_eq!(normalize_timestamp("2023-06-01T12:34:56+02:00"), "2023-06-01T12:34:56+02:00");
+    }
+
+    #[test]
+    fn test_csv_rows_and_dm_name() {
+        let csv = "ID,Timestamp,Contents,Attachments\n\
+                   2,2021-01-01 00:00:01.000000+00:00,\"second, with comma\",https://cdn.discordapp.com/attachments/1/99/cat.png\n\
+                   1,2021-01-01 00:00:00.000000+00:00,first,\n";
+        let rows = parse_messages_csv(csv.as_bytes()).unwrap();
+        let info = PackageChannelInfo {
+            id: "5".to_string(),
+            channel_type: serde_json::json!(1),
+            name: None,
+            guild: None,
+        };
+        let author = Author { id: "7".to_string(), name: "me".to_string(), ..Default::default() };
+        let export = build_export(&info, Some("Direct Message with friend#0001"), &author, rows);
+
+        assert_eq!(export.channel.name, "friend#0001");
+        assert_eq!(export.channel.channel_type, "DirectTextChat");
+        assert_eq!(export.messages[0].content, "first");
+        assert_eq!(export.messages[1].content, "second, with comma");
+        assert_eq!(export.messages[1].attachments[0].file_name, "cat.png");
+        assert_eq!(export.messages[1].attachments[0].id, "99");
+    }
+}
@@ -0,0 +1,199 @@
+use serde::{ Deserialize, Serialize };
+use std::fs;
+use std::io::{ self, Write };
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, SystemTime };
+
+/// Changes to several files of one folder that haven't all been applied yet
+pub const JOURNAL_FILE: &str = "write_journal.json";
+
+/// Temp files younger than this may belong to a write still in progress
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60);
+
+/// Replace `path` with `contents` so a crash leaves either the old or the new file,
+/// never a torn one: write "<name>.tmp", fsync it, rename it over `path`, fsync the folder.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let temp_path = temp_path(path);
+    {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(contents.as_ref())?;
+        file.sync_all()?;
+    }
+    fs::rename(&temp_path, path)?;
+    if let Some(parent) = path.parent() {
+        sync_dir(parent)?;
+    }
+    Ok(())
+}
+
+/// Serialize `value` as pretty JSON and write it with `write_atomic`
+pub fn write_json_atomic(path: &Path, value: &impl Serialize) -> io::Result<()> {
+    let contents = serde_json
+        ::to_string_pretty(value)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_atomic(path, contents)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    PathBuf::from(temp)
+}
+
+/// Make a rename durable. Windows has no way to open a directory for syncing, and NTFS
+/// journals renames itself.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JournalEntry {
+    /// Relative to the journal's folder
+    path: String,
+    /// None removes the file
+    contents: Option<String>,
+}
+
+/// Write-ahead journal for a change spanning several files in one folder, such as a chunk
+/// and the chunk index. Every new file content is first written to the journal; if the app
+/// dies while applying them, `recover` replays the journal so the files agree again.
+pub struct Transaction {
+    dir: PathBuf,
+    entries: Vec<JournalEntry>,
+}
+
+impl Transaction {
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_path_buf(), entries: Vec::new() }
+    }
+
+    pub fn write(&mut self, path: &Path, contents: String) {
+        let path = self.relative(path);
+        self.entries.push(JournalEntry { path, contents: Some(contents) });
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        let path = self.relative(path);
+        self.entries.push(JournalEntry { path, contents: None });
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.dir).unwrap_or(path).to_string_lossy().replace('\\', "/")
+    }
+
+    pub fn commit(self) -> io::Result<()> {
+        let journal_path = self.dir.join(JOURNAL_FILE);
+        write_json_atomic(&journal_path, &self.entries)?;
+        apply(&self.dir, &self.entries)?;
+        fs::remove_file(&journal_path)?;
+        sync_dir(&self.dir)
+    }
+}
+
+fn apply(dir: &Path, entries: &[JournalEntry]) -> io::Result<()> {
+    for entry in entries {
+        let path = dir.join(&entry.path);
+        match &entry.contents {
+            Some(contents) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                write_atomic(&path, contents)?;
+            }
+            None => {
+                if let Err(e) = fs::remove_file(&path) {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finish a transaction in `dir` that was cut short. Returns true if one was replayed.
+pub fn recover(dir: &Path) -> io::Result<bool> {
+    let journal_path = dir.join(JOURNAL_FILE);
+    if !journal_path.exists() {
+        return Ok(false);
+    }
+
+    // The journal itself is written atomically, so a complete one is always safe to replay
+    let contents = fs::read_to_string(&journal_path)?;
+    let entries: Vec<JournalEntry> = serde_json
+        ::from_str(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    apply(dir, &entries)?;
+    fs::remove_file(&journal_path)?;
+    sync_dir(dir)?;
+    Ok(true)
+}
+
+/// Delete temp files left in `dir` by writes that never got to their rename.
+/// Returns how many were removed.
+pub fn remove_stale_temp_files(dir: &Path) -> io::Result<usize> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(0);
+    };
+
+    let mut removed = 0;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if is_stale_temp_file(&entry.path(), &metadata) {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// A temp file old enough that no write in progress can still own it
+pub fn is_stale_temp_file(path: &Path, metadata: &fs::Metadata) -> bool {
+    if path.extension().is_none_or(|ext| ext != "tmp") || !metadata.is_file() {
+        return false;
+    }
+    let age = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .unwrap_or_default();
+    age >= STALE_TEMP_AGE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_recover_replays_journal() {
+        let dir = temp_dir("durable");
+        fs::create_dir_all(dir.join("chunks")).unwrap();
+        fs::write(dir.join("index.json"), "old").unwrap();
+        fs::write(dir.join("chunks/gone.json"), "old").unwrap();
+
+        // A journal written right before a crash, none of it applied
+        let mut transaction = Transaction::new(&dir);
+        transaction.write(&dir.join("chunks/new.json"), "chunk".to_string());
+        transaction.write(&dir.join("index.json"), "new".to_string());
+        transaction.remove(&dir.join("chunks/gone.json"));
+        write_json_atomic(&dir.join(JOURNAL_FILE), &transaction.entries).unwrap();
+
+        assert!(recover(&dir).unwrap());
+        assert_eq!(fs::read_to_string(dir.join("chunks/new.json")).unwrap(), "chunk");
+        assert_eq!(fs::read_to_string(dir.join("index.json")).unwrap(), "new");
+        assert!(!dir.join("chunks/gone.json").exists());
+        assert!(!dir.join(JOURNAL_FILE).exists());
+        assert!(!recover(&dir).unwrap());
+    }
+}
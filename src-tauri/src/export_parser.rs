@@ -0,0 +1,637 @@
+use crate::models::*;
+use serde::Deserialize;
+use regex::Regex;
+use std::collections::{ BTreeSet, HashSet };
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::sync::LazyLock;
+use tracing::{ instrument, warn };
+
+/// File name of one part of a partitioned export: "<base> [part <n>].json"
+static PART_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(.*) \[part (\d+)\]\.json$").unwrap());
+
+/// Top-level fields DCE writes for each message
+const MESSAGE_FIELDS: &[&str] = &[
+    "id",
+    "type",
+    "timestamp",
+    "timestampEdited",
+    "callEndedTimestamp",
+    "isPinned",
+    "content",
+    "author",
+    "attachments",
+    "embeds",
+    "stickers",
+    "reactions",
+    "mentions",
+    "inlineEmojis",
+    "reference",
+];
+
+/// Fields DCE writes for a message author
+const AUTHOR_FIELDS: &[&str] = &[
+    "id",
+    "name",
+    "discriminator",
+    "nickname",
+    "color",
+    "isBot",
+    "roles",
+    "avatarUrl",
+];
+
+/// Export whose messages are read one at a time, so a single bad message can't fail the
+/// whole file and no more than one message is ever held as a JSON tree
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawDiscordExport {
+    guild: Guild,
+    channel: Channel,
+    #[serde(default)]
+    date_range: DateRange,
+    #[serde(default)]
+    exported_at: String,
+    #[serde(default)]
+    messages: LenientMessages,
+}
+
+/// Messages that could be read, and the report on those that couldn't
+#[derive(Default)]
+struct LenientMessages {
+    messages: Vec<Message>,
+    report: ExportValidationReport,
+    unknown_fields: BTreeSet<String>,
+}
+
+impl<'de> Deserialize<'de> for LenientMessages {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(LenientMessagesVisitor)
+    }
+}
+
+struct LenientMessagesVisitor;
+
+impl<'de> serde::de::Visitor<'de> for LenientMessagesVisitor {
+    type Value = LenientMessages;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array of messages")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<LenientMessages, A::Error> {
+        let mut parsed = LenientMessages::default();
+        let mut seen_ids = HashSet::new();
+
+        let mut index = 0;
+        while let Some(value) = seq.next_element::<serde_json::Value>()? {
+            parsed.read_message(index, value, &mut seen_ids);
+            index += 1;
+        }
+        parsed.report.total_messages = index;
+        Ok(parsed)
+    }
+}
+
+impl LenientMessages {
+    fn read_message(&mut self, index: usize, value: serde_json::Value, seen_ids: &mut HashSet<String>) {
+        collect_unknown_fields(&value, &mut self.unknown_fields);
+        let id = value
+            .get("id")
+            .and_then(|v| v.as_str())
+            .map(|id| id.to_string());
+
+        let mut message = match Message::deserialize(value) {
+            Ok(message) => message,
+            Err(e) => {
+                self.report.skipped_messages.push(SkippedMessage {
+                    index,
+                    id,
+                    error: e.to_string(),
+                });
+                return;
+            }
+        };
+
+        if !seen_ids.insert(message.id.clone()) {
+            self.report.duplicate_ids.push(message.id);
+            return;
+        }
+
+        if chrono::DateTime::parse_from_rfc3339(&message.timestamp).is_err() {
+            self.report.unparseable_timestamps.push(message.id.clone());
+        }
+
+        if message.author.nickname.is_empty() {
+            message.author.nickname = message.author.name.clone();
+        }
+
+        collect_nested_unknown_fields(&message, &mut self.unknown_fields);
+        self.messages.push(message);
+    }
+}
+
+pub struct ParsedExport {
+    pub export: DiscordExport,
+    pub report: ExportValidationReport,
+}
+
+/// Parse a DCE export, tolerating missing optional fields and skipping messages
+/// that can't be read. Duplicate message IDs keep their first occurrence. Messages with
+/// an unreadable timestamp take the one of the message before them (or after, at the
+/// start), so they stay in place on the timeline rather than jumping to 1970.
+#[instrument(skip_all)]
+pub fn parse_export_lenient(contents: &str) -> io::Result<ParsedExport> {
+    let raw: RawDiscordExport = serde_json
+        ::from_str(contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let LenientMessages { mut messages, mut report, unknown_fields } = raw.messages;
+    report.valid_messages = messages.len();
+    report.unknown_fields = unknown_fields.into_iter().collect();
+    if !report.unparseable_timestamps.is_empty() {
+        carry_timestamps(&mut messages);
+    }
+
+    if !report.skipped_messages.is_empty() || !report.duplicate_ids.is_empty() {
+        warn!(
+            "Export parsed with {} skipped messages and {} duplicate IDs",
+            report.skipped_messages.len(),
+            report.duplicate_ids.len()
+        );
+    }
+
+    Ok(ParsedExport {
+        export: DiscordExport {
+            guild: raw.guild,
+            channel: raw.channel,
+            date_range: raw.date_range,
+            exported_at: raw.exported_at,
+            messages,
+        },
+        report,
+    })
+}
+
+/// Give messages with an unreadable timestamp the one of the nearest readable message
+/// before them, or after them for those at the start
+fn carry_timestamps(messages: &mut [Message]) {
+    let is_valid = |timestamp: &str| chrono::DateTime::parse_from_rfc3339(timestamp).is_ok();
+    let Some(first_valid) = messages
+        .iter()
+        .find(|m| is_valid(&m.timestamp))
+        .map(|m| m.timestamp.clone()) else {
+        return;
+    };
+
+    let mut previous = first_valid;
+    for message in messages.iter_mut() {
+        if is_valid(&message.timestamp) {
+            previous = message.timestamp.clone();
+        } else {
+            message.timestamp = previous.clone();
+        }
+    }
+}
+
+/// Read only the channel of an export, skipping over its messages
+pub fn read_channel(contents: &str) -> io::Result<Channel> {
+    #[derive(Deserialize)]
+    struct ChannelOnly {
+        channel: Channel,
+    }
+
+    serde_json
+        ::from_str::<ChannelOnly>(contents)
+        .map(|export| export.channel)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Whether `reader` holds a DCE export (guild, channel and messages) rather than some
+/// other JSON file that happens to sit beside it. Messages are skipped over.
+pub fn is_export(reader: impl io::Read) -> bool {
+    #[derive(Deserialize)]
+    struct ExportShape {
+        guild: Guild,
+        channel: Channel,
+        #[serde(rename = "messages")]
+        _messages: Vec<serde::de::IgnoredAny>,
+    }
+
+    serde_json
+        ::from_reader::<_, ExportShape>(io::BufReader::new(reader))
+        .is_ok_and(|export| !export.guild.id.is_empty() && !export.channel.id.is_empty())
+}
+
+/// "Server - channel [123] [part 2].json" -> ("Server - channel [123]", 2)
+pub fn partition_of(path: &Path) -> Option<(String, u32)> {
+    let file_name = path.file_name()?.to_str()?;
+    let caps = PART_REGEX.captures(file_name)?;
+    Some((caps[1].to_string(), caps[2].parse().ok()?))
+}
+
+/// All parts of the partitioned export `path` belongs to, in part order.
+/// A file that isn't partitioned is returned on its own.
+pub fn find_partition_parts(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some((base, _)) = partition_of(path) else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut parts: Vec<(u32, PathBuf)> = fs
+        ::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter_map(|p| {
+            let (other_base, part) = partition_of(&p)?;
+            (other_base == base).then_some((part, p))
+        })
+        .collect();
+    parts.sort_by_key(|(part, _)| *part);
+
+    Ok(
+        parts
+            .into_iter()
+            .map(|(_, p)| p)
+            .collect()
+    )
+}
+
+/// Every DCE export under `folder` (media folders skipped), with the parts of
+/// partitioned exports grouped together in part order
+pub fn find_exports_in_folder(folder: &Path) -> Vec<Vec<PathBuf>> {
+    let mut singles: Vec<PathBuf> = Vec::new();
+    let mut partitioned: std::collections::BTreeMap<PathBuf, Vec<(u32, PathBuf)>> = Default::default();
+
+    let entries = walkdir::WalkDir
+        ::new(folder)
+        .into_iter()
+        .filter_entry(|e| !e.file_name().to_string_lossy().ends_with("_Files"))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file());
+    for entry in entries {
+        let path = entry.into_path();
+        if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) || !fs::File::open(&path).is_ok_and(is_export) {
+            continue;
+        }
+        match partition_of(&path) {
+            Some((base, part)) => {
+                partitioned.entry(path.with_file_name(base)).or_default().push((part, path));
+            }
+            None => singles.push(path),
+        }
+    }
+
+    let mut groups: Vec<Vec<PathBuf>> = singles
+        .into_iter()
+        .map(|p| vec![p])
+        .collect();
+    for (_, mut parts) in partitioned {
+        parts.sort_by_key(|(part, _)| *part);
+        groups.push(
+            parts
+                .into_iter()
+                .map(|(_, p)| p)
+                .collect()
+        );
+    }
+    groups.sort();
+    groups
+}
+
+/// Join the parts of one channel into a single export. Parts are expected in order;
+/// messages repeated at part boundaries keep their first occurrence. Parts of
+/// different channels are refused: file names alone can match by accident.
+pub fn stitch_partitions(parts: Vec<DiscordExport>) -> io::Result<DiscordExport> {
+    let mut parts = parts.into_iter();
+    let mut stitched = parts
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No export parts to stitch"))?;
+
+    let mut seen_ids: HashSet<String> = stitched.messages
+        .iter()
+        .map(|m| m.id.clone())
+        .collect();
+    for part in parts {
+        if part.channel.id != stitched.channel.id || part.guild.id != stitched.guild.id {
+            return Err(
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Export part of channel {} in guild {} doesn't belong with channel {} in guild {}",
+                        part.channel.id,
+                        part.guild.id,
+                        stitched.channel.id,
+                        stitched.guild.id
+                    )
+                )
+            );
+        }
+        if part.date_range.before.is_some() {
+            stitched.date_range.before = part.date_range.before;
+        }
+        stitched.messages.extend(part.messages.into_iter().filter(|m| seen_ids.insert(m.id.clone())));
+    }
+
+    Ok(stitched)
+}
+
+/// Local media files an export points at (attachments, avatars, guild icon, emojis,
+/// stickers, embed media), sorted and without duplicates. Remote URLs are left out.
+pub fn referenced_assets(export: &DiscordExport) -> Vec<String> {
+    let mut assets = BTreeSet::new();
+    let mut add = |url: &str| {
+        let is_remote = url.starts_with("http://") || url.starts_with("https://") || url.starts_with("data:");
+        if !url.is_empty() && !is_remote {
+            assets.insert(url.to_string());
+        }
+    };
+
+    if let Some(icon_url) = &export.guild.icon_url {
+        add(icon_url);
+    }
+    for message in &export.messages {
+        add(&message.author.avatar_url);
+        for attachment in &message.attachments {
+            add(&attachment.url);
+        }
+        for sticker in &message.stickers {
+            add(&sticker.source_url);
+        }
+        for emoji in &message.inline_emojis {
+            add(&emoji.image_url);
+        }
+        for reaction in &message.reactions {
+            add(&reaction.emoji.image_url);
+            for user in &reaction.users {
+                add(user.avatar_url.as_deref().unwrap_or_default());
+            }
+        }
+        for mention in &message.mentions {
+            add(mention.avatar_url.as_deref().unwrap_or_default());
+        }
+        for embed in &message.embeds {
+            let media = embed.thumbnail.iter().chain(&embed.image).chain(&embed.images).chain(&embed.video);
+            for media in media {
+                add(media.url.as_deref().unwrap_or_default());
+            }
+            if let Some(icon_url) = embed.author.as_ref().and_then(|a| a.icon_url.as_deref()) {
+                add(icon_url);
+            }
+            if let Some(icon_url) = embed.footer.as_ref().and_then(|f| f.icon_url.as_deref()) {
+                add(icon_url);
+            }
+            for emoji in &embed.inline_emojis {
+                add(&emoji.image_url);
+            }
+        }
+    }
+
+    assets.into_iter().collect()
+}
+
+/// Relative paths an asset URL may be stored under: as written, then percent-decoded
+/// (DCE escapes characters such as spaces in the paths it writes)
+pub fn asset_path_candidates(url: &str) -> Vec<String> {
+    let mut candidates = vec![url.to_string()];
+    if url.contains('%') {
+        let bytes = url.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+            match hex.filter(|_| bytes[i] == b'%').and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                None => {
+                    decoded.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        if let Ok(decoded) = String::from_utf8(decoded) {
+            if decoded != url {
+                candidates.push(decoded);
+            }
+        }
+    }
+    candidates
+}
+
+/// Whether an export-supplied relative path stays inside the folder it's joined to:
+/// no root, drive prefix, "." or ".." components
+pub fn is_contained_path(relative: &str) -> bool {
+    let normalized = relative.replace('\\', "/");
+    let path = Path::new(&normalized);
+    path.components().next().is_some() &&
+        path.components().all(|c| matches!(c, std::path::Component::Normal(_))) &&
+        !normalized.split('/').any(|part| part.ends_with(':'))
+}
+
+fn collect_unknown_fields(value: &serde_json::Value, unknown: &mut BTreeSet<String>) {
+    let Some(object) = value.as_object() else {
+        return;
+    };
+
+    for key in object.keys() {
+        if !MESSAGE_FIELDS.contains(&key.as_str()) {
+            unknown.insert(format!("message.{}", key));
+        }
+    }
+
+    if let Some(author) = object.get("author").and_then(|a| a.as_object()) {
+        for key in author.keys() {
+            if !AUTHOR_FIELDS.contains(&key.as_str()) {
+                unknown.insert(format!("author.{}", key));
+            }
+        }
+    }
+}
+
+/// Typed sub-objects capture unknown fields themselves; report their keys
+fn collect_nested_unknown_fields(message: &Message, unknown: &mut BTreeSet<String>) {
+    let mut add = |prefix: &str, extra: &ExtraFields| {
+        for key in extra.keys() {
+            unknown.insert(format!("{}.{}", prefix, key));
+        }
+    };
+
+    for role in &message.author.roles {
+        add("author.roles", &role.extra);
+    }
+    for embed in &message.embeds {
+        add("embeds", &embed.extra);
+        if let Some(author) = &embed.author {
+            add("embeds.author", &author.extra);
+        }
+        if let Some(footer) = &embed.footer {
+            add("embeds.footer", &footer.extra);
+        }
+        for field in &embed.fields {
+            add("embeds.fields", &field.extra);
+        }
+    }
+    for sticker in &message.stickers {
+        add("stickers", &sticker.extra);
+    }
+    for reaction in &message.reactions {
+        add("reactions", &reaction.extra);
+        add("reactions.emoji", &reaction.emoji.extra);
+    }
+    for mention in &message.mentions {
+        add("mentions", &mention.extra);
+    }
+    for emoji in &message.inline_emojis {
+        add("inlineEmojis", &emoji.extra);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn export_with_messages(messages: &str) -> String {
+        format!(
+            r#"{{"guild":{{"id":"0","name":"Direct Messages"}},"channel":{{"id":"1","name":"friend"}},"messages":[{}]}}"#,
+            messages
+        )
+    }
+
+    #[test]
+    fn test_referenced_assets() {
+        let export = parse_export_lenient(
+            &export_with_messages(
+                r#"{"id":"1","timestamp":"2020-01-01T00:00:00+00:00",
+                    "author":{"id":"5","name":"a","avatarUrl":"media/avatar.png"},
+                    "attachments":[{"id":"9","url":"media/cat%20pic.png"},{"id":"10","url":"https://cdn.example/x.png"}],
+                    "embeds":[{"thumbnail":{"url":"media/thumb.jpg"}}],
+                    "reactions":[{"emoji":{"name":"x","imageUrl":"media/avatar.png"},"count":1}]}"#
+            )
+        ).unwrap().export;
+
+        assert_eq!(
+            referenced_assets(&export),
+            vec!["media/avatar.png", "media/cat%20pic.png", "media/thumb.jpg"]
+        );
+        assert_eq!(asset_path_candidates("media/cat%20pic.png"), vec!["media/cat%20pic.png", "media/cat pic.png"]);
+        assert_eq!(asset_path_candidates("media/100%.png"), vec!["media/100%.png"]);
+    }
+
+    #[test]
+    fn test_partition_detection_and_stitching() {
+        let (base, part) = partition_of(Path::new("/x/Server - general [123] [part 2].json")).unwrap();
+        assert_eq!(base, "Server - general [123]");
+        assert_eq!(part, 2);
+        assert!(partition_of(Path::new("Server - general [123].json")).is_none());
+
+        let part = |messages: &str| {
+            parse_export_lenient(&export_with_messages(messages)).unwrap().export
+        };
+        let message = |id: &str| {
+            format!(r#"{{"id":"{}","timestamp":"2020-01-01T00:00:00+00:00","author":{{"id":"5","name":"a"}}}}"#, id)
+        };
+        let stitched = stitch_partitions(
+            vec![
+                part(&format!("{},{}", message("1"), message("2"))),
+                part(&format!("{},{}", message("2"), message("3")))
+            ]
+        ).unwrap();
+        let ids: Vec<&str> = stitched.messages
+            .iter()
+            .map(|m| m.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+
+        let mut other_channel = part(&message("4"));
+        other_channel.channel.id = "other".to_string();
+        let err = stitch_partitions(vec![part(&message("1")), other_channel]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let mut other_guild = part(&message("4"));
+        other_guild.guild.id = "other".to_string();
+        assert!(stitch_partitions(vec![part(&message("1")), other_guild]).is_err());
+    }
+
+    #[test]
+    fn test_folder_scan_skips_other_json() {
+        let dir = temp_dir("scan");
+        fs::create_dir_all(dir.join("general.json_Files")).unwrap();
+        let export = export_with_messages("");
+        for name in ["general.json", "random [part 1].json", "random [part 2].json", "general.json_Files/x.json"] {
+            fs::write(dir.join(name), &export).unwrap();
+        }
+        fs::write(dir.join("settings.json"), r#"{"theme":"dark","messages":[]}"#).unwrap();
+        fs::write(dir.join("list.json"), "[1, 2]").unwrap();
+        fs::write(dir.join("truncated.json"), &export[..export.len() - 1]).unwrap();
+
+        let names: Vec<Vec<String>> = find_exports_in_folder(&dir)
+            .into_iter()
+            .map(|group| group.iter().map(|p| p.file_name().unwrap().to_string_lossy().to_string()).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["general.json"], vec!["random [part 1].json", "random [part 2].json"]]);
+    }
+
+    #[test]
+    fn test_missing_optional_fields() {
+        let json = export_with_messages(
+            r#"{"id":"10","timestamp":"2020-01-01T00:00:00+00:00","author":{"id":"5","name":"alice"}}"#
+        );
+        let parsed = parse_export_lenient(&json).unwrap();
+        assert_eq!(parsed.export.messages.len(), 1);
+        assert_eq!(parsed.export.messages[0].author.nickname, "alice");
+        assert_eq!(parsed.export.messages[0].message_type, "Default");
+        assert!(parsed.report.skipped_messages.is_empty());
+    }
+
+    #[test]
+    fn test_bad_message_is_skipped() {
+        let json = export_with_messages(
+            r#"{"id":"10","timestamp":"2020-01-01T00:00:00+00:00","author":{"id":"5","name":"a"}},
+               {"id":"11","timestamp":"2020-01-01T00:00:00+00:00"}"#
+        );
+        let parsed = parse_export_lenient(&json).unwrap();
+        assert_eq!(parsed.report.total_messages, 2);
+        assert_eq!(parsed.report.valid_messages, 1);
+        assert_eq!(parsed.report.skipped_messages[0].id.as_deref(), Some("11"));
+    }
+
+    #[test]
+    fn test_report_duplicates_timestamps_and_unknown_fields() {
+        let json = export_with_messages(
+            r#"{"id":"10","timestamp":"yesterday","author":{"id":"5","name":"a","pronouns":"x"},"interaction":{}},
+               {"id":"10","timestamp":"2020-01-01T00:00:00+00:00","author":{"id":"5","name":"a"}}"#
+        );
+        let parsed = parse_export_lenient(&json).unwrap();
+        assert_eq!(parsed.report.duplicate_ids, vec!["10".to_string()]);
+        assert_eq!(parsed.report.unparseable_timestamps, vec!["10".to_string()]);
+        assert_eq!(
+            parsed.report.unknown_fields,
+            vec!["author.pronouns".to_string(), "message.interaction".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_unreadable_timestamps_take_a_neighbours() {
+        let json = export_with_messages(
+            r#"{"id":"1","timestamp":"","author":{"id":"5","name":"a"}},
+               {"id":"2","timestamp":"2020-01-01T00:00:00+00:00","author":{"id":"5","name":"a"}},
+               {"id":"3","timestamp":"13/01/2020","author":{"id":"5","name":"a"}},
+               {"id":"4","timestamp":"2020-01-02T00:00:00+00:00","author":{"id":"5","name":"a"}}"#
+        );
+        let parsed = parse_export_lenient(&json).unwrap();
+        assert_eq!(parsed.report.unparseable_timestamps, vec!["1".to_string(), "3".to_string()]);
+        let timestamps: Vec<&str> = parsed.export.messages
+            .iter()
+            .map(|m| m.timestamp.as_str())
+            .collect();
+        assert_eq!(timestamps, vec![
+            "2020-01-01T00:00:00+00:00",
+            "2020-01-01T00:00:00+00:00",
+            "2020-01-01T00:00:00+00:00",
+            "2020-01-02T00:00:00+00:00"
+        ]);
+    }
+}
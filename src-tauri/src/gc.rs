@@ -0,0 +1,270 @@
+use crate::blob_store::BlobStore;
+use crate::durable;
+use crate::export_parser;
+use crate::message_storage::{ self, to_library_relative };
+use crate::models::*;
+use crate::sapper_core::SapperCore;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use tracing::{ info, warn };
+use walkdir::WalkDir;
+
+impl SapperCore {
+    /// Find files nothing uses: import folders no conversation refers to, temp files left
+    /// by interrupted writes, cache entries of conversations that are gone, and attachments
+    /// no message refers to. A dry run only reports them; otherwise they're deleted.
+    pub fn collect_garbage(&self, dry_run: bool) -> io::Result<GarbageReport> {
+        let mut report = GarbageReport { dry_run, ..Default::default() };
+
+        // An import finishing or a conversation being restored in between would otherwise
+        // have its folder taken for an orphan
+        let (metadata, orphans) = self.with_metadata_locked(|metadata| {
+            let orphans = self.import_folders(metadata)?.orphans;
+            for folder in &orphans {
+                let (files, size) = dir_stats(folder);
+                let bytes = size.saturating_sub(BlobStore::linked_bytes(folder));
+                let freed = if dry_run {
+                    0
+                } else {
+                    warn!("Deleting orphaned import folder {:?}", folder.file_name());
+                    let freed = BlobStore::new(&self.sapper_dir).release_import(folder)?;
+                    fs::remove_dir_all(folder)?;
+                    freed
+                };
+                report.items.push(
+                    self.garbage_item(GarbageKind::OrphanImportFolder, None, folder, files, bytes + freed)
+                );
+            }
+            Ok((metadata.clone(), orphans))
+        })?;
+
+        // Temp files in an orphaned folder are already counted with it
+        for entry in WalkDir::new(&self.sapper_dir)
+            .into_iter()
+            .filter_entry(|e| !orphans.iter().any(|folder| folder == e.path()))
+            .filter_map(|e| e.ok()) {
+            let Ok(file_metadata) = entry.metadata() else {
+                continue;
+            };
+            if !durable::is_stale_temp_file(entry.path(), &file_metadata) {
+                continue;
+            }
+            if !dry_run {
+                fs::remove_file(entry.path())?;
+            }
+            report.items.push(
+                self.garbage_item(GarbageKind::StaleTempFile, None, entry.path(), 1, file_metadata.len())
+            );
+        }
+
+        for path in self.unused_cache(&metadata)? {
+            let (files, bytes) = dir_stats(&path);
+            if !dry_run {
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)?;
+                } else {
+                    fs::remove_file(&path)?;
+                }
+            }
+            report.items.push(self.garbage_item(GarbageKind::UnusedCache, None, &path, files, bytes));
+        }
+
+        for entry in &metadata.imports {
+            if let Err(e) = self.collect_unreferenced_attachments(entry, dry_run, &mut report) {
+                warn!("Skipping the attachments of import {}: {}", entry.id, e);
+            }
+        }
+
+        report.total_bytes = report.items
+            .iter()
+            .map(|item| item.bytes)
+            .sum();
+        info!(
+            "Garbage collection{}: {} items, {} bytes",
+            if dry_run { " (dry run)" } else { "" },
+            report.items.len(),
+            report.total_bytes
+        );
+        Ok(report)
+    }
+
+    /// Parsed exports of conversations that are gone, and cached avatars. Nothing reads
+    /// cache/avatars any more, so all of it is left over from older versions.
+    fn unused_cache(&self, metadata: &ImportMetadata) -> io::Result<Vec<PathBuf>> {
+        let known: HashSet<&str> = metadata.imports
+            .iter()
+            .map(|e| e.id.as_str())
+            .collect();
+
+        let mut unused = Vec::new();
+        if let Ok(entries) = fs::read_dir(self.sapper_dir.join("cache/parsed")) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !known.contains(name.as_str()) {
+                    unused.push(entry.path());
+                }
+            }
+        }
+        if let Ok(entries) = fs::read_dir(self.sapper_dir.join("cache/avatars")) {
+            unused.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+        }
+        Ok(unused)
+    }
+
+    /// Files in an import's attachments folder that nothing points at, such as whole media
+    /// folders copied by older versions. Besides the export, the import's avatar, member
+    /// avatars picked by the user and the stored messages' media count as references.
+    fn collect_unreferenced_attachments(
+        &self,
+        entry: &ImportEntry,
+        dry_run: bool,
+        report: &mut GarbageReport
+    ) -> io::Result<()> {
+        let import_dir = PathBuf::from(&entry.import_path);
+        let attachments_dir = import_dir.join("attachments");
+        if !attachments_dir.is_dir() {
+            return Ok(());
+        }
+
+        // Without any of these there's no telling what is referenced, so nothing is touched
+        let export = self.parse_export(&import_dir.join("export.json"))?;
+        let members = self.load_import_data(&entry.id)?.members;
+        let messages = message_storage::open_store(import_dir.clone()).load_all_messages()?;
+
+        let mut referenced = HashSet::new();
+        let mut add = |path: &str| {
+            let path = Path::new(path);
+            if path.is_absolute() {
+                if let Ok(relative) = path.strip_prefix(&attachments_dir) {
+                    referenced.insert(relative.to_string_lossy().replace('\\', "/"));
+                }
+            } else {
+                for relative in export_parser::asset_path_candidates(&path.to_string_lossy()) {
+                    referenced.insert(relative.replace('\\', "/"));
+                }
+            }
+        };
+        export_parser::referenced_assets(&export).iter().for_each(|asset| add(asset));
+        add(&entry.avatar_path);
+        members.iter().for_each(|member| add(&member.avatar_url));
+        messages
+            .iter()
+            .flat_map(|message| &message.media_refs)
+            .for_each(|media_ref| add(media_ref));
+
+        let unreferenced: Vec<String> = WalkDir::new(&attachments_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| {
+                let relative = e.path().strip_prefix(&attachments_dir).ok()?;
+                Some(relative.to_string_lossy().replace('\\', "/"))
+            })
+            .filter(|relative| !referenced.contains(relative))
+            .collect();
+        if unreferenced.is_empty() {
+            return Ok(());
+        }
+
+        let blob_store = BlobStore::new(&self.sapper_dir);
+        let bytes = if dry_run {
+            blob_store.removable_bytes(&import_dir, &unreferenced)?
+        } else {
+            info!("Deleting {} unreferenced attachments of import {}", unreferenced.len(), entry.id);
+            blob_store.remove_files(&import_dir, &unreferenced)?
+        };
+        report.items.push(
+            self.garbage_item(
+                GarbageKind::UnreferencedAttachment,
+                Some(&entry.id),
+                &attachments_dir,
+                unreferenced.len(),
+                bytes
+            )
+        );
+        Ok(())
+    }
+
+    fn garbage_item(
+        &self,
+        kind: GarbageKind,
+        import_id: Option<&str>,
+        path: &Path,
+        files: usize,
+        bytes: u64
+    ) -> GarbageItem {
+        GarbageItem {
+            kind,
+            import_id: import_id.map(|id| id.to_string()),
+            path: to_library_relative(&self.sapper_dir, &path.to_string_lossy()).unwrap_or_default(),
+            files,
+            bytes,
+        }
+    }
+}
+
+/// Number and total size of the files under `path`
+pub(crate) fn dir_stats(path: &Path) -> (usize, u64) {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .fold((0, 0), |(files, bytes), m| (files + 1, bytes + m.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sapper_core::tests::import_test_export;
+    use crate::test_support::temp_dir;
+    use crate::sapper_core::{ SapperCore, IMPORT_JOURNAL_FILE };
+    use crate::models::GarbageKind;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_dry_run_then_reclaim() {
+        let dir = temp_dir("gc");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let kept = import_test_export(&core, &dir, "kept", &[("media/cat.png", b"cat")]);
+        let trashed = import_test_export(&core, &dir, "trashed", &[("media/dog.png", b"dog")]);
+        core.delete_import(&trashed.id).unwrap();
+
+        // A member avatar picked by the user is only referenced from import_data.json
+        let kept_dir = PathBuf::from(&kept.import_path);
+        fs::write(dir.join("face.png"), b"face").unwrap();
+        let avatar = core.copy_avatar_to_import(&kept.id, &dir.join("face.png").to_string_lossy()).unwrap();
+        core.update_member(&kept.id, "4", None, Some(avatar.clone()), None).unwrap();
+        fs::write(kept_dir.join("attachments/stray.png"), b"stray").unwrap();
+
+        let imports = dir.join("library/imports");
+        fs::create_dir_all(imports.join("orphan")).unwrap();
+        fs::write(imports.join("orphan/export.json"), b"{}").unwrap();
+        fs::create_dir_all(imports.join("in-progress")).unwrap();
+        fs::write(imports.join("in-progress").join(IMPORT_JOURNAL_FILE), b"{}").unwrap();
+
+        let report = core.collect_garbage(true).unwrap();
+        let mut found: Vec<(GarbageKind, String, usize)> = report.items
+            .iter()
+            .map(|item| (item.kind, item.path.clone(), item.files))
+            .collect();
+        found.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(found, vec![
+            (GarbageKind::UnreferencedAttachment, format!("imports/{}/attachments", kept.id), 1),
+            (GarbageKind::OrphanImportFolder, "imports/orphan".to_string(), 1)
+        ]);
+        assert!(imports.join("orphan").exists() && kept_dir.join("attachments/stray.png").exists());
+
+        let reclaimed = core.collect_garbage(false).unwrap();
+        assert_eq!(reclaimed.items.len(), 2);
+        assert!(!imports.join("orphan").exists());
+        assert!(!kept_dir.join("attachments/stray.png").exists());
+        assert!(kept_dir.join("attachments").join(&avatar).exists());
+        assert!(kept_dir.join("attachments/media/cat.png").exists());
+        assert!(imports.join("in-progress").exists());
+        assert!(dir.join("library/trash").join(&trashed.id).join("attachments/media/dog.png").exists());
+        assert!(core.collect_garbage(true).unwrap().items.is_empty());
+    }
+}
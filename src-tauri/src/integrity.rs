@@ -0,0 +1,557 @@
+use crate::blob_store::BlobStore;
+use crate::message_storage::{ self, to_library_relative, MessageStorage, MessageStore, StoredMessage };
+use crate::models::*;
+use crate::sapper_core::{ SapperCore, IMPORT_JOURNAL_FILE };
+use crate::search::MessageSearchIndex;
+use crate::sqlite_storage::{ SqliteStorage, SQLITE_FILE };
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use tracing::{ info, warn };
+
+/// Import folders whose media is still used: those metadata or an interrupted import refer
+/// to, and those in the trash; and folders under imports/ nothing refers to
+pub(crate) struct ImportFolders {
+    pub in_use: Vec<PathBuf>,
+    pub orphans: Vec<PathBuf>,
+}
+
+impl SapperCore {
+    /// Check every import's chunks, chunk index, message counts, media and search index,
+    /// and the blob store against the import manifests. Nothing is changed.
+    pub fn verify_library(&self, progress: &dyn Fn(&str, usize, usize)) -> io::Result<IntegrityReport> {
+        let mut report = IntegrityReport {
+            checked_at: chrono::Utc::now().to_rfc3339(),
+            ..Default::default()
+        };
+
+        let metadata = match self.load_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                report.issues.push(IntegrityIssue {
+                    kind: IntegrityIssueKind::CorruptJson,
+                    import_id: None,
+                    path: Some("metadata.json".to_string()),
+                    detail: format!("The import list can't be read: {}", e),
+                    repair: None,
+                });
+                return Ok(report);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+
+        for (idx, entry) in metadata.imports.iter().enumerate() {
+            progress(&entry.alias, idx + 1, metadata.imports.len());
+            let import_dir = PathBuf::from(&entry.import_path);
+            if !import_dir.is_dir() {
+                report.issues.push(IntegrityIssue {
+                    kind: IntegrityIssueKind::MissingImportFolder,
+                    import_id: Some(entry.id.clone()),
+                    path: self.library_path(&import_dir),
+                    detail: format!("The folder of \"{}\" is missing", entry.alias),
+                    repair: Some(RepairAction::DropOrphans),
+                });
+                continue;
+            }
+            report.imports_checked += 1;
+            self.check_import(entry, &import_dir, &mut report);
+        }
+
+        let folders = self.import_folders(&metadata)?;
+        for folder in &folders.orphans {
+            report.issues.push(IntegrityIssue {
+                kind: IntegrityIssueKind::OrphanImportFolder,
+                import_id: None,
+                path: self.library_path(folder),
+                detail: "Import folder that no conversation refers to".to_string(),
+                repair: Some(RepairAction::DropOrphans),
+            });
+        }
+
+        self.check_blob_store(&folders.in_use, &mut report);
+
+        info!(
+            "Verified {} imports ({} messages): {} issues",
+            report.imports_checked,
+            report.messages_checked,
+            report.issues.len()
+        );
+        Ok(report)
+    }
+
+    fn check_import(&self, entry: &ImportEntry, import_dir: &Path, report: &mut IntegrityReport) {
+        let import_data_path = import_dir.join("import_data.json");
+        if let Ok(contents) = fs::read_to_string(&import_data_path) {
+            if let Err(e) = serde_json::from_str::<ImportData>(&contents) {
+                report.issues.push(self.import_issue(
+                    entry,
+                    IntegrityIssueKind::CorruptJson,
+                    Some(&import_data_path),
+                    e.to_string(),
+                    None
+                ));
+            }
+        }
+
+        // Files in the blob store are linked from the attachments folder too, which is what
+        // backups copy
+        let blob_store = BlobStore::new(&self.sapper_dir);
+        let unlinked_media = match BlobStore::load_manifest(import_dir) {
+            Ok(manifest) => {
+                let attachments_dir = import_dir.join("attachments");
+                manifest.files
+                    .iter()
+                    .filter(|(relative, blob)| {
+                        !attachments_dir.join(relative).exists() || !blob_store.blob_path(blob).exists()
+                    })
+                    .count()
+            }
+            Err(e) => {
+                let manifest_path = import_dir.join(crate::blob_store::MANIFEST_FILE);
+                report.issues.push(self.import_issue(
+                    entry,
+                    IntegrityIssueKind::CorruptJson,
+                    Some(&manifest_path),
+                    e.to_string(),
+                    None
+                ));
+                0
+            }
+        };
+        if unlinked_media > 0 {
+            report.issues.push(self.import_issue(
+                entry,
+                IntegrityIssueKind::MissingMedia,
+                Some(&import_dir.join("attachments")),
+                format!("{} media files are missing from the attachments folder or the blob store", unlinked_media),
+                Some(RepairAction::RelinkMedia)
+            ));
+        }
+
+        if message_storage::storage_backend(import_dir) == StorageBackend::Sqlite {
+            self.check_database(entry, import_dir, report);
+            return;
+        }
+
+        let storage = MessageStorage::new(import_dir.to_path_buf());
+        let index_path = import_dir.join("chunk_index.json");
+        let index = match storage.load_chunk_index() {
+            Ok(index) => index,
+            Err(e) => {
+                let detail = if e.kind() == io::ErrorKind::NotFound {
+                    "The chunk index is missing".to_string()
+                } else {
+                    e.to_string()
+                };
+                report.issues.push(self.import_issue(
+                    entry,
+                    IntegrityIssueKind::CorruptJson,
+                    Some(&index_path),
+                    detail,
+                    Some(RepairAction::RebuildChunkIndex)
+                ));
+                return;
+            }
+        };
+
+        let mut imported_messages = 0;
+        let mut all_chunks_read = true;
+        let mut missing_media = 0;
+        let mut indexed_files = HashSet::new();
+        for chunk_meta in &index.chunks {
+            let chunk_path = storage.chunk_path(chunk_meta);
+            indexed_files.insert(chunk_path.clone());
+            if !chunk_path.exists() {
+                all_chunks_read = false;
+                report.issues.push(self.import_issue(
+                    entry,
+                    IntegrityIssueKind::MissingChunk,
+                    Some(&chunk_path),
+                    format!(
+                        "{} messages are missing; rebuilding the index drops them, re-importing restores them",
+                        chunk_meta.message_count
+                    ),
+                    Some(RepairAction::RebuildChunkIndex)
+                ));
+                continue;
+            }
+
+            let messages = match storage.load_chunk_file(&chunk_path) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    all_chunks_read = false;
+                    report.issues.push(self.import_issue(
+                        entry,
+                        IntegrityIssueKind::CorruptJson,
+                        Some(&chunk_path),
+                        format!("{}; re-import the conversation to restore it", e),
+                        None
+                    ));
+                    continue;
+                }
+            };
+            if messages.len() != chunk_meta.message_count {
+                report.issues.push(self.import_issue(
+                    entry,
+                    IntegrityIssueKind::ChunkCountMismatch,
+                    Some(&chunk_path),
+                    format!("The index lists {} messages, the chunk holds {}", chunk_meta.message_count, messages.len()),
+                    Some(RepairAction::RebuildChunkIndex)
+                ));
+            }
+
+            report.messages_checked += messages.len();
+            let (imported, missing) = tally_messages(&messages);
+            imported_messages += imported;
+            missing_media += missing;
+        }
+
+        let listed: usize = index.chunks.iter().map(|c| c.message_count).sum();
+        if listed != index.total_messages {
+            report.issues.push(self.import_issue(
+                entry,
+                IntegrityIssueKind::IndexTotalMismatch,
+                Some(&index_path),
+                format!("The index total is {}, its chunks list {}", index.total_messages, listed),
+                Some(RepairAction::RebuildChunkIndex)
+            ));
+        }
+
+        for chunk_path in storage.chunk_files().unwrap_or_default() {
+            if !indexed_files.contains(&chunk_path) {
+                report.issues.push(self.import_issue(
+                    entry,
+                    IntegrityIssueKind::OrphanChunk,
+                    Some(&chunk_path),
+                    "Chunk file the index doesn't list".to_string(),
+                    Some(RepairAction::RebuildChunkIndex)
+                ));
+            }
+        }
+
+        self.check_messages(entry, all_chunks_read.then_some(imported_messages), missing_media, report);
+        self.check_search_index(entry, import_dir, index.total_messages, report);
+    }
+
+    /// An import on the SQLite backend has no chunks to cross-check, just the database
+    fn check_database(&self, entry: &ImportEntry, import_dir: &Path, report: &mut IntegrityReport) {
+        let database_path = import_dir.join(SQLITE_FILE);
+        let storage = SqliteStorage::new(import_dir.to_path_buf());
+        let messages = storage.check().and_then(|problem| match problem {
+            Some(problem) => Err(io::Error::new(io::ErrorKind::InvalidData, problem)),
+            None => storage.load_all_messages(),
+        });
+        let messages = match messages {
+            Ok(messages) => messages,
+            Err(e) => {
+                report.issues.push(self.import_issue(
+                    entry,
+                    IntegrityIssueKind::CorruptDatabase,
+                    Some(&database_path),
+                    format!("{}; re-import the conversation to restore it", e),
+                    None
+                ));
+                return;
+            }
+        };
+
+        report.messages_checked += messages.len();
+        let (imported_messages, missing_media) = tally_messages(&messages);
+        self.check_messages(entry, Some(imported_messages), missing_media, report);
+        self.check_search_index(entry, import_dir, messages.len(), report);
+    }
+
+    /// `imported_messages` is None when some messages couldn't be read, so their count
+    /// says nothing
+    fn check_messages(
+        &self,
+        entry: &ImportEntry,
+        imported_messages: Option<usize>,
+        missing_media: usize,
+        report: &mut IntegrityReport
+    ) {
+        if let Some(imported_messages) = imported_messages.filter(|&n| n != entry.message_count) {
+            report.issues.push(self.import_issue(
+                entry,
+                IntegrityIssueKind::MessageCountMismatch,
+                None,
+                format!("{} messages were imported, {} are stored", entry.message_count, imported_messages),
+                None
+            ));
+        }
+
+        if missing_media > 0 {
+            report.issues.push(self.import_issue(
+                entry,
+                IntegrityIssueKind::MissingMedia,
+                None,
+                format!("{} media files can't be found", missing_media),
+                Some(RepairAction::RelinkMedia)
+            ));
+        }
+    }
+
+    fn check_search_index(&self, entry: &ImportEntry, import_dir: &Path, total_messages: usize, report: &mut IntegrityReport) {
+        let search_dir = import_dir.join("search_index");
+        match MessageSearchIndex::open(&search_dir) {
+            Ok(search_index) => {
+                let indexed = search_index.num_docs();
+                if indexed != total_messages as u64 {
+                    report.issues.push(self.import_issue(
+                        entry,
+                        IntegrityIssueKind::SearchIndexMismatch,
+                        Some(&search_dir),
+                        format!("{} messages are searchable out of {}", indexed, total_messages),
+                        Some(RepairAction::RebuildSearchIndex)
+                    ));
+                }
+            }
+            Err(e) => {
+                report.issues.push(self.import_issue(
+                    entry,
+                    IntegrityIssueKind::SearchIndexUnreadable,
+                    Some(&search_dir),
+                    e.to_string(),
+                    Some(RepairAction::RebuildSearchIndex)
+                ));
+            }
+        }
+    }
+
+    fn check_blob_store(&self, import_dirs: &[PathBuf], report: &mut IntegrityReport) {
+        // A manifest that can't be read is already reported, and would make its blobs
+        // look unused
+        if import_dirs.iter().any(|dir| BlobStore::load_manifest(dir).is_err()) {
+            return;
+        }
+
+        let audit = match BlobStore::new(&self.sapper_dir).audit(import_dirs) {
+            Ok(audit) => audit,
+            Err(e) => {
+                report.issues.push(IntegrityIssue {
+                    kind: IntegrityIssueKind::CorruptJson,
+                    import_id: None,
+                    path: Some("blobs".to_string()),
+                    detail: e.to_string(),
+                    repair: Some(RepairAction::DropOrphans),
+                });
+                return;
+            }
+        };
+
+        if !audit.orphan_blobs.is_empty() {
+            report.issues.push(IntegrityIssue {
+                kind: IntegrityIssueKind::OrphanBlob,
+                import_id: None,
+                path: Some("blobs".to_string()),
+                detail: format!(
+                    "{} shared media files ({} bytes) are used by no conversation",
+                    audit.orphan_blobs.len(),
+                    audit.orphan_bytes
+                ),
+                repair: Some(RepairAction::DropOrphans),
+            });
+        }
+        if audit.wrong_refcounts > 0 {
+            report.issues.push(IntegrityIssue {
+                kind: IntegrityIssueKind::RefcountMismatch,
+                import_id: None,
+                path: Some("blobs".to_string()),
+                detail: format!("{} shared media files have the wrong use count", audit.wrong_refcounts),
+                repair: Some(RepairAction::DropOrphans),
+            });
+        }
+    }
+
+    /// Run repairs, rebuilding chunk indexes first. Per-import actions without an import
+    /// ID apply to every import.
+    pub fn repair_library(&self, repairs: &[RepairRequest]) -> io::Result<()> {
+        let mut repairs = repairs.to_vec();
+        repairs.sort_by(|a, b| a.action.cmp(&b.action).then_with(|| a.import_id.cmp(&b.import_id)));
+        repairs.dedup_by(|a, b| a.action == b.action && a.import_id == b.import_id);
+
+        for repair in &repairs {
+            if repair.action == RepairAction::DropOrphans {
+                self.drop_orphans()?;
+                continue;
+            }
+
+            let import_ids = match &repair.import_id {
+                Some(id) => vec![id.clone()],
+                None => self.load_metadata()?.imports.into_iter().map(|e| e.id).collect(),
+            };
+            for import_id in import_ids {
+                info!("Repair {:?} on import {}", repair.action, import_id);
+                let import_dir = self.get_import_dir(&import_id)?;
+                match repair.action {
+                    // A database has no chunk index to rebuild
+                    RepairAction::RebuildChunkIndex => {
+                        if message_storage::storage_backend(&import_dir) == StorageBackend::Json {
+                            MessageStorage::new(import_dir).rebuild_chunk_index()?;
+                        }
+                    }
+                    RepairAction::RebuildSearchIndex => self.rebuild_search_index(&import_id)?,
+                    RepairAction::RelinkMedia => self.relink_media(&import_dir)?,
+                    RepairAction::DropOrphans => unreachable!(),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget conversations whose folder is gone, delete import folders nothing refers to,
+    /// and delete shared media no conversation uses
+    fn drop_orphans(&self) -> io::Result<()> {
+        let dropped = self.update_metadata(|metadata| {
+            let before = metadata.imports.len();
+            metadata.imports.retain(|entry| {
+                let exists = Path::new(&entry.import_path).is_dir();
+                if !exists {
+                    warn!("Dropping import {} whose folder is missing", entry.id);
+                    let cache_dir = self.sapper_dir.join("cache/parsed").join(&entry.id);
+                    fs::remove_dir_all(cache_dir).ok();
+                }
+                exists
+            });
+            Ok(before - metadata.imports.len())
+        })?;
+
+        // An import finishing or a conversation being restored in between would otherwise
+        // have its folder taken for an orphan
+        let (folders, freed) = self.with_metadata_locked(|metadata| {
+            let blob_store = BlobStore::new(&self.sapper_dir);
+            let folders = self.import_folders(metadata)?;
+            for folder in &folders.orphans {
+                warn!("Deleting orphaned import folder {:?}", folder.file_name());
+                blob_store.release_import(folder)?;
+                fs::remove_dir_all(folder)?;
+            }
+            let freed = blob_store.repair(&folders.in_use)?;
+            Ok((folders, freed))
+        })?;
+        info!(
+            "Dropped {} missing imports and {} orphaned folders, freed {} bytes of shared media",
+            dropped,
+            folders.orphans.len(),
+            freed
+        );
+        Ok(())
+    }
+
+    pub(crate) fn import_folders(&self, metadata: &ImportMetadata) -> io::Result<ImportFolders> {
+        let known: HashSet<_> = metadata.imports
+            .iter()
+            .filter_map(|e| Path::new(&e.import_path).file_name().map(|n| n.to_os_string()))
+            .collect();
+
+        let mut folders = ImportFolders { in_use: Vec::new(), orphans: Vec::new() };
+        for entry in fs::read_dir(self.sapper_dir.join("imports"))?.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if !path.is_dir() {
+                continue;
+            }
+            if known.contains(&entry.file_name()) || path.join(IMPORT_JOURNAL_FILE).exists() {
+                folders.in_use.push(path);
+            } else {
+                folders.orphans.push(path);
+            }
+        }
+        folders.in_use.extend(self.trash_folders()?);
+        Ok(folders)
+    }
+
+    fn import_issue(
+        &self,
+        entry: &ImportEntry,
+        kind: IntegrityIssueKind,
+        path: Option<&Path>,
+        detail: String,
+        repair: Option<RepairAction>
+    ) -> IntegrityIssue {
+        IntegrityIssue {
+            kind,
+            import_id: Some(entry.id.clone()),
+            path: path.and_then(|p| self.library_path(p)),
+            detail,
+            repair,
+        }
+    }
+
+    fn library_path(&self, path: &Path) -> Option<String> {
+        to_library_relative(&self.sapper_dir, &path.to_string_lossy())
+    }
+}
+
+/// How many of `messages` came from the export, and how many local media files they
+/// refer to that are gone
+fn tally_messages(messages: &[StoredMessage]) -> (usize, usize) {
+    let mut imported_messages = 0;
+    let mut missing_media = 0;
+    for message in messages.iter().filter(|m| !m.is_user_message) {
+        imported_messages += 1;
+        missing_media += message.media_refs
+            .iter()
+            .filter(|r| !r.starts_with("http://") && !r.starts_with("https://"))
+            .filter(|r| !Path::new(r).exists())
+            .count();
+    }
+    (imported_messages, missing_media)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sapper_core::tests::import_test_messages;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_chunk_problems_are_found_and_repaired() {
+        let dir = temp_dir("integrity");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let messages = |count: usize| {
+            let messages: Vec<serde_json::Value> = (0..count)
+                .map(|i| {
+                    serde_json::json!({
+                        "id": (i + 1).to_string(), "timestamp": "2020-01-01T00:00:00+00:00",
+                        "author": { "id": "4", "name": "a" }
+                    })
+                })
+                .collect();
+            serde_json::Value::from(messages)
+        };
+        let long = import_test_messages(&core, &dir, "long", messages(1200));
+        let short = import_test_messages(&core, &dir, "short", messages(3));
+
+        let long_storage = MessageStorage::new(PathBuf::from(&long.import_path));
+        let index = long_storage.load_chunk_index().unwrap();
+        assert_eq!(index.chunks.len(), 3);
+        fs::remove_file(long_storage.chunk_path(&index.chunks[1])).unwrap();
+
+        let short_storage = MessageStorage::new(PathBuf::from(&short.import_path));
+        let mut index = short_storage.load_chunk_index().unwrap();
+        index.total_messages += 7;
+        fs::write(PathBuf::from(&short.import_path).join("chunk_index.json"), serde_json::to_string(&index).unwrap()).unwrap();
+
+        let chunk_issues = |report: &IntegrityReport| -> Vec<(IntegrityIssueKind, Option<String>)> {
+            report.issues
+                .iter()
+                .filter(|issue| issue.repair == Some(RepairAction::RebuildChunkIndex))
+                .map(|issue| (issue.kind, issue.import_id.clone()))
+                .collect()
+        };
+        let report = core.verify_library(&|_, _, _| {}).unwrap();
+        assert_eq!(
+            chunk_issues(&report),
+            vec![(IntegrityIssueKind::MissingChunk, Some(long.id.clone())), (IntegrityIssueKind::IndexTotalMismatch, Some(short.id.clone()))]
+        );
+
+        core.repair_library(&[RepairRequest { action: RepairAction::RebuildChunkIndex, import_id: None }]).unwrap();
+        let report = core.verify_library(&|_, _, _| {}).unwrap();
+        assert!(chunk_issues(&report).is_empty());
+        assert_eq!(long_storage.load_chunk_index().unwrap().total_messages, 700);
+        assert_eq!(short_storage.load_chunk_index().unwrap().total_messages, 3);
+    }
+}
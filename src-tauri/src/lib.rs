@@ -1,10 +1,26 @@
+mod blob_store;
+mod discord_package;
 mod discord_presence;
+mod durable;
+mod export_parser;
+mod gc;
+mod integrity;
+mod library;
+mod library_lock;
 mod logger;
 mod message_storage;
 mod models;
+mod package_reader;
 mod sapper_core;
 mod search;
+mod slack;
+mod sqlite_storage;
+mod telegram;
+#[cfg(test)]
+mod test_support;
+mod trash;
 mod versioning;
+mod whatsapp;
 
 use discord_presence::DiscordPresence;
 use logger::LogReloadHandle;
@@ -24,15 +40,54 @@ struct AppState {
     discord: DiscordPresence,
     import_cancelled: Arc<AtomicBool>,
     log_reload_handle: LogReloadHandle,
+    // Conversation the viewer last opened; its message IDs are held on to by the frontend
+    open_conversation: Mutex<Option<String>>,
 }
 
 #[tauri::command]
 fn init_sapper(state: State<AppState>) -> Result<(), String> {
     info!("Initializing Sapper");
+    let core = open_library(&state)?;
+    *state.core.lock().unwrap() = Some(core);
+    info!("Sapper initialized successfully");
+    Ok(())
+}
+
+/// Open the active library and bring older data in it up to date
+fn open_library(state: &AppState) -> Result<SapperCore, String> {
+    // Another running instance writing the same files would lose changes
+    library_lock::hold(&library::active_root()).map_err(|e| {
+        error!("Failed to lock library: {}", e);
+        e.to_string()
+    })?;
+
     let core = SapperCore::new().map_err(|e| {
         error!("Failed to initialize SapperCore: {}", e);
         e.to_string()
     })?;
+    info!("Library: {}", logger::sanitize_string(&core.sapper_dir.to_string_lossy()));
+
+    // Finish writes a crash cut short before anything reads the library
+    if let Err(e) = core.recover_interrupted_writes() {
+        warn!("Failed to recover interrupted writes: {}", e);
+    }
+
+    // Imports made before file hashes were SHA-256 are re-hashed once
+    if let Err(e) = core.migrate_file_hashes() {
+        warn!("Failed to migrate import hashes: {}", e);
+    }
+
+    // Imports made before paths were stored relative to the library are rewritten once
+    if let Err(e) = core.migrate_to_relative_paths() {
+        warn!("Failed to migrate import paths: {}", e);
+    }
+
+    // Deleted conversations past the retention period are purged on open
+    match core.purge_expired_trash() {
+        Ok(0) => {}
+        Ok(purged) => info!("Purged {} expired conversations from the trash", purged),
+        Err(e) => warn!("Failed to purge expired trash: {}", e),
+    }
 
     // Restore debug mode from config if previously enabled
     if let Ok(config) = core.load_config() {
@@ -42,8 +97,61 @@ fn init_sapper(state: State<AppState>) -> Result<(), String> {
         }
     }
 
+    Ok(core)
+}
+
+#[tauri::command]
+fn get_libraries() -> Result<LibraryList, String> {
+    Ok(library::list())
+}
+
+#[tauri::command]
+fn add_library(path: String, name: Option<String>) -> Result<LibraryInfo, String> {
+    info!("Adding library: {}", logger::sanitize_string(&path));
+    let path = PathBuf::from(path);
+    std::fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+    let path = path.canonicalize().map_err(|e| e.to_string())?;
+    library::register(&path, name.as_deref()).map_err(|e| {
+        error!("Failed to add library: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+fn remove_library(path: String) -> Result<(), String> {
+    info!("Removing library: {}", logger::sanitize_string(&path));
+    library::unregister(&path).map_err(|e| e.to_string())
+}
+
+/// Close the current library and open another without restarting. Operations already
+/// running keep using the library they started in.
+#[tauri::command]
+fn switch_library(state: State<AppState>, path: String) -> Result<(), String> {
+    info!("Switching library to {}", logger::sanitize_string(&path));
+    let previous = library::active_root();
+    let path = library::switch_to(Path::new(&path)).map_err(|e| {
+        error!("Failed to switch library: {}", e);
+        e.to_string()
+    })?;
+
+    let core = match open_library(&state) {
+        Ok(core) => core,
+        Err(e) => {
+            // Stay on the library that still works
+            if let Err(revert) = library::switch_to(&previous).and_then(|_| library_lock::hold(&previous)) {
+                error!("Failed to return to the previous library: {}", revert);
+            }
+            return Err(e);
+        }
+    };
+
+    // Only a library that opened is worth starting in next time
+    if let Err(e) = library::remember_active(&path) {
+        warn!("Failed to remember the active library: {}", e);
+    }
+
     *state.core.lock().unwrap() = Some(core);
-    info!("Sapper initialized successfully");
+    info!("Library switched");
     Ok(())
 }
 
@@ -171,6 +279,81 @@ async fn import_conversation(
     Ok(result)
 }
 
+#[tauri::command]
+async fn import_partitioned_conversation(
+    state: State<'_, AppState>,
+    window: Window,
+    paths: Vec<String>,
+    alias: Option<String>
+) -> Result<ImportEntry, String> {
+    info!("Importing partitioned conversation from {} selected parts", paths.len());
+    if let Some(ref a) = alias {
+        debug!("Using alias: {}", logger::sanitize_string(a));
+    }
+
+    // Reset cancellation flag
+    state.import_cancelled.store(false, std::sync::atomic::Ordering::SeqCst);
+    let cancelled = state.import_cancelled.clone();
+
+    let result = tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+
+            let progress = |
+                phase: &str,
+                message: &str,
+                current: Option<usize>,
+                total: Option<usize>
+            | {
+                let _ = window.emit(
+                    "import-progress",
+                    serde_json::json!({
+                    "phase": phase,
+                    "message": message,
+                    "current": current,
+                    "total": total,
+                })
+                );
+            };
+
+            core.import_partitioned_with_callbacks(&paths, alias, progress, &cancelled).map_err(|e|
+                e.to_string()
+            )
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))??;
+
+    info!("Successfully imported partitioned conversation with ID: {}", logger::sanitize_string(&result.id));
+    Ok(result)
+}
+
+#[tauri::command]
+async fn list_archive_exports(path: String) -> Result<Vec<String>, String> {
+    info!("Listing exports in archive: {}", logger::sanitize_string(&path));
+    tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+            core.list_archive_exports(&path).map_err(|e| {
+                error!("Failed to list archive exports: {}", e);
+                e.to_string()
+            })
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+fn detect_export_parts(path: String) -> Result<Vec<String>, String> {
+    let parts = export_parser::find_partition_parts(Path::new(&path)).map_err(|e| {
+        error!("Failed to detect export parts: {}", e);
+        e.to_string()
+    })?;
+    Ok(
+        parts
+            .into_iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect()
+    )
+}
+
 #[tauri::command]
 fn load_conversation(state: State<AppState>, import_id: String) -> Result<DiscordExport, String> {
     info!("Loading conversation with ID: {}", logger::sanitize_string(&import_id));
@@ -195,6 +378,42 @@ fn delete_import(state: State<AppState>, import_id: String) -> Result<(), String
     })
 }
 
+#[tauri::command]
+fn get_trash(state: State<AppState>) -> Result<Vec<TrashedImport>, String> {
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.list_trash().map_err(|e| {
+        error!("Failed to list trash: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+fn restore_import(state: State<AppState>, import_id: String) -> Result<ImportEntry, String> {
+    info!("Restoring import {} from the trash", logger::sanitize_string(&import_id));
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.restore_import(&import_id).map_err(|e| {
+        error!("Failed to restore import {}: {}", logger::sanitize_string(&import_id), e);
+        e.to_string()
+    })
+}
+
+/// Permanently delete one trashed conversation, or empty the trash when no ID is given
+#[tauri::command]
+fn purge_trash(state: State<AppState>, import_id: Option<String>) -> Result<usize, String> {
+    info!("Purging trash");
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.purge_trash(import_id.as_deref()).map_err(|e| {
+        error!("Failed to purge trash: {}", e);
+        e.to_string()
+    })
+}
+
 #[tauri::command]
 fn update_import(state: State<AppState>, import_id: String, alias: String, description: Option<String>) -> Result<(), String> {
     info!("Updating import {} with new alias", logger::sanitize_string(&import_id));
@@ -236,7 +455,7 @@ fn update_config(state: State<AppState>, config: AppConfig) -> Result<(), String
     let core_lock = state.core.lock().unwrap();
     let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
 
-    core.save_config(&config).map_err(|e| e.to_string())
+    core.update_config(|current| *current = config).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -248,9 +467,9 @@ fn save_conversation_position(
     let core_lock = state.core.lock().unwrap();
     let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
 
-    let mut config = core.load_config().map_err(|e| e.to_string())?;
-    config.conversation_positions.insert(import_id, models::ConversationPosition { message_id });
-    core.save_config(&config).map_err(|e| e.to_string())
+    core.update_config(|config| {
+        config.conversation_positions.insert(import_id, models::ConversationPosition { message_id });
+    }).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -298,13 +517,18 @@ fn load_messages(
         })?;
 
     let import_dir = PathBuf::from(&import_entry.import_path);
-    let storage = message_storage::MessageStorage::new(import_dir);
+    let storage = message_storage::open_store(import_dir);
 
-    let result = storage.load_messages_range(start_index, count).map_err(|e| {
+    let mut result = storage.load_messages_range(start_index, count).map_err(|e| {
         error!("Failed to load messages for {}: {}", logger::sanitize_string(&import_id), e);
         e.to_string()
     })?;
 
+    core.apply_member_merges(&import_id, &mut result).map_err(|e| {
+        error!("Failed to apply member merges for {}: {}", logger::sanitize_string(&import_id), e);
+        e.to_string()
+    })?;
+
     debug!("Successfully loaded {} messages", result.len());
     trace!(
         "Message range loaded: IDs {}-{}, {} messages, content lengths: {:?}",
@@ -392,11 +616,11 @@ fn get_total_message_count(state: State<AppState>, import_id: String) -> Result<
         .ok_or("Import not found")?;
 
     let import_dir = PathBuf::from(&import_entry.import_path);
-    let storage = message_storage::MessageStorage::new(import_dir);
+    let storage = message_storage::open_store(import_dir);
 
-    let index = storage.load_chunk_index().map_err(|e| e.to_string())?;
-    trace!("Total message count for {}: {}", logger::sanitize_string(&import_id), index.total_messages);
-    Ok(index.total_messages)
+    let total = storage.total_messages().map_err(|e| e.to_string())?;
+    trace!("Total message count for {}: {}", logger::sanitize_string(&import_id), total);
+    Ok(total)
 }
 
 #[tauri::command]
@@ -414,7 +638,7 @@ fn get_pinned_message_ids(state: State<AppState>, import_id: String) -> Result<V
         .ok_or("Import not found")?;
 
     let import_dir = PathBuf::from(&import_entry.import_path);
-    let storage = message_storage::MessageStorage::new(import_dir);
+    let storage = message_storage::open_store(import_dir);
 
     storage.get_pinned_message_ids().map_err(|e| e.to_string())
 }
@@ -446,6 +670,8 @@ fn get_conversation_info(state: State<AppState>, import_id: String) -> Result<Im
         .find(|e| e.id == import_id)
         .ok_or("Import not found")?;
 
+    // The viewer asks for this first when it opens a conversation
+    *state.open_conversation.lock().unwrap() = Some(import_id);
     Ok(import_entry.clone())
 }
 
@@ -467,7 +693,7 @@ fn get_members(state: State<AppState>, import_id: String) -> Result<serde_json::
     let core_lock = state.core.lock().unwrap();
     let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
 
-    let members = core.load_members(&import_id).map_err(|e| e.to_string())?;
+    let members = core.load_merged_members(&import_id).map_err(|e| e.to_string())?;
     trace!("Loaded {} members", members.members.len());
     serde_json::to_value(&members).map_err(|e| e.to_string())
 }
@@ -487,6 +713,53 @@ fn update_member(
     core.update_member(&import_id, &member_id, nickname, avatar_url, hidden).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn merge_members(
+    state: State<AppState>,
+    import_id: Option<String>,
+    canonical_id: String,
+    member_ids: Vec<String>
+) -> Result<(), String> {
+    info!(
+        "Merging {} members into {} ({})",
+        member_ids.len(),
+        logger::sanitize_string(&canonical_id),
+        import_id.as_deref().map(logger::sanitize_string).unwrap_or_else(|| "library-wide".to_string())
+    );
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.merge_members(import_id.as_deref(), &canonical_id, &member_ids).map_err(|e| {
+        error!("Failed to merge members: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+fn unmerge_member(
+    state: State<AppState>,
+    import_id: Option<String>,
+    member_id: String
+) -> Result<(), String> {
+    info!("Unmerging member {}", logger::sanitize_string(&member_id));
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.unmerge_member(import_id.as_deref(), &member_id).map_err(|e| {
+        error!("Failed to unmerge member: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+fn get_member_stats(state: State<AppState>, import_id: String) -> Result<Vec<MemberStats>, String> {
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.get_member_stats(&import_id).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn copy_avatar_to_import(
     state: State<AppState>,
@@ -587,9 +860,7 @@ fn set_debug_mode(state: State<AppState>, enabled: bool) -> Result<(), String> {
     // Also persist to config
     let core_lock = state.core.lock().unwrap();
     if let Some(core) = core_lock.as_ref() {
-        let mut config = core.load_config().map_err(|e| e.to_string())?;
-        config.debug_mode = enabled;
-        core.save_config(&config).map_err(|e| e.to_string())?;
+        core.update_config(|config| config.debug_mode = enabled).map_err(|e| e.to_string())?;
     }
 
     if enabled {
@@ -621,26 +892,36 @@ fn get_chunk_debug_info(
         .ok_or("Import not found")?;
 
     let import_dir = PathBuf::from(&import_entry.import_path);
-    let storage = message_storage::MessageStorage::new(import_dir.clone());
-
-    let chunk_index = storage.load_chunk_index().map_err(|e| e.to_string())?;
-
-    // Build debug info
-    let chunks_info: Vec<serde_json::Value> = chunk_index.chunks.iter().map(|chunk| {
-        // Get file size
-        let file_size = std::fs::metadata(&chunk.file_path)
-            .map(|m| m.len())
-            .unwrap_or(0);
-
-        serde_json::json!({
-            "chunkId": chunk.chunk_id,
-            "startId": chunk.start_id,
-            "endId": chunk.end_id,
-            "messageCount": chunk.message_count,
-            "filePath": chunk.file_path,
-            "fileSizeBytes": file_size,
-        })
-    }).collect();
+    let backend = message_storage::storage_backend(&import_dir);
+    let (total_messages, chunks_info) = match backend {
+        StorageBackend::Json => {
+            let storage = message_storage::MessageStorage::new(import_dir.clone());
+            let chunk_index = storage.load_chunk_index().map_err(|e| e.to_string())?;
+
+            // Build debug info
+            let chunks_info: Vec<serde_json::Value> = chunk_index.chunks.iter().map(|chunk| {
+                // Get file size
+                let file_size = std::fs::metadata(storage.chunk_path(chunk))
+                    .map(|m| m.len())
+                    .unwrap_or(0);
+
+                serde_json::json!({
+                    "chunkId": chunk.chunk_id,
+                    "startId": chunk.start_id,
+                    "endId": chunk.end_id,
+                    "messageCount": chunk.message_count,
+                    "filePath": chunk.file_path,
+                    "fileSizeBytes": file_size,
+                })
+            }).collect();
+            (chunk_index.total_messages, chunks_info)
+        }
+        // One database, no chunks
+        StorageBackend::Sqlite => {
+            let total = message_storage::open_store(import_dir.clone()).total_messages().map_err(|e| e.to_string())?;
+            (total, Vec::new())
+        }
+    };
 
     // Check search index
     let search_index_dir = import_dir.join("search_index");
@@ -658,9 +939,10 @@ fn get_chunk_debug_info(
     };
 
     Ok(serde_json::json!({
-        "totalMessages": chunk_index.total_messages,
-        "totalChunks": chunk_index.chunks.len(),
+        "totalMessages": total_messages,
+        "totalChunks": chunks_info.len(),
         "chunkSize": 500,
+        "storageBackend": backend,
         "importPath": import_entry.import_path,
         "searchIndexExists": search_index_exists,
         "searchIndexSizeBytes": search_index_size,
@@ -694,13 +976,21 @@ fn get_disk_usage(state: State<AppState>) -> Result<models::AppDiskUsage, String
 
     let cache_bytes = dir_size(&cache_dir);
     let logs_bytes = dir_size(&logs_dir);
+    let blobs_bytes = dir_size(&core.sapper_dir.join("blobs"));
+    let trash_bytes: u64 = core
+        .list_trash()
+        .map(|trashed| trashed.iter().map(|t| t.size_bytes).sum())
+        .unwrap_or(0);
 
     let mut conversations: Vec<models::ConversationDiskUsage> = metadata
         .imports
         .iter()
         .map(|imp| {
             let import_dir = imports_dir.join(&imp.id);
-            let total_bytes = dir_size(&import_dir);
+            // Shared media is counted once, under blobs
+            let total_bytes = dir_size(&import_dir).saturating_sub(
+                blob_store::BlobStore::linked_bytes(&import_dir)
+            );
             models::ConversationDiskUsage {
                 import_id: imp.id.clone(),
                 alias: imp.alias.clone(),
@@ -714,92 +1004,243 @@ fn get_disk_usage(state: State<AppState>) -> Result<models::AppDiskUsage, String
     conversations.sort_by(|a, b| b.total_bytes.cmp(&a.total_bytes));
 
     let imports_bytes: u64 = conversations.iter().map(|c| c.total_bytes).sum();
-    let total_bytes = imports_bytes + cache_bytes + logs_bytes;
+    let total_bytes = imports_bytes + cache_bytes + logs_bytes + blobs_bytes + trash_bytes;
 
     Ok(models::AppDiskUsage {
         total_bytes,
         imports_bytes,
         cache_bytes,
         logs_bytes,
+        blobs_bytes,
+        trash_bytes,
         conversations,
     })
 }
 
 #[tauri::command]
-fn clear_logs(state: State<AppState>) -> Result<u64, String> {
-    let log_dir = &state.log_dir;
+async fn migrate_to_blob_store(window: Window) -> Result<models::BlobMigrationReport, String> {
+    info!("Migrating imports to the shared blob store");
 
-    if !log_dir.exists() {
-        return Ok(0);
-    }
+    tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
 
-    let mut cleared_bytes: u64 = 0;
+            let progress = |alias: &str, current: usize, total: usize| {
+                let _ = window.emit(
+                    "blob-migration-progress",
+                    serde_json::json!({
+                    "alias": alias,
+                    "current": current,
+                    "total": total,
+                })
+                );
+            };
 
-    for entry in std::fs::read_dir(log_dir).map_err(|e| e.to_string())? {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let path = entry.path();
-        if path.is_file() {
-            if let Ok(meta) = path.metadata() {
-                cleared_bytes += meta.len();
-            }
-            std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
-        }
-    }
+            core.migrate_to_blob_store(&progress).map_err(|e| {
+                error!("Blob store migration failed: {}", e);
+                e.to_string()
+            })
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
 
-    info!("Cleared {} bytes of log files, starting fresh logs", cleared_bytes);
+/// Find space taken by files nothing uses; unless `dry_run` is set, delete them
+#[tauri::command]
+async fn collect_garbage(dry_run: bool) -> Result<GarbageReport, String> {
+    info!("Collecting garbage{}", if dry_run { " (dry run)" } else { "" });
 
-    Ok(cleared_bytes)
+    tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+            core.collect_garbage(dry_run).map_err(|e| {
+                error!("Garbage collection failed: {}", e);
+                e.to_string()
+            })
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))?
 }
 
+/// Move a conversation's messages between the JSON chunk and SQLite backends
 #[tauri::command]
-async fn check_for_update(app: tauri::AppHandle) -> Result<Option<serde_json::Value>, String> {
-    info!("Checking for updates...");
+async fn convert_import_storage(import_id: String, backend: StorageBackend) -> Result<usize, String> {
+    info!("Converting storage of {} to {:?}", logger::sanitize_string(&import_id), backend);
 
-    #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    {
-        use tauri_plugin_updater::UpdaterExt;
+    tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+            core.convert_import_storage(&import_id, backend).map_err(|e| {
+                error!("Storage conversion failed: {}", e);
+                e.to_string()
+            })
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
 
-        match app.updater() {
-            Ok(updater_builder) => {
-                match updater_builder.check().await {
-                    Ok(update_option) => {
-                        if let Some(update) = update_option {
-                            info!("Update available: version {}", update.version);
-                            let update_info =
-                                serde_json::json!({
-                                "version": update.version,
-                                "current_version": update.current_version,
-                                "date": update.date,
-                                "body": update.body.unwrap_or_default(),
-                            });
-                            Ok(Some(update_info))
-                        } else {
-                            info!("No updates available");
-                            Ok(None)
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to check for updates: {}", e);
-                        Err(format!("Update check failed: {}", e))
-                    }
-                }
-            }
-            Err(e) => {
-                error!("Failed to initialize updater: {}", e);
-                Err(format!("Updater initialization failed: {}", e))
-            }
-        }
+/// Rewrite one conversation's message storage, or every conversation's, without the gaps
+/// left by deleted messages. Message IDs only change with `renumber`, and never those of
+/// the conversation open in the viewer.
+#[tauri::command]
+async fn compact_imports(
+    state: State<'_, AppState>,
+    import_id: Option<String>,
+    renumber: bool
+) -> Result<Vec<CompactionReport>, String> {
+    match &import_id {
+        Some(id) => info!("Compacting message storage of {}", logger::sanitize_string(id)),
+        None => info!("Compacting message storage of all imports"),
     }
 
-    #[cfg(any(target_os = "android", target_os = "ios"))]
-    {
-        Ok(None)
+    let open_conversation = state.open_conversation.lock().unwrap().clone();
+    if renumber && import_id.is_some() && import_id == open_conversation {
+        return Err("Can't renumber the messages of the open conversation".to_string());
     }
-}
 
-#[tauri::command]
-async fn download_and_install_update(app: tauri::AppHandle, window: Window) -> Result<(), String> {
-    info!("Downloading and installing update...");
+    tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+            let result = match &import_id {
+                Some(id) => core.compact_import(id, renumber).map(|report| vec![report]),
+                None => core.compact_all_imports(renumber, open_conversation.as_deref()),
+            };
+            result.map_err(|e| {
+                error!("Compaction failed: {}", e);
+                e.to_string()
+            })
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Position of the first message at or after `timestamp`, for jumping to a date
+#[tauri::command]
+fn get_message_index_at(state: State<AppState>, import_id: String, timestamp: u64) -> Result<usize, String> {
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    let import_dir = core.get_import_dir(&import_id).map_err(|e| e.to_string())?;
+    message_storage::open_store(import_dir)
+        .index_at_timestamp(timestamp)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn verify_library(window: Window) -> Result<IntegrityReport, String> {
+    info!("Verifying library");
+
+    tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+
+            let progress = |alias: &str, current: usize, total: usize| {
+                let _ = window.emit(
+                    "library-verify-progress",
+                    serde_json::json!({
+                    "alias": alias,
+                    "current": current,
+                    "total": total,
+                })
+                );
+            };
+
+            core.verify_library(&progress).map_err(|e| {
+                error!("Library verification failed: {}", e);
+                e.to_string()
+            })
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Run the chosen repairs, then verify again so the UI can show what is left
+#[tauri::command]
+async fn repair_library(repairs: Vec<RepairRequest>) -> Result<IntegrityReport, String> {
+    info!("Repairing library: {} actions", repairs.len());
+
+    tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+            core.repair_library(&repairs).map_err(|e| {
+                error!("Library repair failed: {}", e);
+                e.to_string()
+            })?;
+            core.verify_library(&|_, _, _| {}).map_err(|e| e.to_string())
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+fn clear_logs(state: State<AppState>) -> Result<u64, String> {
+    let log_dir = &state.log_dir;
+
+    if !log_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut cleared_bytes: u64 = 0;
+
+    for entry in std::fs::read_dir(log_dir).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path();
+        if path.is_file() {
+            if let Ok(meta) = path.metadata() {
+                cleared_bytes += meta.len();
+            }
+            std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+        }
+    }
+
+    info!("Cleared {} bytes of log files, starting fresh logs", cleared_bytes);
+
+    Ok(cleared_bytes)
+}
+
+#[tauri::command]
+async fn check_for_update(app: tauri::AppHandle) -> Result<Option<serde_json::Value>, String> {
+    info!("Checking for updates...");
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    {
+        use tauri_plugin_updater::UpdaterExt;
+
+        match app.updater() {
+            Ok(updater_builder) => {
+                match updater_builder.check().await {
+                    Ok(update_option) => {
+                        if let Some(update) = update_option {
+                            info!("Update available: version {}", update.version);
+                            let update_info =
+                                serde_json::json!({
+                                "version": update.version,
+                                "current_version": update.current_version,
+                                "date": update.date,
+                                "body": update.body.unwrap_or_default(),
+                            });
+                            Ok(Some(update_info))
+                        } else {
+                            info!("No updates available");
+                            Ok(None)
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to check for updates: {}", e);
+                        Err(format!("Update check failed: {}", e))
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to initialize updater: {}", e);
+                Err(format!("Updater initialization failed: {}", e))
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    {
+        Ok(None)
+    }
+}
+
+#[tauri::command]
+async fn download_and_install_update(app: tauri::AppHandle, window: Window) -> Result<(), String> {
+    info!("Downloading and installing update...");
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     {
@@ -998,6 +1439,303 @@ async fn copy_assets_to_json_dir(
     Ok(result)
 }
 
+#[tauri::command]
+async fn import_discord_package(
+    state: State<'_, AppState>,
+    window: Window,
+    path: String
+) -> Result<PackageImportResult, String> {
+    info!("Importing Discord data package from path: {}", logger::sanitize_string(&path));
+
+    state.import_cancelled.store(false, std::sync::atomic::Ordering::SeqCst);
+    let cancelled = state.import_cancelled.clone();
+
+    let result = tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+
+            let progress = |
+                phase: &str,
+                message: &str,
+                current: Option<usize>,
+                total: Option<usize>
+            | {
+                let _ = window.emit(
+                    "import-progress",
+                    serde_json::json!({
+                    "phase": phase,
+                    "message": message,
+                    "current": current,
+                    "total": total,
+                })
+                );
+            };
+
+            core.import_discord_package_with_callbacks(&path, progress, &cancelled).map_err(|e|
+                e.to_string()
+            )
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))??;
+
+    info!(
+        "Imported {} channels from Discord data package ({} skipped, {} failed)",
+        result.imported.len(),
+        result.skipped.len(),
+        result.failed.len()
+    );
+    Ok(result)
+}
+
+#[tauri::command]
+async fn import_telegram(
+    state: State<'_, AppState>,
+    window: Window,
+    path: String
+) -> Result<PackageImportResult, String> {
+    info!("Importing Telegram export from path: {}", logger::sanitize_string(&path));
+
+    state.import_cancelled.store(false, std::sync::atomic::Ordering::SeqCst);
+    let cancelled = state.import_cancelled.clone();
+
+    let result = tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+
+            let progress = |
+                phase: &str,
+                message: &str,
+                current: Option<usize>,
+                total: Option<usize>
+            | {
+                let _ = window.emit(
+                    "import-progress",
+                    serde_json::json!({
+                    "phase": phase,
+                    "message": message,
+                    "current": current,
+                    "total": total,
+                })
+                );
+            };
+
+            core.import_telegram_with_callbacks(&path, progress, &cancelled).map_err(|e|
+                e.to_string()
+            )
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))??;
+
+    info!(
+        "Imported {} chats from Telegram export ({} skipped, {} failed)",
+        result.imported.len(),
+        result.skipped.len(),
+        result.failed.len()
+    );
+    Ok(result)
+}
+
+#[tauri::command]
+async fn import_slack(
+    state: State<'_, AppState>,
+    window: Window,
+    path: String
+) -> Result<PackageImportResult, String> {
+    info!("Importing Slack export from path: {}", logger::sanitize_string(&path));
+
+    state.import_cancelled.store(false, std::sync::atomic::Ordering::SeqCst);
+    let cancelled = state.import_cancelled.clone();
+
+    let result = tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+
+            let progress = |
+                phase: &str,
+                message: &str,
+                current: Option<usize>,
+                total: Option<usize>
+            | {
+                let _ = window.emit(
+                    "import-progress",
+                    serde_json::json!({
+                    "phase": phase,
+                    "message": message,
+                    "current": current,
+                    "total": total,
+                })
+                );
+            };
+
+            core.import_slack_with_callbacks(&path, progress, &cancelled).map_err(|e|
+                e.to_string()
+            )
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))??;
+
+    info!(
+        "Imported {} channels from Slack export ({} skipped, {} failed)",
+        result.imported.len(),
+        result.skipped.len(),
+        result.failed.len()
+    );
+    Ok(result)
+}
+
+#[tauri::command]
+async fn import_folder(
+    state: State<'_, AppState>,
+    window: Window,
+    path: String
+) -> Result<Vec<(String, Result<ImportEntry, String>)>, String> {
+    info!("Importing all exports in folder: {}", logger::sanitize_string(&path));
+
+    state.import_cancelled.store(false, std::sync::atomic::Ordering::SeqCst);
+    let cancelled = state.import_cancelled.clone();
+
+    let result = tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+
+            let progress = |
+                phase: &str,
+                message: &str,
+                current: Option<usize>,
+                total: Option<usize>
+            | {
+                let _ = window.emit(
+                    "import-progress",
+                    serde_json::json!({
+                    "phase": phase,
+                    "message": message,
+                    "current": current,
+                    "total": total,
+                })
+                );
+            };
+
+            core.import_folder_with_callbacks(&path, progress, &cancelled).map_err(|e|
+                e.to_string()
+            )
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))??;
+
+    let success_count = result
+        .iter()
+        .filter(|(_, r)| r.is_ok())
+        .count();
+    info!("Folder import completed: {} successful out of {} total", success_count, result.len());
+    Ok(result)
+}
+
+#[tauri::command]
+fn get_asset_report(state: State<AppState>, import_id: String) -> Result<Option<AssetCopyReport>, String> {
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.get_asset_report(&import_id).map_err(|e| {
+        error!("Failed to get asset report: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+fn list_interrupted_imports(state: State<AppState>) -> Result<Vec<ImportJournal>, String> {
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.list_interrupted_imports().map_err(|e| {
+        error!("Failed to list interrupted imports: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+fn discard_interrupted_import(state: State<AppState>, import_id: String) -> Result<(), String> {
+    info!("Discarding interrupted import: {}", import_id);
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.discard_interrupted_import(&import_id).map_err(|e| {
+        error!("Failed to discard interrupted import: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+fn get_channel_threads(state: State<AppState>, import_id: String) -> Result<Vec<ThreadLink>, String> {
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.get_channel_threads(&import_id).map_err(|e| {
+        error!("Failed to get channel threads: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+fn relink_threads(state: State<AppState>) -> Result<(), String> {
+    info!("Relinking threads across the library");
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.relink_all_threads().map_err(|e| {
+        error!("Failed to relink threads: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+fn get_guild_tree(state: State<AppState>) -> Result<Vec<GuildTreeNode>, String> {
+    let core_lock = state.core.lock().unwrap();
+    let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
+
+    core.get_guild_tree().map_err(|e| {
+        error!("Failed to build guild tree: {}", e);
+        e.to_string()
+    })
+}
+
+#[tauri::command]
+async fn import_whatsapp(
+    state: State<'_, AppState>,
+    window: Window,
+    path: String
+) -> Result<PackageImportResult, String> {
+    info!("Importing WhatsApp chat from path: {}", logger::sanitize_string(&path));
+
+    state.import_cancelled.store(false, std::sync::atomic::Ordering::SeqCst);
+    let cancelled = state.import_cancelled.clone();
+
+    let result = tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+
+            let progress = |
+                phase: &str,
+                message: &str,
+                current: Option<usize>,
+                total: Option<usize>
+            | {
+                let _ = window.emit(
+                    "import-progress",
+                    serde_json::json!({
+                    "phase": phase,
+                    "message": message,
+                    "current": current,
+                    "total": total,
+                })
+                );
+            };
+
+            core.import_whatsapp_with_callbacks(&path, progress, &cancelled).map_err(|e|
+                e.to_string()
+            )
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))??;
+
+    info!("Successfully imported WhatsApp chat");
+    Ok(result)
+}
+
 #[tauri::command]
 fn reimport_conversation(state: State<AppState>, import_id: String) -> Result<(), String> {
     info!("Reimporting conversation with ID: {}", logger::sanitize_string(&import_id));
@@ -1056,26 +1794,7 @@ fn set_mutable_setting(
     let core_lock = state.core.lock().unwrap();
     let core = core_lock.as_ref().ok_or("SapperCore not initialized")?;
 
-    let mut import_data = core.load_import_data(&import_id).map_err(|e| e.to_string())?;
-    import_data.mutable_conversation = enabled;
-    import_data.mutable_member_id = if enabled { member_id } else { None };
-    import_data.last_updated = chrono::Utc::now().to_rfc3339();
-
-    let metadata = core.load_metadata().map_err(|e| e.to_string())?;
-    let import_entry = metadata.imports
-        .iter()
-        .find(|e| e.id == import_id)
-        .ok_or("Import not found")?;
-
-    let import_dir = std::path::PathBuf::from(&import_entry.import_path);
-    let import_data_path = import_dir.join("import_data.json");
-    let temp_path = import_dir.join("import_data.json.tmp");
-
-    let contents = serde_json::to_string_pretty(&import_data).map_err(|e| e.to_string())?;
-    std::fs::write(&temp_path, contents).map_err(|e| e.to_string())?;
-    std::fs::rename(temp_path, import_data_path).map_err(|e| e.to_string())?;
-
-    Ok(())
+    core.set_mutable_conversation(&import_id, enabled, member_id).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -1128,7 +1847,7 @@ fn send_user_message(
         .ok_or("Import not found")?;
 
     let import_dir = PathBuf::from(&import_entry.import_path);
-    let storage = message_storage::MessageStorage::new(import_dir.clone());
+    let storage = message_storage::open_store(import_dir.clone());
 
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -1171,9 +1890,13 @@ fn send_user_message(
     // Update search index
     let index_dir = import_dir.join("search_index");
     if index_dir.exists() {
+        let mut indexed = stored.clone();
+        if let Err(e) = core.apply_member_merges(&import_id, std::slice::from_mut(&mut indexed)) {
+            warn!("Failed to apply member merges: {}", e);
+        }
         match search::MessageSearchIndex::open(&index_dir) {
             Ok(search_index) => {
-                if let Err(e) = search_index.add_message(&stored) {
+                if let Err(e) = search_index.add_message(&indexed) {
                     warn!("Failed to update search index: {}", e);
                 }
             }
@@ -1204,16 +1927,20 @@ fn edit_user_message(
         .ok_or("Import not found")?;
 
     let import_dir = PathBuf::from(&import_entry.import_path);
-    let storage = message_storage::MessageStorage::new(import_dir.clone());
+    let storage = message_storage::open_store(import_dir.clone());
 
     let updated = storage.edit_message(message_id, new_content).map_err(|e| e.to_string())?;
 
     // Update search index
     let index_dir = import_dir.join("search_index");
     if index_dir.exists() {
+        let mut indexed = updated.clone();
+        if let Err(e) = core.apply_member_merges(&import_id, std::slice::from_mut(&mut indexed)) {
+            warn!("Failed to apply member merges: {}", e);
+        }
         match search::MessageSearchIndex::open(&index_dir) {
             Ok(search_index) => {
-                if let Err(e) = search_index.update_message(&updated) {
+                if let Err(e) = search_index.update_message(&indexed) {
                     warn!("Failed to update search index: {}", e);
                 }
             }
@@ -1243,7 +1970,7 @@ fn delete_user_message(
         .ok_or("Import not found")?;
 
     let import_dir = PathBuf::from(&import_entry.import_path);
-    let storage = message_storage::MessageStorage::new(import_dir.clone());
+    let storage = message_storage::open_store(import_dir.clone());
 
     storage.delete_message(message_id).map_err(|e| e.to_string())?;
 
@@ -1282,6 +2009,17 @@ async fn get_import_preview(json_path: String) -> Result<ImportPreview, String>
         .map_err(|e| format!("Task join error: {}", e))?
 }
 
+#[tauri::command]
+async fn validate_export(json_path: String) -> Result<ExportValidationReport, String> {
+    info!("Validating export: {}", logger::sanitize_string(&json_path));
+    tokio::task
+        ::spawn_blocking(move || {
+            let core = SapperCore::new().map_err(|e| e.to_string())?;
+            core.validate_export(&json_path).map_err(|e| e.to_string())
+        }).await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
 #[tauri::command]
 fn save_emoji_cache(state: State<AppState>, data: String) -> Result<(), String> {
     let core_lock = state.core.lock().unwrap();
@@ -1299,13 +2037,15 @@ fn load_emoji_cache(state: State<AppState>) -> Result<Option<String>, String> {
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // Initialize logging first
-    let (log_dir, log_reload_handle) = logger::init_logging().unwrap_or_else(|e| {
+    let (library_root, library_source) = library::resolve_startup();
+    let (log_dir, log_reload_handle) = logger::init_logging(&library_root).unwrap_or_else(|e| {
         eprintln!("Failed to initialize logging: {}", e);
         (std::path::PathBuf::from("."), LogReloadHandle::noop())
     });
 
     info!("Sapper application starting");
     info!("Log directory: {}", log_dir.display());
+    info!("Library chosen by: {:?}", library_source);
 
     // Initialize Discord Rich Presence
     let discord = DiscordPresence::new();
@@ -1324,15 +2064,37 @@ pub fn run() {
             discord,
             import_cancelled: Arc::new(AtomicBool::new(false)),
             log_reload_handle,
+            open_conversation: Mutex::new(None),
         })
         .invoke_handler(
             tauri::generate_handler![
                 init_sapper,
+                get_libraries,
+                add_library,
+                remove_library,
+                switch_library,
                 get_imports,
                 get_imports_with_compatibility,
                 import_conversation,
+                import_partitioned_conversation,
+                detect_export_parts,
+                list_archive_exports,
+                import_discord_package,
+                import_telegram,
+                import_whatsapp,
+                import_slack,
+                import_folder,
+                get_guild_tree,
+                get_channel_threads,
+                get_asset_report,
+                list_interrupted_imports,
+                discard_interrupted_import,
+                relink_threads,
                 load_conversation,
                 delete_import,
+                get_trash,
+                restore_import,
+                purge_trash,
                 update_import,
                 get_config,
                 update_config,
@@ -1348,6 +2110,9 @@ pub fn run() {
                 get_app_version,
                 get_members,
                 update_member,
+                merge_members,
+                unmerge_member,
+                get_member_stats,
                 copy_avatar_to_import,
                 check_asset_path,
                 log_frontend_error,
@@ -1368,7 +2133,15 @@ pub fn run() {
                 batch_reimport_conversations,
                 cancel_import,
                 get_import_preview,
+                validate_export,
                 get_disk_usage,
+                migrate_to_blob_store,
+                verify_library,
+                repair_library,
+                collect_garbage,
+                convert_import_storage,
+                compact_imports,
+                get_message_index_at,
                 clear_logs,
                 reorder_imports,
                 get_mutable_setting,
@@ -0,0 +1,263 @@
+use crate::durable;
+use crate::models::{ LibraryInfo, LibraryList, LibrarySource };
+use serde::{ Deserialize, Serialize };
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Mutex, OnceLock, RwLock };
+
+/// Command line flag selecting the library folder: `--library <path>` or `--library=<path>`
+const LIBRARY_FLAG: &str = "--library";
+/// Environment variable selecting the library folder
+const LIBRARY_ENV: &str = "SAPPER_LIBRARY";
+/// A file with this name next to the executable turns on portable mode
+const PORTABLE_MARKER: &str = "portable";
+/// Name of the library folder in the home directory, or next to the executable when portable
+const LIBRARY_DIR: &str = ".sapper";
+/// Known libraries and the last active one, kept outside any library
+const BOOTSTRAP_FILE: &str = "libraries.json";
+
+/// Library chosen at runtime; wins over everything resolved at startup
+static ACTIVE_LIBRARY: RwLock<Option<PathBuf>> = RwLock::new(None);
+/// What `resolve_startup` found; arguments and environment don't change while running
+static STARTUP_LIBRARY: OnceLock<(PathBuf, LibrarySource)> = OnceLock::new();
+/// Bootstrap config reads and writes from several commands are serialized here
+static BOOTSTRAP_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BootstrapConfig {
+    #[serde(default)]
+    active: Option<String>,
+    #[serde(default)]
+    libraries: Vec<LibraryInfo>,
+}
+
+/// Folder every SapperCore works in. Resolution order: a library switched to at runtime,
+/// the `--library` flag, `SAPPER_LIBRARY`, portable mode, the bootstrap config's active
+/// library, then `~/.sapper`.
+pub fn active_root() -> PathBuf {
+    if let Some(path) = ACTIVE_LIBRARY.read().unwrap().clone() {
+        return path;
+    }
+    resolve_startup().0
+}
+
+/// Library the app starts in, and what chose it
+pub fn resolve_startup() -> (PathBuf, LibrarySource) {
+    STARTUP_LIBRARY.get_or_init(find_startup_library).clone()
+}
+
+fn find_startup_library() -> (PathBuf, LibrarySource) {
+    if let Some(path) = library_from_args(std::env::args_os()) {
+        return (path, LibrarySource::Cli);
+    }
+    if let Some(path) = std::env::var_os(LIBRARY_ENV).filter(|v| !v.is_empty()) {
+        return (PathBuf::from(path), LibrarySource::Env);
+    }
+    if let Some(path) = portable_root() {
+        return (path, LibrarySource::Portable);
+    }
+    if let Some(path) = load_bootstrap().active.filter(|p| !p.is_empty()) {
+        return (PathBuf::from(path), LibrarySource::Config);
+    }
+    (default_root(), LibrarySource::Default)
+}
+
+/// Make `path` the library for every SapperCore opened from now on. Returns the path as
+/// it's stored; pass it to `remember_active` once the library opened.
+pub fn switch_to(path: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(path)?;
+    let path = path.canonicalize()?;
+    *ACTIVE_LIBRARY.write().unwrap() = Some(path.clone());
+    Ok(path)
+}
+
+/// Start in `path` next time. A flag, environment variable or portable mode still wins
+/// at startup.
+pub fn remember_active(path: &Path) -> io::Result<()> {
+    register(path, None)?;
+
+    let _guard = BOOTSTRAP_LOCK.lock().unwrap();
+    let mut bootstrap = load_bootstrap();
+    bootstrap.active = Some(path.to_string_lossy().to_string());
+    save_bootstrap(&bootstrap)
+}
+
+/// Add a library to the known list. An existing entry keeps its name unless one is given.
+pub fn register(path: &Path, name: Option<&str>) -> io::Result<LibraryInfo> {
+    let _guard = BOOTSTRAP_LOCK.lock().unwrap();
+    let mut bootstrap = load_bootstrap();
+    let key = path.to_string_lossy().to_string();
+
+    let info = match bootstrap.libraries.iter_mut().find(|l| l.path == key) {
+        Some(existing) => {
+            if let Some(name) = name.filter(|n| !n.trim().is_empty()) {
+                existing.name = name.trim().to_string();
+            }
+            existing.clone()
+        }
+        None => {
+            let info = LibraryInfo {
+                name: name
+                    .filter(|n| !n.trim().is_empty())
+                    .map(|n| n.trim().to_string())
+                    .unwrap_or_else(|| default_name(path)),
+                path: key,
+            };
+            bootstrap.libraries.push(info.clone());
+            info
+        }
+    };
+    save_bootstrap(&bootstrap)?;
+    Ok(info)
+}
+
+/// Forget a library. Its folder is left on disk.
+pub fn unregister(path: &str) -> io::Result<()> {
+    if Path::new(path) == active_root() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot remove the active library"));
+    }
+    let _guard = BOOTSTRAP_LOCK.lock().unwrap();
+    let mut bootstrap = load_bootstrap();
+    bootstrap.libraries.retain(|l| l.path != path);
+    if bootstrap.active.as_deref() == Some(path) {
+        bootstrap.active = None;
+    }
+    save_bootstrap(&bootstrap)
+}
+
+/// Known libraries plus the active one, which is listed even if it was never registered
+pub fn list() -> LibraryList {
+    let (startup_root, startup_source) = resolve_startup();
+    let runtime = ACTIVE_LIBRARY.read().unwrap().clone();
+    let (active, source) = match runtime {
+        Some(path) if path != startup_root => (path, LibrarySource::Config),
+        _ => (startup_root, startup_source),
+    };
+
+    let _guard = BOOTSTRAP_LOCK.lock().unwrap();
+    let mut libraries = load_bootstrap().libraries;
+    let active_key = active.to_string_lossy().to_string();
+    if !libraries.iter().any(|l| l.path == active_key) {
+        libraries.insert(0, LibraryInfo { name: default_name(&active), path: active_key.clone() });
+    }
+
+    LibraryList {
+        active: active_key,
+        source,
+        portable: portable_root().is_some(),
+        libraries,
+    }
+}
+
+fn default_root() -> PathBuf {
+    match directories::UserDirs::new() {
+        Some(home) => home.home_dir().join(LIBRARY_DIR),
+        None => PathBuf::from(LIBRARY_DIR),
+    }
+}
+
+/// Portable mode keeps the library beside the executable, e.g. on a USB stick. It's on when
+/// a `portable` marker file or a `.sapper` folder sits next to the executable.
+fn portable_root() -> Option<PathBuf> {
+    let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+    let root = exe_dir.join(LIBRARY_DIR);
+    (exe_dir.join(PORTABLE_MARKER).is_file() || root.is_dir()).then_some(root)
+}
+
+/// Arguments aren't necessarily UTF-8; a path given as the next argument is taken as is
+fn library_from_args(args: impl IntoIterator<Item = OsString>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == LIBRARY_FLAG {
+            return args.next().filter(|v| !v.is_empty()).map(PathBuf::from);
+        }
+        let value = arg.to_str().and_then(|arg| arg.strip_prefix(LIBRARY_FLAG)?.strip_prefix('='));
+        if let Some(value) = value {
+            return Some(PathBuf::from(value)).filter(|p| !p.as_os_str().is_empty());
+        }
+    }
+    None
+}
+
+fn default_name(path: &Path) -> String {
+    if path == default_root() {
+        return "Default".to_string();
+    }
+    match path.file_name().map(|n| n.to_string_lossy().to_string()) {
+        Some(name) if name != LIBRARY_DIR => name,
+        _ => path
+            .parent()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string_lossy().to_string()),
+    }
+}
+
+/// The bootstrap config lives in the OS config folder, except in portable mode where it
+/// sits next to the executable so nothing is written to the host machine
+fn bootstrap_path() -> Option<PathBuf> {
+    if let Some(root) = portable_root() {
+        return root.parent().map(|dir| dir.join(BOOTSTRAP_FILE));
+    }
+    directories::ProjectDirs
+        ::from("", "", "Sapper")
+        .map(|dirs| dirs.config_dir().join(BOOTSTRAP_FILE))
+}
+
+fn load_bootstrap() -> BootstrapConfig {
+    bootstrap_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_bootstrap(bootstrap: &BootstrapConfig) -> io::Result<()> {
+    let path = bootstrap_path().ok_or_else(||
+        io::Error::new(io::ErrorKind::NotFound, "No config directory for the library list")
+    )?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    durable::write_json_atomic(&path, bootstrap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<OsString> {
+        values.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_library_from_args() {
+        assert_eq!(library_from_args(args(&["sapper"])), None);
+        assert_eq!(
+            library_from_args(args(&["sapper", "--library", "/mnt/archive"])),
+            Some(PathBuf::from("/mnt/archive"))
+        );
+        assert_eq!(
+            library_from_args(args(&["sapper", "--library=/mnt/work"])),
+            Some(PathBuf::from("/mnt/work"))
+        );
+        assert_eq!(library_from_args(args(&["sapper", "--library"])), None);
+        assert_eq!(library_from_args(args(&["sapper", "--library-other=x"])), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_library_from_non_utf8_args() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let path = OsString::from_vec(b"/mnt/caf\xe9".to_vec());
+        let other = OsString::from_vec(b"--caf\xe9".to_vec());
+        assert_eq!(
+            library_from_args(vec![OsString::from("sapper"), other.clone(), OsString::from("--library"), path.clone()]),
+            Some(PathBuf::from(path))
+        );
+        assert_eq!(library_from_args(vec![OsString::from("sapper"), other]), None);
+    }
+}
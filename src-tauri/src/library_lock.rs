@@ -0,0 +1,111 @@
+use serde::{ Deserialize, Serialize };
+use std::fs::{ self, File, OpenOptions, TryLockError };
+use std::io::{ self, Read, Seek, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use tracing::info;
+
+/// Locked for as long as a process has the library open
+pub const LOCK_FILE: &str = "library.lock";
+
+/// Lock on the library this process has open. Replaced when switching libraries; the
+/// operating system releases it when the process exits, even after a crash.
+static HELD_LOCK: Mutex<Option<LibraryLock>> = Mutex::new(None);
+
+/// Written into the lock file so a second instance can say who has the library
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LockHolder {
+    pid: u32,
+    acquired_at: String,
+}
+
+/// Exclusive lock on a library folder, so only one Sapper process writes to it
+pub struct LibraryLock {
+    root: PathBuf,
+    _file: File,
+}
+
+impl LibraryLock {
+    /// Lock `library_root`, failing with `WouldBlock` if another process has it open
+    pub fn acquire(library_root: &Path) -> io::Result<Self> {
+        fs::create_dir_all(library_root)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(library_root.join(LOCK_FILE))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                // Windows doesn't let other processes read a locked file
+                let holder = read_holder(&mut file)
+                    .map(|holder| format!(" (process {} since {})", holder.pid, holder.acquired_at))
+                    .unwrap_or_default();
+                return Err(
+                    io::Error::new(
+                        io::ErrorKind::WouldBlock,
+                        format!("This library is already open in another Sapper window{}", holder)
+                    )
+                );
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e);
+            }
+        }
+
+        let holder = LockHolder {
+            pid: std::process::id(),
+            acquired_at: chrono::Utc::now().to_rfc3339(),
+        };
+        let contents = serde_json
+            ::to_string(&holder)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+
+        Ok(Self { root: library_root.to_path_buf(), _file: file })
+    }
+}
+
+fn read_holder(file: &mut File) -> Option<LockHolder> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Make this process the only writer of `library_root`, releasing the library it held
+/// before. Holding it already is fine.
+pub fn hold(library_root: &Path) -> io::Result<()> {
+    let mut held = HELD_LOCK.lock().unwrap();
+    if held.as_ref().is_some_and(|lock| lock.root == library_root) {
+        return Ok(());
+    }
+
+    let lock = LibraryLock::acquire(library_root)?;
+    info!("Locked library for this process");
+    *held = Some(lock);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_second_lock_is_refused() {
+        let dir = temp_dir("lock");
+
+        let first = LibraryLock::acquire(&dir).unwrap();
+        let err = LibraryLock::acquire(&dir).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        drop(first);
+        let _again = LibraryLock::acquire(&dir).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use regex::Regex;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 #[cfg(debug_assertions)]
 use tracing_chrome::ChromeLayerBuilder;
@@ -30,15 +30,11 @@ impl LogReloadHandle {
 }
 
 /// Initialize the logging system
-/// Logs will be written to a rotating daily log file in the .sapper directory
+/// Logs will be written to a rotating daily log file in the library the app started in;
+/// they stay there when another library is switched to at runtime.
 /// Returns the log directory path and a handle for dynamically changing log levels.
-pub fn init_logging() -> Result<(PathBuf, LogReloadHandle), Box<dyn std::error::Error>> {
-    let home_dir = directories::BaseDirs::new()
-        .ok_or("Failed to get home directory")?
-        .home_dir()
-        .to_path_buf();
-
-    let log_dir = home_dir.join(".sapper").join("logs");
+pub fn init_logging(library_root: &Path) -> Result<(PathBuf, LogReloadHandle), Box<dyn std::error::Error>> {
+    let log_dir = library_root.join("logs");
     std::fs::create_dir_all(&log_dir)?;
 
     // Create a rolling file appender that creates a new log file daily
@@ -57,7 +53,7 @@ pub fn init_logging() -> Result<(PathBuf, LogReloadHandle), Box<dyn std::error::
         .with_target(true)
         .with_line_number(true);
 
-    // Chrome trace layer (dev builds only) — writes trace JSON to ~/.sapper/logs/trace-{timestamp}.json
+    // Chrome trace layer (dev builds only) — writes trace JSON to <library>/logs/trace-{timestamp}.json
     // View in chrome://tracing or https://ui.perfetto.dev
     #[cfg(debug_assertions)]
     let chrome_guard;
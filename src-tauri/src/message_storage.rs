@@ -1,11 +1,20 @@
-use crate::models::{Attachment, Author, MessageReference};
+use crate::durable::{self, Transaction};
+use crate::models::{Attachment, Author, Embed, Emoji, MessageReference, Reaction, Sticker, StorageBackend, User};
+use crate::sqlite_storage::{SqliteStorage, SQLITE_FILE};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use tracing::instrument;
 
 const CHUNK_SIZE: usize = 500;
+/// A chunk is also cut once its messages reach this many bytes of JSON, so messages with
+/// large embeds don't make single chunks slow to load
+const CHUNK_BYTE_BUDGET: usize = 2 * 1024 * 1024;
+/// How many chunks deletes may leave under a quarter full before they are compacted
+const SMALL_CHUNK_LIMIT: usize = 4;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -21,11 +30,11 @@ pub struct StoredMessage {
     pub content: String,
     pub author: Author,
     pub attachments: Vec<Attachment>,
-    pub embeds: Vec<serde_json::Value>,
-    pub stickers: Vec<serde_json::Value>,
-    pub reactions: Vec<serde_json::Value>,
-    pub mentions: Vec<serde_json::Value>,
-    pub inline_emojis: Vec<serde_json::Value>,
+    pub embeds: Vec<Embed>,
+    pub stickers: Vec<Sticker>,
+    pub reactions: Vec<Reaction>,
+    pub mentions: Vec<User>,
+    pub inline_emojis: Vec<Emoji>,
     pub media_refs: Vec<String>, // paths to media files (computed field)
     pub reference: Option<MessageReference>,
     pub referenced_message: Option<Box<StoredMessage>>, // The actual message being replied to
@@ -33,6 +42,31 @@ pub struct StoredMessage {
     pub is_user_message: bool, // true if sent by the user via mutable conversation
 }
 
+impl StoredMessage {
+    /// Message content plus the text carried by embeds and stickers, for full-text search
+    pub fn searchable_text(&self) -> String {
+        let mut parts: Vec<&str> = vec![&self.content];
+
+        for embed in &self.embeds {
+            parts.extend(embed.title.as_deref());
+            parts.extend(embed.description.as_deref());
+            parts.extend(embed.author.as_ref().and_then(|a| a.name.as_deref()));
+            parts.extend(embed.footer.as_ref().map(|f| f.text.as_str()));
+            for field in &embed.fields {
+                parts.push(&field.name);
+                parts.push(&field.value);
+            }
+        }
+
+        for sticker in &self.stickers {
+            parts.push(&sticker.name);
+        }
+
+        parts.retain(|p| !p.is_empty());
+        parts.join("\n")
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChunkMeta {
     pub chunk_id: usize,
@@ -48,6 +82,10 @@ pub struct ChunkIndex {
     pub total_messages: usize,
     #[serde(default)]
     pub pinned_message_ids: Vec<u64>,
+    /// Lowest ID the next appended message may get, so a deleted message's ID isn't
+    /// handed out again
+    #[serde(default)]
+    pub next_message_id: u64,
 }
 
 impl ChunkIndex {
@@ -56,6 +94,7 @@ impl ChunkIndex {
             chunks: Vec::new(),
             total_messages: 0,
             pinned_message_ids: Vec::new(),
+            next_message_id: 0,
         }
     }
 
@@ -67,10 +106,11 @@ impl ChunkIndex {
     }
 
     pub fn save(&self, path: &Path) -> io::Result<()> {
-        let json = serde_json::to_string(self)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        fs::write(path, json)?;
-        Ok(())
+        durable::write_atomic(path, self.to_json()?)
+    }
+
+    fn to_json(&self) -> io::Result<String> {
+        serde_json::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     pub fn load(path: &Path) -> io::Result<Self> {
@@ -79,6 +119,166 @@ impl ChunkIndex {
     }
 }
 
+/// Path below the library root (".sapper") for an absolute path inside it, with forward
+/// slashes. Paths from a library that has since moved are matched on their
+/// "imports/" or "blobs/" component.
+pub fn to_library_relative(library_root: &Path, path: &str) -> Option<String> {
+    if path.is_empty() || !Path::new(path).is_absolute() {
+        return None;
+    }
+    if let Ok(relative) = Path::new(path).strip_prefix(library_root) {
+        return Some(relative.to_string_lossy().replace('\\', "/"));
+    }
+
+    let normalized = path.replace('\\', "/");
+    ["/imports/", "/blobs/"]
+        .iter()
+        .filter_map(|dir| normalized.rfind(dir))
+        .max()
+        .map(|start| normalized[start + 1..].to_string())
+}
+
+/// Absolute path for a stored path: relative ones are below the library root, absolute
+/// ones written before paths were stored relative are kept, or relocated if they moved
+pub fn resolve_library_path(library_root: &Path, stored: &str) -> String {
+    if stored.is_empty() || stored.starts_with("http://") || stored.starts_with("https://") {
+        return stored.to_string();
+    }
+    if Path::new(stored).is_absolute() {
+        if Path::new(stored).exists() {
+            return stored.to_string();
+        }
+        return match to_library_relative(library_root, stored) {
+            Some(relative) => library_root.join(relative).to_string_lossy().to_string(),
+            None => stored.to_string(),
+        };
+    }
+    library_root.join(stored).to_string_lossy().to_string()
+}
+
+/// Imports live at <library root>/imports/<id>
+pub fn library_root_of(import_dir: &Path) -> PathBuf {
+    import_dir
+        .parent()
+        .and_then(|imports| imports.parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+/// Media paths are stored relative to the library root
+pub fn relativize_media_refs(library_root: &Path, message: &mut StoredMessage) {
+    for media_ref in message.media_refs.iter_mut() {
+        if let Some(relative) = to_library_relative(library_root, media_ref) {
+            *media_ref = relative;
+        }
+    }
+}
+
+pub fn resolve_media_refs(library_root: &Path, message: &mut StoredMessage) {
+    for media_ref in message.media_refs.iter_mut() {
+        *media_ref = resolve_library_path(library_root, media_ref);
+    }
+}
+
+/// Where an import's messages live. Message IDs are assigned in conversation order and
+/// `start_idx` counts positions in that order.
+pub trait MessageStore {
+    fn backend(&self) -> StorageBackend;
+
+    /// Replace all stored messages
+    fn write_messages(&self, messages: Vec<StoredMessage>) -> io::Result<()>;
+
+    fn total_messages(&self) -> io::Result<usize>;
+
+    fn get_pinned_message_ids(&self) -> io::Result<Vec<u64>>;
+
+    fn load_messages_range(&self, start_idx: usize, count: usize) -> io::Result<Vec<StoredMessage>>;
+
+    /// The ID the next appended message will get. Never one a deleted message had.
+    fn next_message_id(&self) -> io::Result<u64>;
+
+    fn load_all_messages(&self) -> io::Result<Vec<StoredMessage>> {
+        self.load_messages_range(0, self.total_messages()?)
+    }
+
+    /// Append a new user message to the end of the conversation.
+    /// Returns the assigned message ID.
+    fn append_message(&self, message: StoredMessage) -> io::Result<StoredMessage>;
+
+    /// Edit a message's content by its ID.
+    fn edit_message(&self, message_id: u64, new_content: String) -> io::Result<StoredMessage>;
+
+    /// Delete a user message by its ID. Later messages keep their IDs.
+    fn delete_message(&self, message_id: u64) -> io::Result<()>;
+
+    /// Extract all user-sent messages (before reimport).
+    fn extract_user_messages(&self) -> io::Result<Vec<StoredMessage>>;
+
+    /// Apply `update` to every stored message
+    fn update_messages(&self, update: &mut dyn FnMut(&mut StoredMessage)) -> io::Result<()>;
+
+    /// Rewrite the stored messages without the gaps left by deletes. With `renumber`,
+    /// message IDs become sequential again; only safe while no one holds on to old IDs.
+    fn compact(&self, renumber: bool) -> io::Result<Compaction>;
+
+    /// Position of the first message sent at or after `timestamp` (ms since the epoch);
+    /// the message count if there is none
+    fn index_at_timestamp(&self, timestamp: u64) -> io::Result<usize>;
+}
+
+/// Which backend holds an import's messages: a messages.db file means SQLite
+pub fn storage_backend(import_dir: &Path) -> StorageBackend {
+    if import_dir.join(SQLITE_FILE).exists() {
+        StorageBackend::Sqlite
+    } else {
+        StorageBackend::Json
+    }
+}
+
+/// Open an import's messages with whichever backend holds them
+pub fn open_store(import_dir: PathBuf) -> Box<dyn MessageStore> {
+    match storage_backend(&import_dir) {
+        StorageBackend::Json => Box::new(MessageStorage::new(import_dir)),
+        StorageBackend::Sqlite => Box::new(SqliteStorage::new(import_dir)),
+    }
+}
+
+/// Store for an import folder that holds no messages yet
+pub fn create_store(import_dir: PathBuf, backend: StorageBackend) -> Box<dyn MessageStore> {
+    match backend {
+        StorageBackend::Json => Box::new(MessageStorage::new(import_dir)),
+        StorageBackend::Sqlite => Box::new(SqliteStorage::new(import_dir)),
+    }
+}
+
+/// Move an import's messages to another backend. The new copy is complete before the old
+/// one is removed, so an interruption leaves the import readable. Returns the number of
+/// messages moved.
+pub fn convert_store(import_dir: &Path, to: StorageBackend) -> io::Result<usize> {
+    let from = storage_backend(import_dir);
+    if from == to {
+        return Ok(0);
+    }
+    let store = open_store(import_dir.to_path_buf());
+    let messages = store.load_all_messages()?;
+    let next_message_id = store.next_message_id()?;
+    let count = messages.len();
+
+    match to {
+        StorageBackend::Sqlite => {
+            // The database file appearing is what switches the import over
+            SqliteStorage::build(import_dir, messages, next_message_id)?;
+            fs::remove_file(import_dir.join("chunk_index.json"))?;
+            fs::remove_dir_all(import_dir.join("chunks"))?;
+        }
+        StorageBackend::Json => {
+            MessageStorage::new(import_dir.to_path_buf()).create_chunks(messages, next_message_id)?;
+            SqliteStorage::remove(import_dir)?;
+        }
+    }
+    Ok(count)
+}
+
 pub struct MessageStorage {
     import_dir: PathBuf,
 }
@@ -88,29 +288,100 @@ impl MessageStorage {
         Self { import_dir }
     }
 
+    fn library_root(&self) -> PathBuf {
+        library_root_of(&self.import_dir)
+    }
+
+    /// Chunk file paths are stored relative to the import folder
+    pub fn chunk_path(&self, chunk_meta: &ChunkMeta) -> PathBuf {
+        let path = Path::new(&chunk_meta.file_path);
+        if !path.is_absolute() {
+            return self.import_dir.join(path);
+        }
+        if path.exists() {
+            return path.to_path_buf();
+        }
+        // Written by a library that has since moved
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_os_string())
+            .unwrap_or_default();
+        self.import_dir.join("chunks").join(file_name)
+    }
+
+    fn write_chunk(&self, path: &Path, messages: &[StoredMessage]) -> io::Result<()> {
+        durable::write_atomic(path, self.chunk_json(messages)?)
+    }
+
+    fn chunk_json(&self, messages: &[StoredMessage]) -> io::Result<String> {
+        let library_root = self.library_root();
+        let messages: Vec<StoredMessage> = messages
+            .iter()
+            .cloned()
+            .map(|mut msg| {
+                relativize_media_refs(&library_root, &mut msg);
+                msg
+            })
+            .collect();
+
+        serde_json::to_string(&messages).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.import_dir.join("chunk_index.json")
+    }
+
+    /// Finish an edit of chunks and the chunk index that was cut short by a crash.
+    /// Returns true if one was replayed.
+    pub fn recover(&self) -> io::Result<bool> {
+        durable::recover(&self.import_dir)
+    }
+
+    /// Rewrite chunks written with absolute paths. Returns false if there were none.
+    pub fn relativize_paths(&self) -> io::Result<bool> {
+        let mut index = match self.load_chunk_index() {
+            Ok(index) => index,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if !index.chunks.iter().any(|c| Path::new(&c.file_path).is_absolute()) {
+            return Ok(false);
+        }
+
+        for chunk_meta in index.chunks.iter_mut() {
+            let messages = self.load_chunk(chunk_meta)?;
+            let path = self.chunk_path(chunk_meta);
+            self.write_chunk(&path, &messages)?;
+            chunk_meta.file_path = format!("chunks/{}", path.file_name().unwrap_or_default().to_string_lossy());
+        }
+
+        index.save(&self.index_path())?;
+        Ok(true)
+    }
+
     #[instrument(skip_all, fields(message_count = messages.len()))]
-    pub fn create_chunks(&self, messages: Vec<StoredMessage>) -> io::Result<ChunkIndex> {
+    pub fn create_chunks(&self, messages: Vec<StoredMessage>, next_message_id: u64) -> io::Result<ChunkIndex> {
         let chunks_dir = self.import_dir.join("chunks");
         fs::create_dir_all(&chunks_dir)?;
 
         let mut chunk_index = ChunkIndex::new();
         chunk_index.total_messages = messages.len();
+        chunk_index.next_message_id = messages.last().map_or(0, |m| m.id + 1).max(next_message_id);
         chunk_index.pinned_message_ids = messages
             .iter()
             .filter(|m| m.is_pinned)
             .map(|m| m.id)
             .collect();
 
-        for (chunk_id, chunk) in messages.chunks(CHUNK_SIZE).enumerate() {
+        for (chunk_id, range) in chunk_ranges(&messages)?.into_iter().enumerate() {
+            let chunk = &messages[range];
             let start_id = chunk.first().map(|m| m.id).unwrap_or(0);
             let end_id = chunk.last().map(|m| m.id).unwrap_or(0);
             let file_name = format!("chunk_{}.json", chunk_id);
             let file_path = chunks_dir.join(&file_name);
 
             // Serialize and write chunk as JSON
-            let json = serde_json::to_string(&chunk)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-            fs::write(&file_path, json)?;
+            self.write_chunk(&file_path, chunk)?;
 
             // Add to index
             chunk_index.chunks.push(ChunkMeta {
@@ -118,42 +389,87 @@ impl MessageStorage {
                 start_id,
                 end_id,
                 message_count: chunk.len(),
-                file_path: file_path.to_string_lossy().to_string(),
+                file_path: format!("chunks/{}", file_name),
             });
         }
 
         // Save index
-        let index_path = self.import_dir.join("chunk_index.json");
-        chunk_index.save(&index_path)?;
+        chunk_index.save(&self.index_path())?;
 
         Ok(chunk_index)
     }
 
-    /// Extract all user-sent messages from existing chunks (before reimport).
-    pub fn extract_user_messages(&self) -> io::Result<Vec<StoredMessage>> {
-        let index = match self.load_chunk_index() {
-            Ok(idx) => idx,
-            Err(_) => return Ok(Vec::new()),
-        };
+    #[instrument(skip_all, fields(chunk_id = chunk_meta.chunk_id, message_count = chunk_meta.message_count))]
+    pub fn load_chunk(&self, chunk_meta: &ChunkMeta) -> io::Result<Vec<StoredMessage>> {
+        self.load_chunk_file(&self.chunk_path(chunk_meta))
+    }
 
-        let mut user_messages = Vec::new();
-        for chunk_meta in &index.chunks {
-            if let Ok(messages) = self.load_chunk(chunk_meta) {
-                for msg in messages {
-                    if msg.is_user_message {
-                        user_messages.push(msg);
-                    }
-                }
-            }
+    pub fn load_chunk_file(&self, path: &Path) -> io::Result<Vec<StoredMessage>> {
+        let contents = fs::read_to_string(path)?;
+        let mut messages: Vec<StoredMessage> = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let library_root = self.library_root();
+        for msg in messages.iter_mut() {
+            resolve_media_refs(&library_root, msg);
         }
+        Ok(messages)
+    }
 
-        Ok(user_messages)
+    /// Chunk files in the chunks folder, whether or not the index lists them
+    pub fn chunk_files(&self) -> io::Result<Vec<PathBuf>> {
+        let chunks_dir = self.import_dir.join("chunks");
+        if !chunks_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut files: Vec<PathBuf> = fs::read_dir(chunks_dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                name.starts_with("chunk_") && name.ends_with(".json")
+            })
+            .collect();
+        files.sort();
+        Ok(files)
     }
 
-    #[instrument(skip_all, fields(chunk_id = chunk_meta.chunk_id, message_count = chunk_meta.message_count))]
-    pub fn load_chunk(&self, chunk_meta: &ChunkMeta) -> io::Result<Vec<StoredMessage>> {
-        let contents = fs::read_to_string(&chunk_meta.file_path)?;
-        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    /// Recreate chunk_index.json from the chunk files on disk. Unreadable chunks are
+    /// left out.
+    pub fn rebuild_chunk_index(&self) -> io::Result<ChunkIndex> {
+        let mut index = ChunkIndex::new();
+        for path in self.chunk_files()? {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let messages = match self.load_chunk_file(&path) {
+                Ok(messages) if !messages.is_empty() => messages,
+                Ok(_) => continue,
+                Err(e) => {
+                    tracing::warn!("Leaving unreadable chunk {} out of the index: {}", file_name, e);
+                    continue;
+                }
+            };
+            let chunk_id = file_name
+                .trim_start_matches("chunk_")
+                .trim_end_matches(".json")
+                .parse()
+                .unwrap_or(index.chunks.len());
+
+            index.total_messages += messages.len();
+            index.pinned_message_ids.extend(messages.iter().filter(|m| m.is_pinned).map(|m| m.id));
+            index.chunks.push(ChunkMeta {
+                chunk_id,
+                start_id: messages.first().map(|m| m.id).unwrap_or(0),
+                end_id: messages.last().map(|m| m.id).unwrap_or(0),
+                message_count: messages.len(),
+                file_path: format!("chunks/{}", file_name),
+            });
+        }
+
+        index.chunks.sort_by_key(|c| c.start_id);
+        index.pinned_message_ids.sort_unstable();
+        index.next_message_id = index.chunks.iter().map(|c| c.end_id + 1).max().unwrap_or(0);
+        index.save(&self.index_path())?;
+        Ok(index)
     }
 
     #[instrument(skip_all)]
@@ -162,14 +478,187 @@ impl MessageStorage {
         ChunkIndex::load(&index_path)
     }
 
+    /// Whether deletes have left enough near-empty chunks to be worth compacting. The
+    /// last chunk is still being filled by appends, so it doesn't count.
+    pub fn needs_compaction(&self, index: &ChunkIndex) -> bool {
+        let Some((_, filled)) = index.chunks.split_last() else {
+            return false;
+        };
+        let small = filled
+            .iter()
+            .filter(|chunk_meta| {
+                chunk_meta.message_count < CHUNK_SIZE / 4 &&
+                    fs::metadata(self.chunk_path(chunk_meta)).is_ok_and(|m| (m.len() as usize) < CHUNK_BYTE_BUDGET / 4)
+            })
+            .count();
+        small > SMALL_CHUNK_LIMIT
+    }
+
+    #[instrument(skip_all, fields(renumber))]
+    fn compact_chunks(&self, renumber: bool) -> io::Result<Compaction> {
+        self.recover()?;
+        let index = self.load_chunk_index()?;
+
+        let mut messages = Vec::with_capacity(index.total_messages);
+        for chunk_meta in &index.chunks {
+            messages.extend(self.load_chunk(chunk_meta)?);
+        }
+        let renumbered = if renumber { renumber_messages(&mut messages) } else { HashMap::new() };
+
+        let chunks_dir = self.import_dir.join("chunks");
+        fs::create_dir_all(&chunks_dir)?;
+
+        let mut compacted = ChunkIndex::new();
+        compacted.total_messages = messages.len();
+        compacted.next_message_id = if renumber { messages.len() as u64 } else { index.next_message_id };
+        compacted.pinned_message_ids = messages
+            .iter()
+            .filter(|m| m.is_pinned)
+            .map(|m| m.id)
+            .collect();
+
+        // Every old chunk is read above, so chunk files can be reused under new contents
+        let mut transaction = Transaction::new(&self.import_dir);
+        let mut written = HashSet::new();
+        for (chunk_id, range) in chunk_ranges(&messages)?.into_iter().enumerate() {
+            let chunk = &messages[range];
+            let file_name = format!("chunk_{}.json", chunk_id);
+            let file_path = chunks_dir.join(&file_name);
+            transaction.write(&file_path, self.chunk_json(chunk)?);
+            written.insert(file_path);
+
+            compacted.chunks.push(ChunkMeta {
+                chunk_id,
+                start_id: chunk.first().map(|m| m.id).unwrap_or(0),
+                end_id: chunk.last().map(|m| m.id).unwrap_or(0),
+                message_count: chunk.len(),
+                file_path: format!("chunks/{}", file_name),
+            });
+        }
+        for chunk_meta in &index.chunks {
+            let path = self.chunk_path(chunk_meta);
+            if !written.contains(&path) {
+                transaction.remove(&path);
+            }
+        }
+        transaction.write(&self.index_path(), compacted.to_json()?);
+        transaction.commit()?;
+
+        Ok(Compaction {
+            chunks_before: index.chunks.len(),
+            chunks_after: compacted.chunks.len(),
+            renumbered,
+        })
+    }
+
+    /// Compact after a delete once enough small chunks have built up. Message IDs are
+    /// kept, as the conversation is open and its messages are referred to by ID.
+    fn compact_if_needed(&self) {
+        let needed = self.load_chunk_index().is_ok_and(|index| self.needs_compaction(&index));
+        if !needed {
+            return;
+        }
+        match self.compact_chunks(false) {
+            Ok(compaction) => tracing::info!(
+                "Compacted {} chunks into {}",
+                compaction.chunks_before,
+                compaction.chunks_after
+            ),
+            Err(e) => tracing::warn!("Failed to compact chunks: {}", e),
+        }
+    }
+}
+
+/// After deletes the count is below the highest ID, so it can't be the next one
+fn next_id(index: &ChunkIndex) -> u64 {
+    index.chunks
+        .iter()
+        .map(|c| c.end_id + 1)
+        .max()
+        .unwrap_or(0)
+        .max(index.next_message_id)
+}
+
+/// Split `messages` into chunks of at most CHUNK_SIZE messages and CHUNK_BYTE_BUDGET
+/// bytes. A single message over the budget gets a chunk of its own.
+fn chunk_ranges(messages: &[StoredMessage]) -> io::Result<Vec<Range<usize>>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut bytes = 0;
+    for (i, msg) in messages.iter().enumerate() {
+        let size = serde_json::to_vec(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?.len();
+        if i > start && (i - start == CHUNK_SIZE || bytes + size > CHUNK_BYTE_BUDGET) {
+            ranges.push(start..i);
+            start = i;
+            bytes = 0;
+        }
+        bytes += size;
+    }
+    if start < messages.len() {
+        ranges.push(start..messages.len());
+    }
+    Ok(ranges)
+}
+
+/// Give messages the IDs 0, 1, 2... in order, as the conversation viewer treats an ID as
+/// the message's position. Replies are pointed at the new IDs. Returns the old ID of
+/// each renumbered message with its new one.
+pub fn renumber_messages(messages: &mut [StoredMessage]) -> HashMap<u64, u64> {
+    let mut renumbered = HashMap::new();
+    for (position, msg) in messages.iter_mut().enumerate() {
+        let new_id = position as u64;
+        if msg.id != new_id {
+            renumbered.insert(msg.id, new_id);
+            msg.id = new_id;
+        }
+    }
+    if !renumbered.is_empty() {
+        for reply in messages.iter_mut().filter_map(|m| m.referenced_message.as_mut()) {
+            if let Some(&new_id) = renumbered.get(&reply.id) {
+                reply.id = new_id;
+            }
+        }
+    }
+    renumbered
+}
+
+/// What compacting an import's message storage changed
+#[derive(Debug, Default)]
+pub struct Compaction {
+    pub chunks_before: usize,
+    pub chunks_after: usize,
+    /// Old ID to new, for the messages that were renumbered
+    pub renumbered: HashMap<u64, u64>,
+}
+
+
+/// JSON chunk files of up to 500 messages, with chunk_index.json listing them
+impl MessageStore for MessageStorage {
+    fn backend(&self) -> StorageBackend {
+        StorageBackend::Json
+    }
+
+    fn write_messages(&self, messages: Vec<StoredMessage>) -> io::Result<()> {
+        self.create_chunks(messages, 0).map(|_| ())
+    }
+
+    fn total_messages(&self) -> io::Result<usize> {
+        Ok(self.load_chunk_index()?.total_messages)
+    }
+
+    fn next_message_id(&self) -> io::Result<u64> {
+        self.recover()?;
+        Ok(next_id(&self.load_chunk_index()?))
+    }
+
     #[instrument(skip_all)]
-    pub fn get_pinned_message_ids(&self) -> io::Result<Vec<u64>> {
+    fn get_pinned_message_ids(&self) -> io::Result<Vec<u64>> {
         let index = self.load_chunk_index()?;
         Ok(index.pinned_message_ids)
     }
 
     #[instrument(skip_all, fields(start_idx, count))]
-    pub fn load_messages_range(
+    fn load_messages_range(
         &self,
         start_idx: usize,
         count: usize,
@@ -209,31 +698,33 @@ impl MessageStorage {
         Ok(messages)
     }
 
-    /// Append a new user message to the end of the conversation.
-    /// Returns the assigned message ID.
-    pub fn append_message(&self, message: StoredMessage) -> io::Result<StoredMessage> {
+    fn append_message(&self, message: StoredMessage) -> io::Result<StoredMessage> {
+        self.recover()?;
         let mut index = self.load_chunk_index()?;
-        let msg_id = index.total_messages as u64;
+        let msg_id = next_id(&index);
+        index.next_message_id = msg_id + 1;
 
         let mut message = message;
         message.id = msg_id;
 
         // Check if the last chunk has room
         if let Some(last_chunk) = index.chunks.last_mut() {
-            if last_chunk.message_count < CHUNK_SIZE {
+            let within_budget = fs
+                ::metadata(self.chunk_path(last_chunk))
+                .is_ok_and(|m| (m.len() as usize) < CHUNK_BYTE_BUDGET);
+            if last_chunk.message_count < CHUNK_SIZE && within_budget {
                 // Append to existing chunk
                 let mut chunk_messages = self.load_chunk(last_chunk)?;
                 chunk_messages.push(message.clone());
                 last_chunk.end_id = msg_id;
                 last_chunk.message_count = chunk_messages.len();
 
-                let json = serde_json::to_string(&chunk_messages)
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                fs::write(&last_chunk.file_path, json)?;
+                let mut transaction = Transaction::new(&self.import_dir);
+                transaction.write(&self.chunk_path(last_chunk), self.chunk_json(&chunk_messages)?);
 
                 index.total_messages += 1;
-                let index_path = self.import_dir.join("chunk_index.json");
-                index.save(&index_path)?;
+                transaction.write(&self.index_path(), index.to_json()?);
+                transaction.commit()?;
 
                 return Ok(message);
             }
@@ -243,32 +734,36 @@ impl MessageStorage {
         let chunks_dir = self.import_dir.join("chunks");
         fs::create_dir_all(&chunks_dir)?;
 
-        let chunk_id = index.chunks.len();
+        // Deletes can leave the chunk count below the highest chunk ID in use
+        let chunk_id = index.chunks
+            .iter()
+            .map(|c| c.chunk_id + 1)
+            .max()
+            .unwrap_or(0);
         let file_name = format!("chunk_{}.json", chunk_id);
         let file_path = chunks_dir.join(&file_name);
 
         let chunk_messages = vec![message.clone()];
-        let json = serde_json::to_string(&chunk_messages)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        fs::write(&file_path, &json)?;
+        let mut transaction = Transaction::new(&self.import_dir);
+        transaction.write(&file_path, self.chunk_json(&chunk_messages)?);
 
         index.chunks.push(ChunkMeta {
             chunk_id,
             start_id: msg_id,
             end_id: msg_id,
             message_count: 1,
-            file_path: file_path.to_string_lossy().to_string(),
+            file_path: format!("chunks/{}", file_name),
         });
         index.total_messages += 1;
 
-        let index_path = self.import_dir.join("chunk_index.json");
-        index.save(&index_path)?;
+        transaction.write(&self.index_path(), index.to_json()?);
+        transaction.commit()?;
 
         Ok(message)
     }
 
-    /// Edit a message's content by its ID.
-    pub fn edit_message(&self, message_id: u64, new_content: String) -> io::Result<StoredMessage> {
+    fn edit_message(&self, message_id: u64, new_content: String) -> io::Result<StoredMessage> {
+        self.recover()?;
         let index = self.load_chunk_index()?;
 
         for chunk_meta in &index.chunks {
@@ -286,9 +781,7 @@ impl MessageStorage {
 
                     let result = msg.clone();
 
-                    let json = serde_json::to_string(&chunk_messages)
-                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                    fs::write(&chunk_meta.file_path, json)?;
+                    self.write_chunk(&self.chunk_path(chunk_meta), &chunk_messages)?;
 
                     return Ok(result);
                 }
@@ -301,9 +794,8 @@ impl MessageStorage {
         ))
     }
 
-    /// Delete a user message by its ID. Marks it as deleted by removing content
-    /// and setting a deleted flag, keeping IDs stable.
-    pub fn delete_message(&self, message_id: u64) -> io::Result<()> {
+    fn delete_message(&self, message_id: u64) -> io::Result<()> {
+        self.recover()?;
         let mut index = self.load_chunk_index()?;
 
         for chunk_meta in index.chunks.iter_mut() {
@@ -322,25 +814,26 @@ impl MessageStorage {
                     chunk_messages.remove(pos);
                     chunk_meta.message_count = chunk_messages.len();
 
+                    let mut transaction = Transaction::new(&self.import_dir);
                     if chunk_messages.is_empty() {
                         // Remove the chunk file and update index
-                        let _ = fs::remove_file(&chunk_meta.file_path);
+                        transaction.remove(&self.chunk_path(chunk_meta));
                     } else {
                         chunk_meta.start_id = chunk_messages.first().map(|m| m.id).unwrap_or(0);
                         chunk_meta.end_id = chunk_messages.last().map(|m| m.id).unwrap_or(0);
 
-                        let json = serde_json::to_string(&chunk_messages)
-                            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                        fs::write(&chunk_meta.file_path, json)?;
+                        transaction.write(&self.chunk_path(chunk_meta), self.chunk_json(&chunk_messages)?);
                     }
 
                     // Update index
                     index.total_messages = index.total_messages.saturating_sub(1);
+                    index.next_message_id = index.next_message_id.max(message_id + 1);
                     index.chunks.retain(|c| c.message_count > 0);
 
-                    let index_path = self.import_dir.join("chunk_index.json");
-                    index.save(&index_path)?;
+                    transaction.write(&self.index_path(), index.to_json()?);
+                    transaction.commit()?;
 
+                    self.compact_if_needed();
                     return Ok(());
                 }
             }
@@ -351,4 +844,133 @@ impl MessageStorage {
             format!("Message {} not found", message_id),
         ))
     }
+
+    /// Extract all user-sent messages from existing chunks (before reimport).
+    fn extract_user_messages(&self) -> io::Result<Vec<StoredMessage>> {
+        let index = match self.load_chunk_index() {
+            Ok(idx) => idx,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut user_messages = Vec::new();
+        for chunk_meta in &index.chunks {
+            if let Ok(messages) = self.load_chunk(chunk_meta) {
+                for msg in messages {
+                    if msg.is_user_message {
+                        user_messages.push(msg);
+                    }
+                }
+            }
+        }
+
+        Ok(user_messages)
+    }
+
+    fn update_messages(&self, update: &mut dyn FnMut(&mut StoredMessage)) -> io::Result<()> {
+        let index = self.load_chunk_index()?;
+        for chunk_meta in &index.chunks {
+            let mut messages = self.load_chunk(chunk_meta)?;
+            messages.iter_mut().for_each(&mut *update);
+            self.write_chunk(&self.chunk_path(chunk_meta), &messages)?;
+        }
+        Ok(())
+    }
+
+    fn compact(&self, renumber: bool) -> io::Result<Compaction> {
+        self.compact_chunks(renumber)
+    }
+
+    fn index_at_timestamp(&self, timestamp: u64) -> io::Result<usize> {
+        let index = self.load_chunk_index()?;
+        let mut position = 0;
+        for chunk_meta in &index.chunks {
+            let messages = self.load_chunk(chunk_meta)?;
+            if let Some(offset) = messages.iter().position(|m| m.timestamp >= timestamp) {
+                return Ok(position + offset);
+            }
+            position += messages.len();
+        }
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_library_relative_paths() {
+        let root = Path::new("/home/a/.sapper");
+        assert_eq!(
+            to_library_relative(root, "/home/a/.sapper/imports/x/attachments/cat.png").as_deref(),
+            Some("imports/x/attachments/cat.png")
+        );
+        // Written before the library moved
+        assert_eq!(
+            to_library_relative(root, "/home/old/.sapper/blobs/ab/abcd.png").as_deref(),
+            Some("blobs/ab/abcd.png")
+        );
+        assert_eq!(to_library_relative(root, "https://cdn.example/cat.png"), None);
+
+        assert_eq!(resolve_library_path(root, "imports/x/avatar.png"), "/home/a/.sapper/imports/x/avatar.png");
+        assert_eq!(resolve_library_path(root, "/home/old/.sapper/imports/x/avatar.png"), "/home/a/.sapper/imports/x/avatar.png");
+        assert_eq!(resolve_library_path(root, "https://cdn.example/cat.png"), "https://cdn.example/cat.png");
+    }
+
+    /// A user-sent message with its timestamp equal to its ID
+    pub(crate) fn message(id: u64) -> StoredMessage {
+        serde_json::from_value(serde_json::json!({
+            "id": id, "originalId": id.to_string(), "type": "Default", "timestamp": id,
+            "timestampEdited": null, "callEndedTimestamp": null, "isPinned": false,
+            "content": "hi", "author": { "id": "1", "name": "a" }, "attachments": [],
+            "embeds": [], "stickers": [], "reactions": [], "mentions": [], "inlineEmojis": [],
+            "mediaRefs": [], "reference": null, "referencedMessage": null, "isUserMessage": true
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_compaction_fills_chunks() {
+        let dir = temp_dir("compact");
+        let import_dir = dir.join("imports").join("a");
+        let storage = MessageStorage::new(import_dir.clone());
+
+        // Chunks left nearly empty by deletes, with gaps in both chunk and message IDs
+        let mut index = ChunkIndex::new();
+        for chunk_id in (0..16).step_by(2) {
+            let messages: Vec<StoredMessage> = (0..3).map(|i| message(chunk_id as u64 * 100 + i * 10)).collect();
+            let file_path = format!("chunks/chunk_{}.json", chunk_id);
+            fs::create_dir_all(import_dir.join("chunks")).unwrap();
+            storage.write_chunk(&import_dir.join(&file_path), &messages).unwrap();
+            index.chunks.push(ChunkMeta {
+                chunk_id,
+                start_id: messages[0].id,
+                end_id: messages[2].id,
+                message_count: 3,
+                file_path,
+            });
+        }
+        index.total_messages = 24;
+        index.save(&storage.index_path()).unwrap();
+        assert!(storage.needs_compaction(&index));
+
+        let compaction = storage.compact(false).unwrap();
+        assert_eq!((compaction.chunks_before, compaction.chunks_after), (8, 1));
+        assert!(compaction.renumbered.is_empty());
+        assert_eq!(storage.chunk_files().unwrap(), vec![import_dir.join("chunks/chunk_0.json")]);
+        let index = storage.load_chunk_index().unwrap();
+        assert!(!storage.needs_compaction(&index));
+        assert_eq!(index.chunks[0].end_id, 1420);
+
+        // The next ID follows the highest one, not the message count, and isn't reused
+        // once that message is deleted
+        assert_eq!(storage.append_message(message(0)).unwrap().id, 1421);
+        storage.delete_message(1421).unwrap();
+        assert_eq!(storage.append_message(message(0)).unwrap().id, 1422);
+
+        let compaction = storage.compact(true).unwrap();
+        assert_eq!(compaction.renumbered.get(&1422), Some(&24));
+        let ids: Vec<u64> = storage.load_all_messages().unwrap().iter().map(|m| m.id).collect();
+        assert_eq!(ids, (0..25).collect::<Vec<u64>>());
+    }
 }
@@ -1,15 +1,26 @@
 use serde::{ Deserialize, Serialize };
+use std::collections::HashMap;
 
 fn default_true() -> bool {
     true
 }
 
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+fn default_message_type() -> String {
+    "Default".to_string()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DiscordExport {
     pub guild: Guild,
     pub channel: Channel,
+    #[serde(default)]
     pub date_range: DateRange,
+    #[serde(default)]
     pub exported_at: String,
     pub messages: Vec<Message>,
 }
@@ -19,6 +30,7 @@ pub struct DiscordExport {
 pub struct Guild {
     pub id: String,
     pub name: String,
+    #[serde(default)]
     pub icon_url: Option<String>,
 }
 
@@ -26,59 +38,81 @@ pub struct Guild {
 #[serde(rename_all = "camelCase")]
 pub struct Channel {
     pub id: String,
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub channel_type: String,
+    #[serde(default)]
     pub category_id: Option<String>,
+    #[serde(default)]
     pub category: Option<String>,
     pub name: String,
+    #[serde(default)]
     pub topic: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DateRange {
     pub after: Option<String>,
     pub before: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Message {
     pub id: String,
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default = "default_message_type")]
     pub message_type: String,
     pub timestamp: String,
+    #[serde(default)]
     pub timestamp_edited: Option<String>,
+    #[serde(default)]
     pub call_ended_timestamp: Option<String>,
+    #[serde(default)]
     pub is_pinned: bool,
+    #[serde(default)]
     pub content: String,
     pub author: Author,
+    #[serde(default)]
     pub attachments: Vec<Attachment>,
-    pub embeds: Vec<serde_json::Value>,
-    pub stickers: Vec<serde_json::Value>,
-    pub reactions: Vec<serde_json::Value>,
-    pub mentions: Vec<serde_json::Value>,
-    pub inline_emojis: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub embeds: Vec<Embed>,
+    #[serde(default)]
+    pub stickers: Vec<Sticker>,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    #[serde(default)]
+    pub mentions: Vec<User>,
+    #[serde(default)]
+    pub inline_emojis: Vec<Emoji>,
+    #[serde(default)]
     pub reference: Option<MessageReference>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageReference {
+    #[serde(default)]
     pub message_id: String,
+    #[serde(default)]
     pub channel_id: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Author {
     pub id: String,
     pub name: String,
+    #[serde(default)]
     pub discriminator: String,
-    pub nickname: String,
+    #[serde(default)]
+    pub nickname: String, // Older exports may omit this; the parser falls back to `name`
+    #[serde(default)]
     pub color: Option<String>,
+    #[serde(default)]
     pub is_bot: bool,
-    pub roles: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    #[serde(default)]
     pub avatar_url: String,
 }
 
@@ -87,21 +121,149 @@ pub struct Author {
 pub struct Attachment {
     pub id: String,
     pub url: String,
+    #[serde(default)]
     pub file_name: String,
+    #[serde(default)]
     pub file_size_bytes: u64,
 }
 
+// Fields DCE writes that these models don't know about yet, kept so nothing is lost on re-serialization
+pub type ExtraFields = serde_json::Map<String, serde_json::Value>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Role {
+    pub id: String,
+    pub name: String,
+    pub color: Option<String>,
+    pub position: i64,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+// A user as written in mentions and reaction user lists
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub discriminator: String,
+    pub nickname: String,
+    pub color: Option<String>,
+    pub is_bot: bool,
+    pub avatar_url: Option<String>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+// Custom or standard emoji, used by inline emojis and reactions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Emoji {
+    pub id: Option<String>,
+    pub name: String,
+    pub code: String,
+    pub is_animated: bool,
+    pub image_url: String,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Reaction {
+    pub emoji: Emoji,
+    pub count: u64,
+    pub users: Vec<User>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Sticker {
+    pub id: String,
+    pub name: String,
+    pub format: String,
+    pub source_url: String,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Embed {
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub timestamp: Option<String>,
+    pub description: Option<String>,
+    pub color: Option<String>,
+    pub author: Option<EmbedAuthor>,
+    pub thumbnail: Option<EmbedMedia>,
+    pub image: Option<EmbedMedia>,
+    pub images: Vec<EmbedMedia>,
+    pub video: Option<EmbedMedia>,
+    pub footer: Option<EmbedFooter>,
+    pub fields: Vec<EmbedField>,
+    pub inline_emojis: Vec<Emoji>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EmbedAuthor {
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub icon_url: Option<String>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+// Thumbnail, image or video attached to an embed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EmbedMedia {
+    pub url: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EmbedFooter {
+    pub text: String,
+    pub icon_url: Option<String>,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EmbedField {
+    pub name: String,
+    pub value: String,
+    pub is_inline: bool,
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
 // Metadata structures for .sapper directory
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ImportMetadata {
     pub imports: Vec<ImportEntry>,
+    #[serde(default)]
+    pub member_merges: HashMap<String, String>, // Library-wide merges: member ID -> canonical member ID
 }
 
 impl Default for ImportMetadata {
     fn default() -> Self {
         Self {
             imports: Vec::new(),
+            member_merges: HashMap::new(),
         }
     }
 }
@@ -121,6 +283,48 @@ pub struct ImportEntry {
     pub avatar_path: String,
     #[serde(default)]
     pub description: String,
+    #[serde(default)]
+    pub source: ImportSource,
+    #[serde(default)]
+    pub channel_id: String,
+    #[serde(default)]
+    pub category_id: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub channel_type: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+}
+
+// How ImportEntry.file_hash was computed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum HashAlgorithm {
+    #[default]
+    Legacy, // std's DefaultHasher, which isn't stable across Rust versions
+    Sha256,
+}
+
+// Where an import's messages are stored
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum StorageBackend {
+    #[default]
+    Json, // 500-message JSON chunk files
+    Sqlite, // One messages.db per import
+}
+
+// Which kind of export an import was created from
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportSource {
+    #[default]
+    DiscordChatExporter,
+    DiscordDataPackage,
+    Telegram,
+    WhatsApp,
+    Slack,
 }
 
 // Member storage for conversations
@@ -134,21 +338,45 @@ pub struct Member {
     pub avatar_url: String, // Path to avatar file (editable)
     pub color: Option<String>, // Role color
     pub is_bot: bool,
-    pub roles: Vec<serde_json::Value>,
+    pub roles: Vec<Role>,
     #[serde(default)]
     pub hidden: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_ids: Vec<String>, // Other member IDs folded into this one (display only)
+}
+
+// One run of a nickname, avatar or color value, in the order it appeared in the export
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberHistoryEntry {
+    pub value: Option<String>,
+    pub first_seen: String, // Timestamp of the first message with this value
+    pub last_seen: String, // Timestamp of the last message with this value
+}
+
+// Timeline of identity changes for a single member within an export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberHistory {
+    pub member_id: String,
+    pub nicknames: Vec<MemberHistoryEntry>,
+    pub avatars: Vec<MemberHistoryEntry>,
+    pub colors: Vec<MemberHistoryEntry>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MemberStorage {
     pub members: Vec<Member>,
+    #[serde(default)]
+    pub member_history: Vec<MemberHistory>,
 }
 
 impl Default for MemberStorage {
     fn default() -> Self {
         Self {
             members: Vec::new(),
+            member_history: Vec::new(),
         }
     }
 }
@@ -165,6 +393,49 @@ pub struct ImportData {
     pub mutable_conversation: bool,
     #[serde(default)]
     pub mutable_member_id: Option<String>, // Which member "is you" for mutable conversations
+    #[serde(default)]
+    pub member_history: Vec<MemberHistory>,
+    #[serde(default)]
+    pub member_merges: HashMap<String, String>, // Member ID -> canonical member ID within this import
+    #[serde(default)]
+    pub thread_links: Vec<ThreadLink>, // Threads of this channel that are imported too
+    #[serde(default)]
+    pub asset_report: Option<AssetCopyReport>, // Absent for imports made before it was recorded
+}
+
+// Which of the export's media files an import copied and which it left behind
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AssetCopyReport {
+    pub copied: usize,
+    pub copied_bytes: u64,
+    pub reused: usize, // Already at the destination, e.g. when resuming an interrupted import
+    pub failed: Vec<String>, // Found but could not be copied
+    pub missing: Vec<String>, // Referenced by the export but not found
+    pub unreferenced: Vec<String>, // Files in the export's media folders that nothing points at
+}
+
+// Progress record of an import that has not completed yet (import_journal.json)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportJournal {
+    pub import_id: String,
+    pub file_hash: String,
+    pub alias: String,
+    pub channel_name: String,
+    pub started_at: String,
+    #[serde(default)]
+    pub copied_files: usize, // Counted from the copy journal when listed
+}
+
+// A thread import linked to its parent channel import
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadLink {
+    pub thread_import_id: String,
+    pub thread_channel_id: String,
+    pub thread_name: String,
+    pub starter_message_id: Option<u64>, // Stored message ID in the parent import
 }
 
 impl Default for ImportData {
@@ -176,17 +447,31 @@ impl Default for ImportData {
             last_updated: chrono::Utc::now().to_rfc3339(),
             mutable_conversation: false,
             mutable_member_id: None,
+            member_history: Vec::new(),
+            member_merges: HashMap::new(),
+            thread_links: Vec::new(),
+            asset_report: None,
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberStats {
+    pub member_id: String,
+    pub nickname: String,
+    pub message_count: usize,
+    pub first_timestamp: u64,
+    pub last_timestamp: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AppConfig {
     pub theme: String,
     pub last_opened_chat: Option<String>,
     #[serde(default)]
-    pub conversation_positions: std::collections::HashMap<String, ConversationPosition>,
+    pub conversation_positions: HashMap<String, ConversationPosition>,
     #[serde(default)]
     pub last_changelog_version: Option<String>,
     #[serde(default = "default_true")]
@@ -197,6 +482,10 @@ pub struct AppConfig {
     pub debug_mode: bool,
     #[serde(default)]
     pub conversation_order: Vec<String>,
+    #[serde(default)]
+    pub storage_backend: StorageBackend, // For new imports; existing ones are converted explicitly
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32, // Deleted conversations are purged after this long; 0 keeps them
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -210,12 +499,14 @@ impl Default for AppConfig {
         Self {
             theme: "dark".to_string(),
             last_opened_chat: None,
-            conversation_positions: std::collections::HashMap::new(),
+            conversation_positions: HashMap::new(),
             last_changelog_version: None,
             notifications_enabled: true,
             skip_large_import_warning: false,
             debug_mode: false,
             conversation_order: Vec::new(),
+            storage_backend: StorageBackend::default(),
+            trash_retention_days: default_trash_retention_days(),
         }
     }
 }
@@ -232,6 +523,43 @@ pub struct ImportPreview {
     pub attachments_size: u64,
 }
 
+// Server tree built from the guild/category of each import
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuildTreeNode {
+    pub guild_id: String,
+    pub guild_name: String,
+    pub categories: Vec<CategoryTreeNode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryTreeNode {
+    pub category_id: Option<String>,
+    pub category: Option<String>,
+    pub import_ids: Vec<String>,
+}
+
+// Dry-run validation of a DCE export, produced by the lenient parser
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportValidationReport {
+    pub total_messages: usize,
+    pub valid_messages: usize,
+    pub skipped_messages: Vec<SkippedMessage>,
+    pub unknown_fields: Vec<String>, // Dotted paths, e.g. "message.interaction"
+    pub duplicate_ids: Vec<String>,
+    pub unparseable_timestamps: Vec<String>, // IDs of messages whose timestamp isn't RFC 3339
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedMessage {
+    pub index: usize,
+    pub id: Option<String>,
+    pub error: String,
+}
+
 // Disk usage structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -249,9 +577,155 @@ pub struct AppDiskUsage {
     pub imports_bytes: u64,
     pub cache_bytes: u64,
     pub logs_bytes: u64,
+    pub blobs_bytes: u64, // Media shared between imports, not counted in any conversation
+    #[serde(default)]
+    pub trash_bytes: u64,
     pub conversations: Vec<ConversationDiskUsage>,
 }
 
+// A deleted conversation kept in the trash until it's restored or purged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashedImport {
+    pub entry: ImportEntry,
+    pub deleted_at: String,
+    pub purge_at: Option<String>, // None while retention is off
+    pub size_bytes: u64, // Shared media is counted under blobs, as for conversations
+}
+
+// Outcome of moving existing imports' media into the shared blob store
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobMigrationReport {
+    pub imports_migrated: usize,
+    pub files_deduplicated: usize,
+    pub bytes_saved: u64,
+}
+
+// Library integrity check results
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum IntegrityIssueKind {
+    CorruptJson,
+    CorruptDatabase,
+    MissingImportFolder,
+    OrphanImportFolder,
+    MissingChunk,
+    OrphanChunk,
+    ChunkCountMismatch,
+    IndexTotalMismatch,
+    MessageCountMismatch,
+    MissingMedia,
+    SearchIndexUnreadable,
+    SearchIndexMismatch,
+    OrphanBlob,
+    RefcountMismatch,
+}
+
+// Declared in the order repairs run: later ones read the chunk index
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum RepairAction {
+    RebuildChunkIndex,
+    RebuildSearchIndex,
+    RelinkMedia,
+    DropOrphans,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityIssue {
+    pub kind: IntegrityIssueKind,
+    pub import_id: Option<String>, // None for library-wide issues
+    pub path: Option<String>, // Relative to the library root
+    pub detail: String,
+    pub repair: Option<RepairAction>, // None when only a re-import can help
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityReport {
+    pub checked_at: String,
+    pub imports_checked: usize,
+    pub messages_checked: usize,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+// A repair to run; library-wide when import_id is None
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairRequest {
+    pub action: RepairAction,
+    #[serde(default)]
+    pub import_id: Option<String>,
+}
+
+// Space taken by files nothing uses, found by a garbage collection pass
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum GarbageKind {
+    OrphanImportFolder,
+    StaleTempFile,
+    UnusedCache,
+    UnreferencedAttachment,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GarbageItem {
+    pub kind: GarbageKind,
+    pub import_id: Option<String>,
+    pub path: String, // Relative to the library root
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GarbageReport {
+    pub dry_run: bool,
+    pub items: Vec<GarbageItem>,
+    pub total_bytes: u64, // Reclaimable on a dry run, reclaimed otherwise
+}
+
+// Result of compacting one conversation's message storage
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompactionReport {
+    pub import_id: String,
+    pub chunks_before: usize,
+    pub chunks_after: usize,
+    pub renumbered_messages: usize,
+}
+
+// Library folders known to this installation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryInfo {
+    pub name: String,
+    pub path: String,
+}
+
+// What chose the active library
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LibrarySource {
+    Cli,
+    Env,
+    Portable,
+    Config,
+    Default,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryList {
+    pub active: String,
+    pub source: LibrarySource,
+    pub portable: bool,
+    pub libraries: Vec<LibraryInfo>,
+}
+
 // Import backup result structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -277,6 +751,15 @@ pub struct FailedImport {
     pub error: String,
 }
 
+// Outcome of importing a package that holds several conversations
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageImportResult {
+    pub imported: Vec<ImportEntry>,
+    pub skipped: Vec<String>, // Already in the library
+    pub failed: Vec<FailedImport>,
+}
+
 // Version compatibility structures
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "UPPERCASE")]
@@ -302,3 +785,52 @@ pub struct ImportEntryWithCompatibility {
     pub compatibility: VersionCompatibility,
     pub missing: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dce_embeds_and_stickers_round_trip() {
+        let message =
+            serde_json::json!({
+            "id": "1", "type": "Default", "timestamp": "2023-05-01T12:00:00+00:00", "timestampEdited": null,
+            "callEndedTimestamp": null, "isPinned": false, "content": "https://example.com/post",
+            "author": {
+                "id": "5", "name": "alice", "discriminator": "0000", "nickname": "Alice", "color": "#1ABC9C", "isBot": false,
+                "roles": [{ "id": "9", "name": "Mod", "color": "#1ABC9C", "position": 3 }],
+                "avatarUrl": "media/avatar.png"
+            },
+            "attachments": [],
+            "embeds": [{
+                "title": "A post", "url": "https://example.com/post", "timestamp": "2023-05-01T11:00:00+00:00",
+                "description": "Read more <:wave:42>", "color": "#5865F2",
+                "author": { "name": "Example", "url": "https://example.com", "iconUrl": "media/icon.png" },
+                "thumbnail": { "url": "media/thumb.png", "width": 80, "height": 80 },
+                "image": null,
+                "images": [{ "url": "media/image.png", "width": 640, "height": 480 }],
+                "video": { "url": "https://example.com/clip.mp4", "width": 1280, "height": 720 },
+                "footer": { "text": "Example", "iconUrl": null },
+                "fields": [{ "name": "Votes", "value": "12", "isInline": true }],
+                "inlineEmojis": [{ "id": "42", "name": "wave", "code": "wave", "isAnimated": false, "imageUrl": "media/wave.png" }],
+                "provider": { "name": "Example" }
+            }],
+            "stickers": [{ "id": "7", "name": "Hello", "format": "Apng", "sourceUrl": "media/sticker.png" }],
+            "reactions": [{
+                "emoji": { "id": null, "name": "👍", "code": "thumbsup", "isAnimated": false, "imageUrl": "media/thumbsup.svg" },
+                "count": 1,
+                "users": [{ "id": "6", "name": "bob", "discriminator": "0000", "nickname": "Bob", "color": null, "isBot": false, "avatarUrl": null }]
+            }],
+            "mentions": [{ "id": "6", "name": "bob", "discriminator": "0000", "nickname": "Bob", "color": null, "isBot": false, "avatarUrl": "media/bob.png", "roles": [] }],
+            "inlineEmojis": [],
+            "reference": null
+        });
+
+        let parsed: Message = serde_json::from_value(message.clone()).unwrap();
+        let parsed = serde_json::to_value(&parsed).unwrap();
+        for field in ["embeds", "stickers", "reactions", "mentions"] {
+            assert_eq!(parsed[field], message[field], "{} changed", field);
+        }
+        assert_eq!(parsed["author"]["roles"], message["author"]["roles"]);
+    }
+}
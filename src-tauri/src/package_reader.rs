@@ -0,0 +1,225 @@
+use crate::export_parser::is_contained_path;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ self, Read };
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use walkdir::WalkDir;
+
+/// Separates an archive from an entry inside it: "exports.zip::general.json"
+pub const ARCHIVE_ENTRY_SEPARATOR: &str = "::";
+
+/// Split "exports.zip::inner/general.json" into the archive and the entry. Plain
+/// ".zip" paths have no entry; anything else isn't an archive path.
+pub fn split_archive_path(path: &str) -> Option<(PathBuf, Option<String>)> {
+    if let Some((archive, entry)) = path.split_once(ARCHIVE_ENTRY_SEPARATOR) {
+        return Some((PathBuf::from(archive), Some(entry.to_string())));
+    }
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+        .then(|| (PathBuf::from(path), None))
+}
+
+/// Entry names come from the package or the export inside it, so one leading out of
+/// the package root is refused rather than read or written
+fn check_entry_name(name: &str) -> io::Result<()> {
+    if is_contained_path(name) {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Entry {} leads outside the package", name)))
+    }
+}
+
+/// Read-only view over an export that was either extracted to a folder or left as a zip.
+/// Entry names are relative to the root and always use forward slashes.
+pub struct PackageReader {
+    source: PackageSource,
+    /// Entry names by their lowercase form, for `find`
+    lowercase_names: HashMap<String, String>,
+}
+
+enum PackageSource {
+    Directory(PathBuf),
+    Zip(Mutex<zip::ZipArchive<fs::File>>),
+}
+
+impl PackageReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let source = if path.is_dir() {
+            PackageSource::Directory(path.to_path_buf())
+        } else {
+            let file = fs::File::open(path)?;
+            let archive = zip::ZipArchive
+                ::new(file)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            PackageSource::Zip(Mutex::new(archive))
+        };
+
+        let mut reader = Self { source, lowercase_names: HashMap::new() };
+        for name in reader.file_names() {
+            reader.lowercase_names.entry(name.to_lowercase()).or_insert(name);
+        }
+        Ok(reader)
+    }
+
+    /// All file entries in the package
+    pub fn file_names(&self) -> Vec<String> {
+        match &self.source {
+            PackageSource::Directory(root) =>
+                WalkDir::new(root)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file())
+                    .filter_map(|e| {
+                        e.path()
+                            .strip_prefix(root)
+                            .ok()
+                            .map(|p| p.to_string_lossy().replace('\\', "/"))
+                    })
+                    .collect(),
+            PackageSource::Zip(archive) => {
+                let archive = archive.lock().unwrap();
+                // Names like "../x" would be written outside wherever the entry is copied
+                archive
+                    .file_names()
+                    .filter(|name| !name.ends_with('/') && is_contained_path(name))
+                    .map(|name| name.to_string())
+                    .collect()
+            }
+        }
+    }
+
+    /// All file entries with their uncompressed sizes
+    pub fn file_sizes(&self) -> Vec<(String, u64)> {
+        match &self.source {
+            PackageSource::Directory(root) =>
+                self
+                    .file_names()
+                    .into_iter()
+                    .map(|name| {
+                        let size = fs::metadata(root.join(&name)).map(|m| m.len()).unwrap_or(0);
+                        (name, size)
+                    })
+                    .collect(),
+            PackageSource::Zip(archive) => {
+                let mut archive = archive.lock().unwrap();
+                (0..archive.len())
+                    .filter_map(|i| {
+                        let entry = archive.by_index_raw(i).ok()?;
+                        (!entry.is_dir()).then(|| (entry.name().to_string(), entry.size()))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Find an entry by path, ignoring case (package layouts differ in capitalization)
+    pub fn find(&self, name: &str) -> Option<String> {
+        self.lowercase_names.get(&name.to_lowercase()).cloned()
+    }
+
+    pub fn read_bytes(&self, name: &str) -> io::Result<Vec<u8>> {
+        check_entry_name(name)?;
+        match &self.source {
+            PackageSource::Directory(root) => fs::read(root.join(name)),
+            PackageSource::Zip(archive) => {
+                let mut archive = archive.lock().unwrap();
+                let mut entry = archive
+                    .by_name(name)
+                    .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+                let mut contents = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut contents)?;
+                Ok(contents)
+            }
+        }
+    }
+
+    pub fn read_to_string(&self, name: &str) -> io::Result<String> {
+        let bytes = self.read_bytes(name)?;
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn entry_size(&self, name: &str) -> io::Result<u64> {
+        check_entry_name(name)?;
+        match &self.source {
+            PackageSource::Directory(root) => Ok(fs::metadata(root.join(name))?.len()),
+            PackageSource::Zip(archive) => {
+                let mut archive = archive.lock().unwrap();
+                let entry = archive
+                    .by_name(name)
+                    .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+                Ok(entry.size())
+            }
+        }
+    }
+
+    /// Stream an entry through `read` rather than reading it into memory
+    pub fn read_with<T>(&self, name: &str, read: impl FnOnce(&mut dyn Read) -> T) -> io::Result<T> {
+        check_entry_name(name)?;
+        match &self.source {
+            PackageSource::Directory(root) => Ok(read(&mut fs::File::open(root.join(name))?)),
+            PackageSource::Zip(archive) => {
+                let mut archive = archive.lock().unwrap();
+                let mut entry = archive
+                    .by_name(name)
+                    .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+                Ok(read(&mut entry))
+            }
+        }
+    }
+
+    /// SHA-256 of an entry, streamed rather than read into memory
+    pub fn hash(&self, name: &str) -> io::Result<String> {
+        self.read_with(name, |entry| crate::sapper_core::hash_reader(entry))?
+    }
+
+    /// Copy one entry to `dest`, creating parent directories
+    pub fn copy_to(&self, name: &str, dest: &Path) -> io::Result<u64> {
+        check_entry_name(name)?;
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match &self.source {
+            PackageSource::Directory(root) => fs::copy(root.join(name), dest),
+            PackageSource::Zip(archive) => {
+                let mut archive = archive.lock().unwrap();
+                let mut entry = archive
+                    .by_name(name)
+                    .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+                let mut file = fs::File::create(dest)?;
+                io::copy(&mut entry, &mut file)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use std::io::Write;
+
+    #[test]
+    fn test_entries_outside_root_are_refused() {
+        let dir = temp_dir("zip");
+        let zip_path = dir.join("export.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        for name in ["media/cat.png", "../evil.txt", "media/../../evil.txt"] {
+            writer.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(b"x").unwrap();
+        }
+        writer.finish().unwrap();
+
+        let package = PackageReader::open(&zip_path).unwrap();
+        assert_eq!(package.file_names(), vec!["media/cat.png"]);
+        assert_eq!(package.find("Media/CAT.png").as_deref(), Some("media/cat.png"));
+        assert_eq!(package.find("../evil.txt"), None);
+        let dest = dir.join("out");
+        let err = package.copy_to("../evil.txt", &dest.join("../evil.txt")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!dir.join("evil.txt").exists());
+        package.copy_to("media/cat.png", &dest.join("media/cat.png")).unwrap();
+    }
+}
@@ -1,24 +1,224 @@
-use crate::message_storage::{ MessageStorage, StoredMessage };
+use crate::blob_store::{ self, BlobStore };
+use crate::discord_package;
+use crate::durable;
+use crate::export_parser;
+use crate::library;
+use crate::logger;
+use crate::message_storage::{ self, resolve_library_path, to_library_relative, MessageStorage, StoredMessage };
 use crate::models::*;
+use crate::package_reader::{ self, PackageReader };
 use crate::search::MessageSearchIndex;
+use crate::slack;
+use crate::telegram;
+use crate::whatsapp;
+use std::collections::{ HashMap, HashSet };
 use std::fs;
 use std::io::{ self, Read };
 use std::path::{ Path, PathBuf };
-use tracing::instrument;
+use std::sync::{ Arc, Mutex };
+use tracing::{ info, instrument };
 use uuid::Uuid;
 
 pub struct SapperCore {
     pub sapper_dir: PathBuf,
 }
 
+/// Background tasks open their own SapperCore, so metadata and config read-modify-writes
+/// are serialized process-wide rather than per instance. Other processes are kept out by
+/// the library lock.
+static METADATA_LOCK: Mutex<()> = Mutex::new(());
+static CONFIG_LOCK: Mutex<()> = Mutex::new(());
+static IMPORT_DATA_LOCK: Mutex<()> = Mutex::new(());
+
+/// Progress callback: phase, message, current, total
+pub type ImportProgress<'a> = dyn Fn(&str, &str, Option<usize>, Option<usize>) + 'a;
+
+/// Where an import's media files are read from
+pub enum MediaSource {
+    None,
+    /// Asset URLs in the export are relative to these directories (several for
+    /// partitioned exports); files present in more than one are copied once
+    Directories(Vec<PathBuf>),
+    /// Only these entries of a folder or zip, kept at the same relative paths
+    Files {
+        package: Arc<PackageReader>,
+        files: Vec<String>,
+    },
+    /// Everything under `root` inside an archive, the zipped counterpart of `Directories`
+    Archive {
+        package: Arc<PackageReader>,
+        root: String,
+    },
+}
+
+/// Media files already placed during a batch import, by source path. Later imports
+/// hard-link to the first copy instead of copying the file again.
+#[derive(Clone, Default)]
+pub struct MediaDedup(Arc<std::sync::Mutex<HashMap<PathBuf, PathBuf>>>);
+
+impl MediaDedup {
+    fn place(&self, source: &Path, dest: &Path) -> io::Result<()> {
+        let existing = self.0.lock().unwrap().get(source).cloned();
+        if let Some(existing) = existing {
+            // Hard links can fail across volumes; fall back to a copy
+            if existing.exists() && fs::hard_link(&existing, dest).is_ok() {
+                return Ok(());
+            }
+        }
+        fs::copy(source, dest)?;
+        self.0.lock().unwrap().insert(source.to_path_buf(), dest.to_path_buf());
+        Ok(())
+    }
+}
+
+/// The export JSON kept as the import's export.json
+pub enum OriginalJson {
+    File(PathBuf),
+    Contents(String),
+}
+
+/// A DCE export JSON, either on disk next to its media folder or inside a zip
+enum ExportFile {
+    Disk(PathBuf),
+    Archive {
+        package: Arc<PackageReader>,
+        entry: String,
+    },
+}
+
+impl ExportFile {
+    /// Accepts a JSON path, "archive.zip::entry.json", or a zip holding a single export
+    fn open(path: &str) -> io::Result<Self> {
+        let Some((archive_path, entry)) = package_reader::split_archive_path(path) else {
+            let json_path = PathBuf::from(path);
+            if !json_path.exists() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "JSON file not found"));
+            }
+            return Ok(ExportFile::Disk(json_path));
+        };
+
+        if !archive_path.exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Archive not found"));
+        }
+        let package = Arc::new(PackageReader::open(&archive_path)?);
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                let mut exports = archive_exports(&package);
+                if exports.len() != 1 {
+                    return Err(
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Archive contains {} exports, pick one to import", exports.len())
+                        )
+                    );
+                }
+                exports.remove(0)
+            }
+        };
+        Ok(ExportFile::Archive { package, entry })
+    }
+
+    fn read_contents(&self) -> io::Result<String> {
+        match self {
+            ExportFile::Disk(path) => fs::read_to_string(path),
+            ExportFile::Archive { package, entry } => package.read_to_string(entry),
+        }
+    }
+
+    fn file_name(&self) -> String {
+        let name = match self {
+            ExportFile::Disk(path) => path.to_string_lossy().to_string(),
+            ExportFile::Archive { entry, .. } => entry.clone(),
+        };
+        name.rsplit(['/', '\\']).next().unwrap_or("unknown").to_string()
+    }
+
+    /// Media URLs are relative to the folder holding the JSON
+    fn media_source(&self) -> MediaSource {
+        match self {
+            ExportFile::Disk(path) =>
+                match path.parent() {
+                    Some(source_dir) if source_dir.exists() =>
+                        MediaSource::Directories(vec![source_dir.to_path_buf()]),
+                    _ => MediaSource::None,
+                }
+            ExportFile::Archive { package, entry } =>
+                MediaSource::Archive { package: package.clone(), root: archive_root(entry) },
+        }
+    }
+
+    /// The referenced media found beside the export, as an import would copy it
+    fn resolve_assets(&self, export: &DiscordExport) -> io::Result<(Vec<CopyJob>, AssetCopyReport)> {
+        let assets = export_parser::referenced_assets(export);
+        match self {
+            ExportFile::Disk(path) => {
+                let source_dir = path.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+                resolve_directory_assets(&[source_dir], &assets)
+            }
+            ExportFile::Archive { package, entry } => Ok(resolve_archive_assets(package, &archive_root(entry), &assets)),
+        }
+    }
+
+    fn job_size(&self, job: &CopyJob) -> u64 {
+        let size = match (&job.source, self) {
+            (CopySource::Disk(path), _) => fs::metadata(path).map(|m| m.len()),
+            (CopySource::Package(name), ExportFile::Archive { package, .. }) => package.entry_size(name),
+            (CopySource::Package(_), ExportFile::Disk(_)) => Ok(0),
+        };
+        size.unwrap_or(0)
+    }
+}
+
+/// "exports/general.json" -> "exports/"
+fn archive_root(entry: &str) -> String {
+    entry
+        .rsplit_once('/')
+        .map(|(dir, _)| format!("{}/", dir))
+        .unwrap_or_default()
+}
+
+/// DCE export JSONs inside an archive, leaving out anything in a media folder and JSON
+/// files that aren't exports
+fn archive_exports(package: &PackageReader) -> Vec<String> {
+    let mut exports: Vec<String> = package
+        .file_names()
+        .into_iter()
+        .filter(|name| name.to_lowercase().ends_with(".json"))
+        .filter(|name| !name.split('/').any(|part| part.ends_with("_Files")))
+        .filter(|name| package.read_with(name, |entry| export_parser::is_export(entry)).unwrap_or(false))
+        .collect();
+    exports.sort();
+    exports
+}
+
+/// A conversation converted from a non-DCE format, with the media files it references
+pub struct ConvertedExport {
+    pub export: DiscordExport,
+    pub file_hash: String,
+    pub media_files: Vec<String>,
+}
+
+/// An export ready for the import pipeline, whatever format it was converted from
+pub struct PreparedImport {
+    pub export: DiscordExport,
+    pub file_hash: String,
+    pub source: ImportSource,
+    /// Kept verbatim as export.json; otherwise `export` is serialized
+    pub original_json: Option<OriginalJson>,
+    pub media: MediaSource,
+    /// Shared across a batch so media used by several channels is stored once
+    pub media_dedup: Option<MediaDedup>,
+}
+
 impl SapperCore {
+    /// Open the active library (see `library::active_root`)
     pub fn new() -> io::Result<Self> {
-        let sapper_dir = if let Some(home) = directories::UserDirs::new() {
-            home.home_dir().join(".sapper")
-        } else {
-            PathBuf::from(".sapper")
-        };
+        Self::open(library::active_root())
+    }
 
+    /// Open the library at `sapper_dir`, creating its folders if needed
+    pub fn open(sapper_dir: PathBuf) -> io::Result<Self> {
         let core = Self { sapper_dir };
         core.init()?;
         Ok(core)
@@ -52,24 +252,59 @@ impl SapperCore {
         Ok(())
     }
 
+    /// Metadata with import paths resolved against the library root
     pub fn load_metadata(&self) -> io::Result<ImportMetadata> {
+        let mut metadata = self.load_stored_metadata()?;
+        for entry in metadata.imports.iter_mut() {
+            entry.import_path = resolve_library_path(&self.sapper_dir, &entry.import_path);
+            entry.avatar_path = resolve_library_path(&self.sapper_dir, &entry.avatar_path);
+        }
+        Ok(metadata)
+    }
+
+    /// Metadata as written, with paths relative to the library root
+    fn load_stored_metadata(&self) -> io::Result<ImportMetadata> {
         let metadata_path = self.sapper_dir.join("metadata.json");
         let contents = fs::read_to_string(metadata_path)?;
         serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
-    pub fn save_metadata(&self, metadata: &ImportMetadata) -> io::Result<()> {
-        let metadata_path = self.sapper_dir.join("metadata.json");
-        let temp_path = self.sapper_dir.join("metadata.json.tmp");
+    /// Copy of `metadata` with paths made relative to the library root, so the library
+    /// can move without breaking
+    fn relativize_metadata(&self, metadata: &ImportMetadata) -> ImportMetadata {
+        let mut metadata = metadata.clone();
+        for entry in metadata.imports.iter_mut() {
+            if let Some(relative) = to_library_relative(&self.sapper_dir, &entry.import_path) {
+                entry.import_path = relative;
+            }
+            if let Some(relative) = to_library_relative(&self.sapper_dir, &entry.avatar_path) {
+                entry.avatar_path = relative;
+            }
+        }
+        metadata
+    }
 
-        let contents = serde_json
-            ::to_string_pretty(metadata)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fn save_metadata(&self, metadata: &ImportMetadata) -> io::Result<()> {
+        let metadata_path = self.sapper_dir.join("metadata.json");
+        durable::write_json_atomic(&metadata_path, &self.relativize_metadata(metadata))
+    }
 
-        fs::write(&temp_path, contents)?;
-        fs::rename(temp_path, metadata_path)?;
+    /// Load the metadata, apply `update` and save the result, with no other update in
+    /// between. Nothing is saved if `update` fails.
+    pub fn update_metadata<T>(&self, update: impl FnOnce(&mut ImportMetadata) -> io::Result<T>) -> io::Result<T> {
+        let _guard = METADATA_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut metadata = self.load_metadata()?;
+        let result = update(&mut metadata)?;
+        self.save_metadata(&metadata)?;
+        Ok(result)
+    }
 
-        Ok(())
+    /// Run `f` on the current metadata with updates held off, for work that needs the
+    /// import list and the import folders to agree, such as deleting folders nothing
+    /// refers to. `f` must not update the metadata itself.
+    pub(crate) fn with_metadata_locked<T>(&self, f: impl FnOnce(&ImportMetadata) -> io::Result<T>) -> io::Result<T> {
+        let _guard = METADATA_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        f(&self.load_metadata()?)
     }
 
     pub fn load_config(&self) -> io::Result<AppConfig> {
@@ -78,26 +313,23 @@ impl SapperCore {
         serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
-    pub fn save_config(&self, config: &AppConfig) -> io::Result<()> {
+    fn save_config(&self, config: &AppConfig) -> io::Result<()> {
         let config_path = self.sapper_dir.join("config.json");
-        let temp_path = self.sapper_dir.join("config.json.tmp");
-
-        let contents = serde_json
-            ::to_string_pretty(config)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-
-        fs::write(&temp_path, contents)?;
-        fs::rename(temp_path, config_path)?;
+        durable::write_json_atomic(&config_path, config)
+    }
 
-        Ok(())
+    /// Load the config, apply `update` and save the result, with no other update in between
+    pub fn update_config<T>(&self, update: impl FnOnce(&mut AppConfig) -> T) -> io::Result<T> {
+        let _guard = CONFIG_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut config = self.load_config()?;
+        let result = update(&mut config);
+        self.save_config(&config)?;
+        Ok(result)
     }
 
     pub fn save_emoji_cache(&self, data: &str) -> io::Result<()> {
         let emoji_path = self.sapper_dir.join("cache/emojis/data.json");
-        let temp_path = self.sapper_dir.join("cache/emojis/data.json.tmp");
-        fs::write(&temp_path, data)?;
-        fs::rename(temp_path, emoji_path)?;
-        Ok(())
+        durable::write_atomic(&emoji_path, data)
     }
 
     pub fn load_emoji_cache(&self) -> io::Result<Option<String>> {
@@ -129,19 +361,545 @@ impl SapperCore {
     ) -> io::Result<ImportEntry>
         where F: Fn(&str, &str, Option<usize>, Option<usize>)
     {
-        let json_path_buf = PathBuf::from(json_path);
-
-        if !json_path_buf.exists() {
-            return Err(io::Error::new(io::ErrorKind::NotFound, "JSON file not found"));
-        }
+        let export_file = ExportFile::open(json_path)?;
 
         progress("parsing", "Parsing JSON export...", None, None);
-        let export_data = self.parse_export(&json_path_buf)?;
-        let file_hash = self.calculate_file_hash(&json_path_buf)?;
+        let contents = export_file.read_contents()?;
+        let export_data = export_parser::parse_export_lenient(&contents)?.export;
+        let file_hash = hash_bytes(contents.as_bytes());
 
         self.check_cancelled(cancelled)?;
 
-        let import_id = Uuid::new_v4().to_string();
+        let media = export_file.media_source();
+        let original_json = match export_file {
+            ExportFile::Disk(path) => OriginalJson::File(path),
+            ExportFile::Archive { .. } => OriginalJson::Contents(contents),
+        };
+
+        self.import_prepared(
+            PreparedImport {
+                export: export_data,
+                file_hash,
+                source: ImportSource::DiscordChatExporter,
+                original_json: Some(original_json),
+                media,
+                media_dedup: None,
+            },
+            alias,
+            &progress,
+            cancelled
+        )
+    }
+
+    /// Import the parts of a partitioned DCE export ("channel [part N].json") as one conversation.
+    /// A single path is expanded to all sibling parts of the same channel.
+    #[instrument(skip_all)]
+    pub fn import_partitioned_with_callbacks<F>(
+        &self,
+        json_paths: &[String],
+        alias: Option<String>,
+        progress: F,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<ImportEntry>
+        where F: Fn(&str, &str, Option<usize>, Option<usize>)
+    {
+        let part_paths = match json_paths {
+            [single] => export_parser::find_partition_parts(Path::new(single))?,
+            _ => {
+                let mut paths: Vec<PathBuf> = json_paths.iter().map(PathBuf::from).collect();
+                paths.sort_by_key(|p| export_parser::partition_of(p).map(|(_, part)| part));
+                paths
+            }
+        };
+        let prepared = self.prepare_parts(&part_paths, &progress, cancelled)?;
+        self.import_prepared(prepared, alias, &progress, cancelled)
+    }
+
+    /// Parse one DCE export, or all parts of a partitioned one stitched together
+    fn prepare_parts(
+        &self,
+        part_paths: &[PathBuf],
+        progress: &ImportProgress,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<PreparedImport> {
+        if let [json_path] = part_paths {
+            let contents = fs::read_to_string(json_path)?;
+            let media = match json_path.parent() {
+                Some(source_dir) => MediaSource::Directories(vec![source_dir.to_path_buf()]),
+                None => MediaSource::None,
+            };
+            return Ok(PreparedImport {
+                export: export_parser::parse_export_lenient(&contents)?.export,
+                file_hash: hash_bytes(contents.as_bytes()),
+                source: ImportSource::DiscordChatExporter,
+                original_json: Some(OriginalJson::File(json_path.clone())),
+                media,
+                media_dedup: None,
+            });
+        }
+
+        let total = part_paths.len();
+        let mut parts = Vec::with_capacity(total);
+        let mut part_hashes = String::new();
+        let mut source_dirs: Vec<PathBuf> = Vec::new();
+        for (idx, part_path) in part_paths.iter().enumerate() {
+            self.check_cancelled(cancelled)?;
+            progress("parsing", "Parsing export parts...", Some(idx + 1), Some(total));
+
+            parts.push(self.parse_export(part_path)?);
+            part_hashes.push_str(&self.calculate_file_hash(part_path)?);
+            if let Some(parent) = part_path.parent() {
+                if !source_dirs.iter().any(|d| d == parent) {
+                    source_dirs.push(parent.to_path_buf());
+                }
+            }
+        }
+
+        let export = export_parser::stitch_partitions(parts)?;
+        info!("Stitched {} parts into {} messages", total, export.messages.len());
+
+        Ok(PreparedImport {
+            export,
+            file_hash: hash_bytes(part_hashes.as_bytes()),
+            source: ImportSource::DiscordChatExporter,
+            original_json: None,
+            media: MediaSource::Directories(source_dirs),
+            media_dedup: None,
+        })
+    }
+
+    /// Import every DCE export found in a folder (e.g. a whole server), stitching
+    /// partitioned channels and copying media shared between channels only once
+    #[instrument(skip_all, fields(folder_path))]
+    pub fn import_folder_with_callbacks<F>(
+        &self,
+        folder_path: &str,
+        progress: F,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<Vec<(String, Result<ImportEntry, String>)>>
+        where F: Fn(&str, &str, Option<usize>, Option<usize>)
+    {
+        let folder = PathBuf::from(folder_path);
+        if !folder.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Folder not found"));
+        }
+
+        progress("parsing", "Looking for exports...", None, None);
+        let groups = export_parser::find_exports_in_folder(&folder);
+        let total = groups.len();
+        info!("Found {} exports in folder", total);
+
+        let media_dedup = MediaDedup::default();
+        let mut results = Vec::with_capacity(total);
+        for (idx, part_paths) in groups.into_iter().enumerate() {
+            self.check_cancelled(cancelled)?;
+            let name = part_paths[0]
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            progress("parsing", &format!("Importing: {}", name), Some(idx + 1), Some(total));
+
+            let result = self
+                .prepare_parts(&part_paths, &(|_, _, _, _| {}), cancelled)
+                .and_then(|mut prepared| {
+                    prepared.media_dedup = Some(media_dedup.clone());
+                    self.import_unlinked(prepared, None, &(|_, _, _, _| {}), cancelled)
+                });
+            match result {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    return Err(e);
+                }
+                result => {
+                    let path = part_paths[0].to_string_lossy().to_string();
+                    results.push((path, result.map_err(|e| e.to_string())));
+                }
+            }
+        }
+
+        let imported: Vec<&str> = results
+            .iter()
+            .filter_map(|(_, result)| result.as_ref().ok())
+            .map(|entry| entry.id.as_str())
+            .collect();
+        self.link_imported_threads(&imported);
+
+        progress("done", "Import complete!", None, None);
+        Ok(results)
+    }
+
+    /// Link `import_id` with its parent channel (if it is a thread) or with its threads
+    /// (if it is a channel), recording the links on the parent import
+    pub fn link_threads(&self, import_id: &str) -> io::Result<()> {
+        let metadata = self.load_metadata()?;
+        let entry = metadata.imports
+            .iter()
+            .find(|e| e.id == import_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Import not found"))?;
+
+        if is_thread(entry) {
+            // The same channel may have been imported more than once
+            for parent in metadata.imports.iter().filter(|p| is_thread_parent(p, entry)) {
+                self.link_parent_threads(&metadata, parent)?;
+            }
+            Ok(())
+        } else {
+            self.link_parent_threads(&metadata, entry)
+        }
+    }
+
+    /// Link a batch of new imports with their threads or parent channels, reading each
+    /// parent channel once however many of its threads were imported
+    fn link_imported_threads(&self, import_ids: &[&str]) {
+        let metadata = match self.load_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                tracing::warn!("Failed to link threads: {}", e);
+                return;
+            }
+        };
+        let imported: Vec<&ImportEntry> = metadata.imports
+            .iter()
+            .filter(|e| import_ids.contains(&e.id.as_str()))
+            .collect();
+
+        let parents = metadata.imports.iter().filter(|parent| {
+            !is_thread(parent) &&
+                (import_ids.contains(&parent.id.as_str()) ||
+                    imported.iter().any(|thread| is_thread_parent(parent, thread)))
+        });
+        for parent in parents {
+            if let Err(e) = self.link_parent_threads(&metadata, parent) {
+                tracing::warn!("Failed to link threads of {}: {}", parent.id, e);
+            }
+        }
+    }
+
+    /// Recompute the thread links stored on a parent channel import
+    fn link_parent_threads(&self, metadata: &ImportMetadata, parent: &ImportEntry) -> io::Result<()> {
+        let threads: Vec<&ImportEntry> = metadata.imports
+            .iter()
+            .filter(|t| is_thread_parent(parent, t))
+            .collect();
+
+        if threads.is_empty() && self.load_import_data(&parent.id)?.thread_links.is_empty() {
+            return Ok(());
+        }
+
+        // Read outside the update: the parent's messages can take a while to load
+        let messages = if threads.is_empty() {
+            Vec::new()
+        } else {
+            message_storage::open_store(PathBuf::from(&parent.import_path)).load_all_messages()?
+        };
+        let thread_links: Vec<ThreadLink> = threads
+            .iter()
+            .map(|thread| ThreadLink {
+                thread_import_id: thread.id.clone(),
+                thread_channel_id: thread.channel_id.clone(),
+                thread_name: thread.channel_name.clone(),
+                starter_message_id: find_thread_starter(&messages, thread),
+            })
+            .collect();
+        info!("Linked {} threads to {}", thread_links.len(), parent.id);
+
+        self.update_import_data(&parent.id, |import_data| {
+            import_data.thread_links = thread_links;
+            Ok(())
+        })
+    }
+
+    /// Threads of a channel import whose thread import still exists
+    pub fn get_channel_threads(&self, import_id: &str) -> io::Result<Vec<ThreadLink>> {
+        let metadata = self.load_metadata()?;
+        let import_data = self.load_import_data(import_id)?;
+        Ok(
+            import_data.thread_links
+                .into_iter()
+                .filter(|link| metadata.imports.iter().any(|e| e.id == link.thread_import_id))
+                .collect()
+        )
+    }
+
+    /// Fill in channel details for imports made before they were recorded, then link
+    /// every channel with its threads
+    pub fn relink_all_threads(&self) -> io::Result<()> {
+        let mut metadata = self.load_metadata()?;
+        if metadata.imports.iter().any(|e| e.channel_id.is_empty()) {
+            metadata = self.update_metadata(|metadata| {
+                for entry in metadata.imports.iter_mut().filter(|e| e.channel_id.is_empty()) {
+                    let export_path = self.get_export_path(entry);
+                    let Ok(contents) = fs::read_to_string(&export_path) else {
+                        continue;
+                    };
+                    if let Ok(channel) = export_parser::read_channel(&contents) {
+                        entry.channel_id = channel.id;
+                        entry.channel_type = channel.channel_type;
+                        entry.category_id = channel.category_id;
+                        entry.category = channel.category;
+                    }
+                }
+                Ok(metadata.clone())
+            })?;
+        }
+
+        for entry in metadata.imports.iter().filter(|e| !is_thread(e)) {
+            if let Err(e) = self.link_parent_threads(&metadata, entry) {
+                tracing::warn!("Failed to link threads of import {}: {}", entry.id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Group imports of the same guild by channel category for a server tree view.
+    /// DMs (guild "0") are left out.
+    pub fn get_guild_tree(&self) -> io::Result<Vec<GuildTreeNode>> {
+        let metadata = self.load_metadata()?;
+        let mut guilds: Vec<GuildTreeNode> = Vec::new();
+
+        for entry in metadata.imports.iter().filter(|e| e.guild_id != "0") {
+            let guild_index = match guilds.iter().position(|g| g.guild_id == entry.guild_id) {
+                Some(index) => index,
+                None => {
+                    guilds.push(GuildTreeNode {
+                        guild_id: entry.guild_id.clone(),
+                        guild_name: entry.guild_name.clone(),
+                        categories: Vec::new(),
+                    });
+                    guilds.len() - 1
+                }
+            };
+            let guild = &mut guilds[guild_index];
+
+            let category_index = match
+                guild.categories.iter().position(|c| c.category_id == entry.category_id)
+            {
+                Some(index) => index,
+                None => {
+                    guild.categories.push(CategoryTreeNode {
+                        category_id: entry.category_id.clone(),
+                        category: entry.category.clone(),
+                        import_ids: Vec::new(),
+                    });
+                    guild.categories.len() - 1
+                }
+            };
+            guild.categories[category_index].import_ids.push(entry.id.clone());
+        }
+
+        Ok(guilds)
+    }
+
+    /// Import every channel of a Discord "Request My Data" package (folder or zip)
+    #[instrument(skip_all, fields(package_path))]
+    pub fn import_discord_package_with_callbacks<F>(
+        &self,
+        package_path: &str,
+        progress: F,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<PackageImportResult>
+        where F: Fn(&str, &str, Option<usize>, Option<usize>)
+    {
+        let package_path = PathBuf::from(package_path);
+        if !package_path.exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Data package not found"));
+        }
+
+        progress("parsing", "Reading data package...", None, None);
+        let package = Arc::new(PackageReader::open(&package_path)?);
+        let channels = discord_package::read_discord_package(&package)?;
+
+        self.import_converted(channels, ImportSource::DiscordDataPackage, package, &progress, cancelled)
+    }
+
+    /// Import a Telegram Desktop export (folder or zip holding result.json), one import per chat
+    #[instrument(skip_all, fields(export_path))]
+    pub fn import_telegram_with_callbacks<F>(
+        &self,
+        export_path: &str,
+        progress: F,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<PackageImportResult>
+        where F: Fn(&str, &str, Option<usize>, Option<usize>)
+    {
+        let mut export_path = PathBuf::from(export_path);
+        if !export_path.exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Telegram export not found"));
+        }
+        // Accept result.json itself as well as its folder
+        if export_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) {
+            export_path = export_path.parent().map(Path::to_path_buf).unwrap_or(export_path);
+        }
+
+        progress("parsing", "Reading Telegram export...", None, None);
+        let package = Arc::new(PackageReader::open(&export_path)?);
+        let chats = telegram::read_telegram_export(&package)?;
+
+        self.import_converted(chats, ImportSource::Telegram, package, &progress, cancelled)
+    }
+
+    /// Import a WhatsApp "Export chat" zip, its extracted folder or the chat .txt inside it
+    #[instrument(skip_all, fields(export_path))]
+    pub fn import_whatsapp_with_callbacks<F>(
+        &self,
+        export_path: &str,
+        progress: F,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<PackageImportResult>
+        where F: Fn(&str, &str, Option<usize>, Option<usize>)
+    {
+        let mut export_path = PathBuf::from(export_path);
+        if !export_path.exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "WhatsApp export not found"));
+        }
+        let name_hint = export_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if export_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("txt")) {
+            export_path = export_path.parent().map(Path::to_path_buf).unwrap_or(export_path);
+        }
+
+        progress("parsing", "Reading WhatsApp chat...", None, None);
+        let package = Arc::new(PackageReader::open(&export_path)?);
+        let chat = whatsapp::read_whatsapp_export(&package, &name_hint)?;
+
+        self.import_converted(vec![chat], ImportSource::WhatsApp, package, &progress, cancelled)
+    }
+
+    /// Import a Slack workspace export (zip or folder), one import per channel
+    #[instrument(skip_all, fields(export_path))]
+    pub fn import_slack_with_callbacks<F>(
+        &self,
+        export_path: &str,
+        progress: F,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<PackageImportResult>
+        where F: Fn(&str, &str, Option<usize>, Option<usize>)
+    {
+        let export_path = PathBuf::from(export_path);
+        if !export_path.exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Slack export not found"));
+        }
+        // "Acme Slack export Jan 1 2020 - Jan 1 2024.zip" -> "Acme"
+        let stem = export_path
+            .file_stem()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let workspace_name = stem.split(" Slack export").next().unwrap_or(&stem).to_string();
+
+        progress("parsing", "Reading Slack export...", None, None);
+        let package = Arc::new(PackageReader::open(&export_path)?);
+        let channels = slack::read_slack_export(&package, &workspace_name)?;
+
+        self.import_converted(channels, ImportSource::Slack, package, &progress, cancelled)
+    }
+
+    /// Import conversations converted from another format, one import each. A channel
+    /// that fails doesn't stop the rest, and one already in the library is skipped.
+    pub fn import_converted(
+        &self,
+        exports: Vec<ConvertedExport>,
+        source: ImportSource,
+        package: Arc<PackageReader>,
+        progress: &ImportProgress,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<PackageImportResult> {
+        let total = exports.len();
+        let mut existing_hashes: HashSet<String> = self
+            .load_metadata()?
+            .imports.into_iter()
+            .filter(|e| e.hash_algorithm == HashAlgorithm::Sha256)
+            .map(|e| e.file_hash)
+            .collect();
+
+        let mut result = PackageImportResult::default();
+        for (index, converted) in exports.into_iter().enumerate() {
+            self.check_cancelled(cancelled)?;
+            let conversation_name = converted.export.channel.name.clone();
+            progress("parsing", &format!("Importing: {}", conversation_name), Some(index + 1), Some(total));
+
+            if !existing_hashes.insert(converted.file_hash.clone()) {
+                result.skipped.push(conversation_name);
+                continue;
+            }
+
+            let media = if converted.media_files.is_empty() {
+                MediaSource::None
+            } else {
+                MediaSource::Files { package: package.clone(), files: converted.media_files }
+            };
+
+            let imported = self.import_unlinked(
+                PreparedImport {
+                    export: converted.export,
+                    file_hash: converted.file_hash,
+                    source,
+                    original_json: None,
+                    media,
+                    media_dedup: None,
+                },
+                None,
+                &(|_, _, _, _| {}),
+                cancelled
+            );
+            match imported {
+                Ok(entry) => result.imported.push(entry),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    return Err(e);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to import {}: {}", conversation_name, e);
+                    result.failed.push(FailedImport { conversation_name, error: e.to_string() });
+                }
+            }
+        }
+
+        let imported: Vec<&str> = result.imported
+            .iter()
+            .map(|entry| entry.id.as_str())
+            .collect();
+        self.link_imported_threads(&imported);
+
+        progress("done", "Import complete!", None, None);
+        Ok(result)
+    }
+
+    /// Run the shared import pipeline (storage, search index, members, metadata)
+    /// for an export produced by any of the supported importers
+    pub fn import_prepared(
+        &self,
+        prepared: PreparedImport,
+        alias: Option<String>,
+        progress: &ImportProgress,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<ImportEntry> {
+        let import_entry = self.import_unlinked(prepared, alias, progress, cancelled)?;
+        if let Err(e) = self.link_threads(&import_entry.id) {
+            tracing::warn!("Failed to link threads: {}", e);
+        }
+        progress("done", "Import complete!", None, None);
+        Ok(import_entry)
+    }
+
+    /// The import pipeline without thread linking, which reads the whole parent channel:
+    /// batches link once at the end instead (see `link_imported_threads`)
+    #[instrument(skip_all, fields(source = ?prepared.source))]
+    fn import_unlinked(
+        &self,
+        prepared: PreparedImport,
+        alias: Option<String>,
+        progress: &ImportProgress,
+        cancelled: &std::sync::atomic::AtomicBool
+    ) -> io::Result<ImportEntry> {
+        let PreparedImport {
+            export: export_data,
+            file_hash,
+            source,
+            original_json,
+            media,
+            media_dedup,
+        } = prepared;
+
         let final_alias = alias.unwrap_or_else(|| {
             if export_data.guild.id == "0" {
                 format!("{}", export_data.channel.name)
@@ -150,66 +908,162 @@ impl SapperCore {
             }
         });
 
+        // Importing the same export again picks up an interrupted attempt where it stopped
+        let import_id = match self.find_interrupted_import(&file_hash) {
+            Some(journal) => {
+                info!("Resuming interrupted import {}", journal.import_id);
+                journal.import_id
+            }
+            None => Uuid::new_v4().to_string(),
+        };
+
+        // A folder without a journal or metadata entry looks orphaned to garbage collection
         let import_dir = self.sapper_dir.join("imports").join(&import_id);
-        fs::create_dir_all(&import_dir)?;
+        self.with_metadata_locked(|_| {
+            fs::create_dir_all(&import_dir)?;
+            clear_partial_import(&import_dir)?;
+            self.write_import_journal(&import_dir, &ImportJournal {
+                import_id: import_id.clone(),
+                file_hash: file_hash.clone(),
+                alias: final_alias.clone(),
+                channel_name: export_data.channel.name.clone(),
+                started_at: chrono::Utc::now().to_rfc3339(),
+                copied_files: 0,
+            })
+        })?;
 
         progress("copying", "Copying export file...", None, None);
         let dest_json = import_dir.join("export.json");
-        fs::copy(&json_path_buf, &dest_json)?;
+        match &original_json {
+            // Keep the original file byte-for-byte when there is one
+            Some(OriginalJson::File(json_path)) => {
+                fs::copy(json_path, &dest_json)?;
+            }
+            Some(OriginalJson::Contents(contents)) => {
+                fs::write(&dest_json, contents)?;
+            }
+            None => {
+                let contents = serde_json
+                    ::to_string(&export_data)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                fs::write(&dest_json, contents)?;
+            }
+        }
 
-        self.check_cancelled_with_cleanup(cancelled, &import_dir)?;
+        self.check_cancelled(cancelled)?;
 
-        let source_dir = json_path_buf.parent().unwrap();
         let attachments_dir = import_dir.join("attachments");
 
-        if source_dir.exists() {
-            self.copy_attachments_with_progress(
-                source_dir,
-                &attachments_dir,
-                &(|filename, current, total| {
-                    progress(
-                        "copying",
-                        &format!("Copying: {}", filename),
-                        Some(current),
-                        Some(total)
-                    );
-                }),
-                cancelled
-            )?;
-        }
+        let copy_progress = |filename: &str, current: usize, total: usize| {
+            progress("copying", &format!("Copying: {}", filename), Some(current), Some(total));
+        };
+        let assets = export_parser::referenced_assets(&export_data);
+        let mut journal = CopyJournal::open(&import_dir)?;
+        let asset_report = match &media {
+            MediaSource::None => AssetCopyReport { missing: assets, ..Default::default() },
+            MediaSource::Directories(source_dirs) => {
+                let (jobs, mut report) = resolve_directory_assets(source_dirs, &assets)?;
+                self.run_copy_jobs(
+                    &jobs,
+                    None,
+                    &attachments_dir,
+                    media_dedup.as_ref(),
+                    &mut journal,
+                    &mut report,
+                    &copy_progress,
+                    cancelled
+                )?;
+                report
+            }
+            MediaSource::Archive { package, root } => {
+                let (jobs, mut report) = resolve_archive_assets(package, root, &assets);
+                self.run_copy_jobs(
+                    &jobs,
+                    Some(package),
+                    &attachments_dir,
+                    None,
+                    &mut journal,
+                    &mut report,
+                    &copy_progress,
+                    cancelled
+                )?;
+                report
+            }
+            MediaSource::Files { package, files } => {
+                // The converters take these paths from the export, which may point anywhere
+                let (files, outside): (Vec<&String>, Vec<&String>) = files
+                    .iter()
+                    .partition(|file| export_parser::is_contained_path(file));
+                let jobs: Vec<CopyJob> = files
+                    .into_iter()
+                    .map(|file| CopyJob { source: CopySource::Package(file.clone()), relative: file.clone() })
+                    .collect();
+                let mut report = AssetCopyReport {
+                    missing: outside.into_iter().cloned().collect(),
+                    ..Default::default()
+                };
+                self.run_copy_jobs(
+                    &jobs,
+                    Some(package),
+                    &attachments_dir,
+                    None,
+                    &mut journal,
+                    &mut report,
+                    &copy_progress,
+                    cancelled
+                )?;
+                report
+            }
+        };
+        info!(
+            "Copied {} assets ({} bytes), {} already in place, {} failed, {} missing, {} unreferenced files left behind",
+            asset_report.copied,
+            asset_report.copied_bytes,
+            asset_report.reused,
+            asset_report.failed.len(),
+            asset_report.missing.len(),
+            asset_report.unreferenced.len()
+        );
 
-        self.check_cancelled_with_cleanup(cancelled, &import_dir)?;
+        self.check_cancelled(cancelled)?;
+
+        progress("copying", "Deduplicating media...", None, None);
+        let adopted = BlobStore::new(&self.sapper_dir).adopt_import(&import_dir)?;
+        if adopted.files_deduplicated > 0 {
+            info!(
+                "{} media files ({} bytes) were already stored by other imports",
+                adopted.files_deduplicated,
+                adopted.bytes_saved
+            );
+        }
 
         progress("processing", "Extracting avatar...", None, None);
-        let avatar_path = self.extract_avatar(
-            &export_data,
-            &import_id,
-            &json_path_buf,
-            &import_dir
-        )?;
+        let avatar_path = self.extract_avatar(&export_data, &import_dir)?;
 
         progress("indexing", "Processing messages...", None, None);
         let stored_messages = self.convert_messages_to_stored(&export_data, &import_dir)?;
 
-        self.check_cancelled_with_cleanup(cancelled, &import_dir)?;
+        self.check_cancelled(cancelled)?;
 
         progress("indexing", "Creating message storage...", None, None);
-        let storage = MessageStorage::new(import_dir.clone());
-        storage.create_chunks(stored_messages.clone())?;
+        let backend = self.load_config().map(|c| c.storage_backend).unwrap_or_default();
+        message_storage::create_store(import_dir.clone(), backend).write_messages(stored_messages.clone())?;
 
-        self.check_cancelled_with_cleanup(cancelled, &import_dir)?;
+        self.check_cancelled(cancelled)?;
 
         progress("indexing", "Building search index...", None, None);
+        let members = self.extract_members(&export_data)?;
+        let mut stored_messages = stored_messages;
+        merge_authors(&mut stored_messages, &self.load_metadata()?.member_merges, &members.members);
         let index_dir = import_dir.join("search_index");
         fs::create_dir_all(&index_dir)?;
         let search_index = MessageSearchIndex::create(&index_dir)?;
         search_index.index_messages(&stored_messages)?;
 
-        self.check_cancelled_with_cleanup(cancelled, &import_dir)?;
+        self.check_cancelled(cancelled)?;
 
         progress("finalizing", "Saving member data...", None, None);
-        let members = self.extract_members(&export_data)?;
-        self.save_members(&import_dir, &members)?;
+        self.save_members(&import_dir, &members, Some(asset_report))?;
 
         progress("finalizing", "Updating metadata...", None, None);
         let import_entry = ImportEntry {
@@ -224,13 +1078,19 @@ impl SapperCore {
             message_count: export_data.messages.len(),
             avatar_path,
             description: String::new(),
+            source,
+            channel_id: export_data.channel.id.clone(),
+            category_id: export_data.channel.category_id.clone(),
+            category: export_data.channel.category.clone(),
+            channel_type: export_data.channel.channel_type.clone(),
+            hash_algorithm: HashAlgorithm::Sha256,
         };
 
-        let mut metadata = self.load_metadata()?;
-        metadata.imports.push(import_entry.clone());
-        self.save_metadata(&metadata)?;
-
-        progress("done", "Import complete!", None, None);
+        self.update_metadata(|metadata| {
+            metadata.imports.push(import_entry.clone());
+            Ok(())
+        })?;
+        journal.finish()?;
 
         Ok(import_entry)
     }
@@ -243,19 +1103,6 @@ impl SapperCore {
         }
     }
 
-    fn check_cancelled_with_cleanup(
-        &self,
-        cancelled: &std::sync::atomic::AtomicBool,
-        import_dir: &Path
-    ) -> io::Result<()> {
-        if cancelled.load(std::sync::atomic::Ordering::Relaxed) {
-            fs::remove_dir_all(import_dir).ok();
-            Err(io::Error::new(io::ErrorKind::Interrupted, "Import cancelled"))
-        } else {
-            Ok(())
-        }
-    }
-
     #[instrument(skip_all, fields(message_count = export.messages.len()))]
     fn convert_messages_to_stored(
         &self,
@@ -263,6 +1110,8 @@ impl SapperCore {
         import_dir: &Path
     ) -> io::Result<Vec<StoredMessage>> {
         let mut stored_messages = Vec::new();
+        let blob_store = BlobStore::new(&self.sapper_dir);
+        let manifest = BlobStore::load_manifest(import_dir)?;
 
         for (idx, msg) in export.messages.iter().enumerate() {
             let timestamp = chrono::DateTime
@@ -273,11 +1122,7 @@ impl SapperCore {
             // Collect only attachments into media_refs (stickers are handled separately)
             let media_refs: Vec<String> = msg.attachments
                 .iter()
-                .map(|att| {
-                    // Use att.url which contains the actual filename with suffix
-                    let attachment_path = import_dir.join("attachments").join(&att.url);
-                    attachment_path.to_string_lossy().to_string()
-                })
+                .map(|att| media_ref(&blob_store, &manifest, import_dir, &att.url))
                 .collect();
 
             stored_messages.push(StoredMessage {
@@ -303,16 +1148,18 @@ impl SapperCore {
             });
         }
 
-        // Second pass: populate referenced messages
+        // Second pass: populate referenced messages, looked up by original_id
+        // (stitched partitioned exports make linear scans too slow)
+        let positions: HashMap<String, usize> = stored_messages
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (m.original_id.clone(), i))
+            .collect();
         for i in 0..stored_messages.len() {
             if let Some(ref reference) = stored_messages[i].reference {
-                // Find the referenced message by original_id
-                if
-                    let Some(referenced) = stored_messages
-                        .iter()
-                        .find(|m| m.original_id == reference.message_id)
-                {
-                    stored_messages[i].referenced_message = Some(Box::new(referenced.clone()));
+                if let Some(&target) = positions.get(&reference.message_id) {
+                    let referenced = stored_messages[target].clone();
+                    stored_messages[i].referenced_message = Some(Box::new(referenced));
                 }
             }
         }
@@ -321,73 +1168,335 @@ impl SapperCore {
     }
 
     #[instrument(skip_all)]
-    fn parse_export(&self, path: &Path) -> io::Result<DiscordExport> {
+    pub(crate) fn parse_export(&self, path: &Path) -> io::Result<DiscordExport> {
         let contents = fs::read_to_string(path)?;
-        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        Ok(export_parser::parse_export_lenient(&contents)?.export)
+    }
+
+    /// Exports inside a zip, as "archive.zip::entry.json" paths the import commands accept
+    pub fn list_archive_exports(&self, archive_path: &str) -> io::Result<Vec<String>> {
+        let package = PackageReader::open(Path::new(archive_path))?;
+        Ok(
+            archive_exports(&package)
+                .into_iter()
+                .map(|entry| format!("{}{}{}", archive_path, package_reader::ARCHIVE_ENTRY_SEPARATOR, entry))
+                .collect()
+        )
+    }
+
+    /// Dry run of the export parser, reporting what an import would skip or ignore
+    pub fn validate_export(&self, json_path: &str) -> io::Result<ExportValidationReport> {
+        let contents = ExportFile::open(json_path)?.read_contents()?;
+        Ok(export_parser::parse_export_lenient(&contents)?.report)
     }
 
     fn calculate_file_hash(&self, path: &Path) -> io::Result<String> {
-        let mut file = fs::File::open(path)?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)?;
+        hash_reader(fs::File::open(path)?)
+    }
+
+    /// Re-hash imports whose hash predates SHA-256 from the export.json they keep,
+    /// so hashes can be compared across installs. Returns how many were updated.
+    pub fn migrate_file_hashes(&self) -> io::Result<usize> {
+        let metadata = self.load_metadata()?;
+        if !metadata.imports.iter().any(|e| e.hash_algorithm == HashAlgorithm::Legacy) {
+            return Ok(0);
+        }
+
+        let migrated = self.update_metadata(|metadata| {
+            let mut migrated = 0;
+            for entry in metadata.imports
+                .iter_mut()
+                .filter(|e| e.hash_algorithm == HashAlgorithm::Legacy) {
+                match self.calculate_file_hash(&self.get_export_path(entry)) {
+                    Ok(hash) => {
+                        entry.file_hash = hash;
+                        entry.hash_algorithm = HashAlgorithm::Sha256;
+                        migrated += 1;
+                    }
+                    Err(e) => tracing::warn!("Could not re-hash import {}: {}", entry.id, e),
+                }
+            }
+            Ok(migrated)
+        })?;
+        info!("Re-hashed {} imports with SHA-256", migrated);
+        Ok(migrated)
+    }
+
+    /// Replay chunk edits cut short by a crash and clear temp files left by interrupted
+    /// writes. Returns how many edits were replayed.
+    pub fn recover_interrupted_writes(&self) -> io::Result<usize> {
+        let mut temp_files = 0;
+        for dir in [self.sapper_dir.clone(), self.sapper_dir.join("blobs"), self.sapper_dir.join("cache/emojis")] {
+            temp_files += durable::remove_stale_temp_files(&dir)?;
+        }
+
+        let mut replayed = 0;
+        for entry in fs::read_dir(self.sapper_dir.join("imports"))?.filter_map(|e| e.ok()) {
+            let import_dir = entry.path();
+            if !import_dir.is_dir() {
+                continue;
+            }
+            match MessageStorage::new(import_dir.clone()).recover() {
+                Ok(true) => {
+                    replayed += 1;
+                }
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Could not recover writes in {:?}: {}", entry.file_name(), e),
+            }
+            temp_files += durable::remove_stale_temp_files(&import_dir)?;
+            temp_files += durable::remove_stale_temp_files(&import_dir.join("chunks"))?;
+        }
+
+        if replayed > 0 || temp_files > 0 {
+            info!("Replayed {} interrupted edits, removed {} temp files", replayed, temp_files);
+        }
+        Ok(replayed)
+    }
 
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{ Hash, Hasher };
+    /// Rewrite imports stored with absolute paths (import folder, avatar, chunk files,
+    /// media) to paths relative to the library root. Returns how many were rewritten.
+    pub fn migrate_to_relative_paths(&self) -> io::Result<usize> {
+        let stored = self.load_stored_metadata()?;
+        let metadata_has_absolute = stored.imports
+            .iter()
+            .any(|e| Path::new(&e.import_path).is_absolute() || Path::new(&e.avatar_path).is_absolute());
 
-        let mut hasher = DefaultHasher::new();
-        contents.hash(&mut hasher);
-        let hash = hasher.finish();
+        let metadata = self.load_metadata()?;
+        let mut migrated = 0;
+        for entry in &metadata.imports {
+            match MessageStorage::new(PathBuf::from(&entry.import_path)).relativize_paths() {
+                Ok(true) => {
+                    migrated += 1;
+                }
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Could not relativize paths of import {}: {}", entry.id, e),
+            }
+        }
 
-        Ok(format!("{:x}", hash))
+        // Saving relativizes the paths
+        if metadata_has_absolute {
+            self.update_metadata(|_| Ok(()))?;
+        }
+        if migrated > 0 {
+            info!("Rewrote {} imports with library-relative paths", migrated);
+        }
+        Ok(migrated)
     }
 
-    #[allow(dead_code)]
-    fn copy_attachments(&self, source: &Path, dest: &Path) -> io::Result<()> {
-        let never_cancelled = std::sync::atomic::AtomicBool::new(false);
-        self.copy_attachments_with_progress(source, dest, &(|_, _, _| {}), &never_cancelled)
+    /// An import's hash as SHA-256, computed from its export.json for entries made before
+    /// the switch. Falls back to the stored hash when the export can't be read.
+    fn comparable_hash(&self, import_dir: &Path, entry: &ImportEntry) -> (String, HashAlgorithm) {
+        if entry.hash_algorithm == HashAlgorithm::Sha256 {
+            return (entry.file_hash.clone(), HashAlgorithm::Sha256);
+        }
+        match self.calculate_file_hash(&import_dir.join("export.json")) {
+            Ok(hash) => (hash, HashAlgorithm::Sha256),
+            Err(_) => (entry.file_hash.clone(), HashAlgorithm::Legacy),
+        }
     }
 
-    fn copy_attachments_with_progress(
+    /// Copy media files into an import with a bounded pool of workers. Files already at
+    /// the destination are kept, and a failed file is reported rather than failing the import.
+    /// Finished files are recorded in the journal so an interrupted import can skip them.
+    #[allow(clippy::too_many_arguments)]
+    fn run_copy_jobs(
         &self,
-        source: &Path,
+        jobs: &[CopyJob],
+        package: Option<&PackageReader>,
         dest: &Path,
+        dedup: Option<&MediaDedup>,
+        journal: &mut CopyJournal,
+        report: &mut AssetCopyReport,
         progress: &dyn Fn(&str, usize, usize),
         cancelled: &std::sync::atomic::AtomicBool
     ) -> io::Result<()> {
+        use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+
         fs::create_dir_all(dest)?;
 
-        let entries: Vec<_> = walkdir::WalkDir
-            ::new(source)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().is_file())
-            .collect();
+        let next_job = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let workers = std::thread
+            ::available_parallelism()
+            .map_or(1, |n| n.get())
+            .clamp(1, MAX_COPY_WORKERS)
+            .min(jobs.len().max(1));
+        let journaled = journal.done.clone();
+
+        std::thread::scope(|scope| {
+            let (tx, rx) = std::sync::mpsc::channel();
+            for _ in 0..workers {
+                let tx = tx.clone();
+                let (next_job, stop, journaled) = (&next_job, &stop, &journaled);
+                scope.spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let idx = next_job.fetch_add(1, Ordering::Relaxed);
+                        let Some(job) = jobs.get(idx) else {
+                            break;
+                        };
+                        let is_journaled = journaled.contains(&job.relative);
+                        let result = copy_job(job, package, dest, dedup, is_journaled);
+                        if tx.send((idx, result)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            // Progress, the journal and the report are only touched from this thread
+            for (done, (idx, result)) in rx.into_iter().enumerate() {
+                let job: &CopyJob = &jobs[idx];
+                progress(job.relative.rsplit(['/', '\\']).next().unwrap_or(&job.relative), done + 1, jobs.len());
+                match result {
+                    Ok(CopyOutcome::Copied(bytes)) => {
+                        report.copied += 1;
+                        report.copied_bytes += bytes;
+                        journal.record(&job.relative);
+                    }
+                    Ok(CopyOutcome::AlreadyInPlace) => {
+                        report.reused += 1;
+                        journal.record(&job.relative);
+                    }
+                    Err(e) => {
+                        tracing::warn!("Could not copy {}: {}", logger::sanitize_string(&job.relative), e);
+                        report.failed.push(job.relative.clone());
+                    }
+                }
+                if cancelled.load(Ordering::Relaxed) {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+        });
 
-        let total = entries.len();
+        report.failed.sort();
+        self.check_cancelled(cancelled)
+    }
+
+    /// Move the media of imports made before the blob store into it, pointing their
+    /// attachments at the shared copies
+    pub fn migrate_to_blob_store(
+        &self,
+        progress: &dyn Fn(&str, usize, usize)
+    ) -> io::Result<BlobMigrationReport> {
+        let metadata = self.load_metadata()?;
+        let blob_store = BlobStore::new(&self.sapper_dir);
+        let mut report = BlobMigrationReport::default();
 
-        for (idx, entry) in entries.iter().enumerate() {
-            if cancelled.load(std::sync::atomic::Ordering::Relaxed) {
-                return Err(io::Error::new(io::ErrorKind::Interrupted, "Import cancelled"));
+        for (idx, entry) in metadata.imports.iter().enumerate() {
+            progress(&entry.alias, idx + 1, metadata.imports.len());
+            let import_dir = PathBuf::from(&entry.import_path);
+            if import_dir.join(blob_store::MANIFEST_FILE).exists() {
+                continue;
             }
 
-            let path = entry.path();
-            if let Ok(relative) = path.strip_prefix(source) {
-                let dest_path = dest.join(relative);
-                if let Some(parent) = dest_path.parent() {
-                    fs::create_dir_all(parent)?;
-                }
+            let adopted = blob_store.adopt_import(&import_dir)?;
+            report.imports_migrated += 1;
+            report.files_deduplicated += adopted.files_deduplicated;
+            report.bytes_saved += adopted.bytes_saved;
 
-                let filename = path
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("unknown");
+            // Stored messages still point at the import's own attachments folder
+            self.relink_media(&import_dir)?;
+        }
+
+        info!(
+            "Blob store migration: {} imports, {} duplicate files, {} bytes saved",
+            report.imports_migrated,
+            report.files_deduplicated,
+            report.bytes_saved
+        );
+        Ok(report)
+    }
 
-                progress(filename, idx + 1, total);
-                fs::copy(path, &dest_path)?;
+    /// Point imported messages' media at the blob store or the attachments folder,
+    /// restoring whichever of a blob and its attachment link has gone missing
+    pub fn relink_media(&self, import_dir: &Path) -> io::Result<()> {
+        let blob_store = BlobStore::new(&self.sapper_dir);
+        let manifest = BlobStore::load_manifest(import_dir)?;
+        let attachments_dir = import_dir.join("attachments");
+
+        for (relative, blob) in &manifest.files {
+            let attachment = attachments_dir.join(relative);
+            let blob_path = blob_store.blob_path(blob);
+            let (from, to) = match (attachment.exists(), blob_path.exists()) {
+                (false, true) => (blob_path, attachment),
+                (true, false) => (attachment, blob_path),
+                _ => continue,
+            };
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if fs::hard_link(&from, &to).is_err() {
+                fs::copy(&from, &to)?;
             }
         }
 
-        Ok(())
+        message_storage::open_store(import_dir.to_path_buf()).update_messages(
+            &mut (|message| {
+                if !message.is_user_message {
+                    message.media_refs = message.attachments
+                        .iter()
+                        .map(|att| media_ref(&blob_store, &manifest, import_dir, &att.url))
+                        .collect();
+                }
+            })
+        )
+    }
+
+    /// Interrupted imports are import folders that still hold a journal
+    pub fn list_interrupted_imports(&self) -> io::Result<Vec<ImportJournal>> {
+        let imports_dir = self.sapper_dir.join("imports");
+        let mut interrupted = Vec::new();
+        for entry in fs::read_dir(&imports_dir)?.filter_map(|e| e.ok()) {
+            let import_dir = entry.path();
+            let Ok(contents) = fs::read_to_string(import_dir.join(IMPORT_JOURNAL_FILE)) else {
+                continue;
+            };
+            match serde_json::from_str::<ImportJournal>(&contents) {
+                Ok(mut journal) => {
+                    journal.copied_files = CopyJournal::open(&import_dir)
+                        .map(|j| j.done.len())
+                        .unwrap_or(0);
+                    interrupted.push(journal);
+                }
+                Err(e) => tracing::warn!("Unreadable import journal in {:?}: {}", import_dir, e),
+            }
+        }
+        interrupted.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+        Ok(interrupted)
+    }
+
+    fn find_interrupted_import(&self, file_hash: &str) -> Option<ImportJournal> {
+        let metadata = self.load_metadata().ok()?;
+        self.list_interrupted_imports()
+            .ok()?
+            .into_iter()
+            .filter(|j| !metadata.imports.iter().any(|e| e.id == j.import_id))
+            .find(|j| j.file_hash == file_hash)
+    }
+
+    /// Delete what an interrupted import left behind instead of resuming it
+    pub fn discard_interrupted_import(&self, import_id: &str) -> io::Result<()> {
+        let metadata = self.load_metadata()?;
+        if metadata.imports.iter().any(|e| e.id == import_id) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Import already completed"));
+        }
+
+        let import_dir = self.sapper_dir.join("imports").join(import_id);
+        if !import_dir.join(IMPORT_JOURNAL_FILE).exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Interrupted import not found"));
+        }
+        BlobStore::new(&self.sapper_dir).release_import(&import_dir)?;
+        fs::remove_dir_all(import_dir)
+    }
+
+    fn write_import_journal(&self, import_dir: &Path, journal: &ImportJournal) -> io::Result<()> {
+        durable::write_json_atomic(&import_dir.join(IMPORT_JOURNAL_FILE), journal)
+    }
+
+    /// What the import copied from the export's media folders, if it was recorded
+    pub fn get_asset_report(&self, import_id: &str) -> io::Result<Option<AssetCopyReport>> {
+        Ok(self.load_import_data(import_id)?.asset_report)
     }
 
     pub fn get_export_path(&self, import_entry: &ImportEntry) -> PathBuf {
@@ -405,66 +1514,56 @@ impl SapperCore {
         self.parse_export(&export_path)
     }
 
+    /// Move an import to the trash, where it can be restored until it's purged
     pub fn delete_import(&self, import_id: &str) -> io::Result<()> {
-        let mut metadata = self.load_metadata()?;
-
-        if let Some(index) = metadata.imports.iter().position(|e| e.id == import_id) {
-            let entry = metadata.imports.remove(index);
-
-            // Delete the import directory
-            let import_dir = PathBuf::from(&entry.import_path);
-            if import_dir.exists() {
-                fs::remove_dir_all(import_dir)?;
-            }
-
-            // Clean up cache for this import
-            let cache_dir = self.sapper_dir.join("cache/parsed").join(import_id);
-            if cache_dir.exists() {
-                fs::remove_dir_all(cache_dir)?;
-            }
+        let metadata = self.load_metadata()?;
+        let Some(entry) = metadata.imports.iter().find(|e| e.id == import_id) else {
+            return Ok(());
+        };
+        self.move_to_trash(entry)?;
 
-            self.save_metadata(&metadata)?;
+        // Clean up cache for this import
+        let cache_dir = self.sapper_dir.join("cache/parsed").join(import_id);
+        if cache_dir.exists() {
+            fs::remove_dir_all(cache_dir)?;
         }
 
         Ok(())
     }
 
     pub fn update_import_alias(&self, import_id: &str, new_alias: String, new_description: Option<String>) -> io::Result<()> {
-        let mut metadata = self.load_metadata()?;
-
-        if let Some(entry) = metadata.imports.iter_mut().find(|e| e.id == import_id) {
+        self.update_metadata(|metadata| {
+            let entry = metadata.imports
+                .iter_mut()
+                .find(|e| e.id == import_id)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Import not found"))?;
             entry.alias = new_alias;
             if let Some(desc) = new_description {
                 entry.description = desc;
             }
-            self.save_metadata(&metadata)?;
-        } else {
-            return Err(io::Error::new(io::ErrorKind::NotFound, "Import not found"));
-        }
-
-        Ok(())
+            Ok(())
+        })
     }
 
     pub fn reorder_imports(&self, ordered_ids: Vec<String>) -> io::Result<()> {
-        let mut config = self.load_config()?;
-        config.conversation_order = ordered_ids;
-        self.save_config(&config)?;
-        Ok(())
+        self.update_config(|config| {
+            config.conversation_order = ordered_ids;
+        })
     }
 
-    // Extract unique members from export messages
+    // Extract unique members from export messages, along with the timeline of
+    // nickname, avatar and color changes seen for each of them
     fn extract_members(&self, export: &DiscordExport) -> io::Result<MemberStorage> {
-        use std::collections::HashMap;
-
         let mut members_map: HashMap<String, Member> = HashMap::new();
+        let mut history_map: HashMap<String, MemberHistory> = HashMap::new();
 
         for msg in &export.messages {
             let author_id = msg.author.id.clone();
 
-            // Only add if we haven't seen this author before
+            // The first appearance defines the member; later ones only extend the history
             if !members_map.contains_key(&author_id) {
                 members_map.insert(author_id.clone(), Member {
-                    id: author_id,
+                    id: author_id.clone(),
                     name: msg.author.name.clone(),
                     discriminator: msg.author.discriminator.clone(),
                     nickname: msg.author.nickname.clone(),
@@ -473,19 +1572,38 @@ impl SapperCore {
                     is_bot: msg.author.is_bot,
                     roles: msg.author.roles.clone(),
                     hidden: false,
+                    merged_ids: Vec::new(),
                 });
             }
+
+            let history = history_map.entry(author_id.clone()).or_insert_with(|| MemberHistory {
+                member_id: author_id,
+                nicknames: Vec::new(),
+                avatars: Vec::new(),
+                colors: Vec::new(),
+            });
+            record_history(&mut history.nicknames, Some(&msg.author.nickname), &msg.timestamp);
+            record_history(&mut history.avatars, Some(&msg.author.avatar_url), &msg.timestamp);
+            record_history(&mut history.colors, msg.author.color.as_deref(), &msg.timestamp);
         }
 
         let mut members: Vec<Member> = members_map.into_values().collect();
         // Sort by ID for consistency
         members.sort_by(|a, b| a.id.cmp(&b.id));
 
-        Ok(MemberStorage { members })
+        let mut member_history: Vec<MemberHistory> = history_map.into_values().collect();
+        member_history.sort_by(|a, b| a.member_id.cmp(&b.member_id));
+
+        Ok(MemberStorage { members, member_history })
     }
 
     // Save members to import directory (uses new ImportData format)
-    fn save_members(&self, import_dir: &Path, members: &MemberStorage) -> io::Result<()> {
+    fn save_members(
+        &self,
+        import_dir: &Path,
+        members: &MemberStorage,
+        asset_report: Option<AssetCopyReport>
+    ) -> io::Result<()> {
         // Create new ImportData structure
         let import_data = ImportData {
             import_version: crate::versioning::CURRENT_VERSION.to_string(),
@@ -494,35 +1612,34 @@ impl SapperCore {
             last_updated: chrono::Utc::now().to_rfc3339(),
             mutable_conversation: false,
             mutable_member_id: None,
+            member_history: members.member_history.clone(),
+            member_merges: HashMap::new(),
+            thread_links: Vec::new(),
+            asset_report,
         };
 
         // Save as import_data.json
-        let import_data_path = import_dir.join("import_data.json");
-        let temp_path = import_dir.join("import_data.json.tmp");
-
-        let contents = serde_json
-            ::to_string_pretty(&import_data)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-
-        fs::write(&temp_path, contents)?;
-        fs::rename(temp_path, import_data_path)?;
-
-        Ok(())
+        durable::write_json_atomic(&import_dir.join("import_data.json"), &import_data)
     }
 
     // Save ImportData directly (for updates)
     fn save_import_data(&self, import_dir: &Path, import_data: &ImportData) -> io::Result<()> {
-        let import_data_path = import_dir.join("import_data.json");
-        let temp_path = import_dir.join("import_data.json.tmp");
-
-        let contents = serde_json
-            ::to_string_pretty(import_data)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-
-        fs::write(&temp_path, contents)?;
-        fs::rename(temp_path, import_data_path)?;
+        durable::write_json_atomic(&import_dir.join("import_data.json"), import_data)
+    }
 
-        Ok(())
+    /// Load an import's data, apply `update` and save it, holding off other updates so
+    /// concurrent edits (say, renaming a member while threads are linked) aren't lost
+    pub fn update_import_data<T>(
+        &self,
+        import_id: &str,
+        update: impl FnOnce(&mut ImportData) -> io::Result<T>
+    ) -> io::Result<T> {
+        let _guard = IMPORT_DATA_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let import_dir = self.get_import_dir(import_id)?;
+        let mut import_data = self.load_import_data(import_id)?;
+        let result = update(&mut import_data)?;
+        self.save_import_data(&import_dir, &import_data)?;
+        Ok(result)
     }
 
     // Load members from import directory (supports both old and new formats)
@@ -545,6 +1662,7 @@ impl SapperCore {
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
             return Ok(MemberStorage {
                 members: import_data.members,
+                member_history: import_data.member_history,
             });
         }
 
@@ -556,14 +1674,14 @@ impl SapperCore {
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
             // Migrate to new format
-            self.save_members(&import_dir, &members)?;
+            self.save_members(&import_dir, &members, None)?;
             return Ok(members);
         }
 
         // If neither exists, create from export
         let export = self.load_export(import_id)?;
         let members = self.extract_members(&export)?;
-        self.save_members(&import_dir, &members)?;
+        self.save_members(&import_dir, &members, None)?;
         Ok(members)
     }
 
@@ -588,6 +1706,10 @@ impl SapperCore {
                 last_updated: chrono::Utc::now().to_rfc3339(),
                 mutable_conversation: false,
                 mutable_member_id: None,
+                member_history: members.member_history,
+                member_merges: HashMap::new(),
+                thread_links: Vec::new(),
+                asset_report: None,
             });
         }
 
@@ -604,34 +1726,314 @@ impl SapperCore {
         avatar_url: Option<String>,
         hidden: Option<bool>
     ) -> io::Result<()> {
+        self.update_import_data(import_id, |import_data| {
+            let member = import_data.members
+                .iter_mut()
+                .find(|m| m.id == member_id)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Member not found"))?;
+            if let Some(nick) = nickname {
+                member.nickname = nick;
+            }
+            if let Some(avatar) = avatar_url {
+                member.avatar_url = avatar;
+            }
+            if let Some(h) = hidden {
+                member.hidden = h;
+            }
+
+            // Update last_updated timestamp
+            import_data.last_updated = chrono::Utc::now().to_rfc3339();
+            Ok(())
+        })
+    }
+
+    /// Let the user post into an import as `member_id`, or stop letting them
+    pub fn set_mutable_conversation(&self, import_id: &str, enabled: bool, member_id: Option<String>) -> io::Result<()> {
+        self.update_import_data(import_id, |import_data| {
+            import_data.mutable_conversation = enabled;
+            import_data.mutable_member_id = if enabled { member_id } else { None };
+            import_data.last_updated = chrono::Utc::now().to_rfc3339();
+            Ok(())
+        })
+    }
+
+    /// Resolve an import's directory from its metadata entry
+    pub fn get_import_dir(&self, import_id: &str) -> io::Result<PathBuf> {
         let metadata = self.load_metadata()?;
         let import_entry = metadata.imports
             .iter()
             .find(|e| e.id == import_id)
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Import not found"))?;
 
-        let import_dir = PathBuf::from(&import_entry.import_path);
-        let mut import_data = self.load_import_data(import_id)?;
+        Ok(PathBuf::from(&import_entry.import_path))
+    }
 
-        if let Some(member) = import_data.members.iter_mut().find(|m| m.id == member_id) {
-            if let Some(nick) = nickname {
-                member.nickname = nick;
+    /// Effective member merges for an import: library-wide merges overlaid with the import's own
+    pub fn load_member_merges(&self, import_id: &str) -> io::Result<HashMap<String, String>> {
+        let mut merges = self.load_metadata()?.member_merges;
+        let import_data = self.load_import_data(import_id)?;
+        merges.extend(import_data.member_merges);
+        Ok(merges)
+    }
+
+    /// Merge `member_ids` into `canonical_id`, either within one import or library-wide
+    /// when no import is given. Merges only affect how data is read, so they can be undone.
+    pub fn merge_members(
+        &self,
+        import_id: Option<&str>,
+        canonical_id: &str,
+        member_ids: &[String]
+    ) -> io::Result<()> {
+        let merged_ids: Vec<&String> = member_ids
+            .iter()
+            .filter(|id| id.as_str() != canonical_id)
+            .collect();
+
+        if merged_ids.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "No members to merge"));
+        }
+
+        let mut affected: Vec<String> = merged_ids.iter().map(|id| id.to_string()).collect();
+        affected.push(canonical_id.to_string());
+
+        match import_id {
+            Some(import_id) => {
+                self.update_import_data(import_id, |import_data| {
+                    for id in merged_ids {
+                        import_data.member_merges.insert(id.clone(), canonical_id.to_string());
+                    }
+                    // The canonical member can't itself be merged away
+                    import_data.member_merges.remove(canonical_id);
+                    import_data.last_updated = chrono::Utc::now().to_rfc3339();
+                    Ok(())
+                })?;
+                self.rebuild_search_index(import_id)
             }
-            if let Some(avatar) = avatar_url {
-                member.avatar_url = avatar;
+            None => {
+                let metadata = self.update_metadata(|metadata| {
+                    for id in merged_ids {
+                        metadata.member_merges.insert(id.clone(), canonical_id.to_string());
+                    }
+                    metadata.member_merges.remove(canonical_id);
+                    Ok(metadata.clone())
+                })?;
+
+                self.rebuild_search_indexes_for_members(&metadata, &affected)
             }
-            if let Some(h) = hidden {
-                member.hidden = h;
+        }
+    }
+
+    /// Undo a merge for a single member, either within one import or library-wide
+    pub fn unmerge_member(&self, import_id: Option<&str>, member_id: &str) -> io::Result<()> {
+        match import_id {
+            Some(import_id) => {
+                self.update_import_data(import_id, |import_data| {
+                    if import_data.member_merges.remove(member_id).is_none() {
+                        return Err(io::Error::new(io::ErrorKind::NotFound, "Member is not merged"));
+                    }
+                    import_data.last_updated = chrono::Utc::now().to_rfc3339();
+                    Ok(())
+                })?;
+                self.rebuild_search_index(import_id)
+            }
+            None => {
+                let (metadata, canonical_id) = self.update_metadata(|metadata| {
+                    let canonical_id = metadata.member_merges
+                        .remove(member_id)
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Member is not merged"))?;
+                    Ok((metadata.clone(), canonical_id))
+                })?;
+
+                self.rebuild_search_indexes_for_members(
+                    &metadata,
+                    &[member_id.to_string(), canonical_id]
+                )
+            }
+        }
+    }
+
+    /// Load members with merges applied: merged members are folded into their canonical member,
+    /// or take on the canonical ID when that member isn't part of this import
+    pub fn load_merged_members(&self, import_id: &str) -> io::Result<MemberStorage> {
+        let mut storage = self.load_members(import_id)?;
+        let merges = self.load_member_merges(import_id)?;
+
+        if merges.is_empty() {
+            return Ok(storage);
+        }
+
+        let mut members: Vec<Member> = Vec::with_capacity(storage.members.len());
+        let mut folded: Vec<Member> = Vec::new();
+
+        for member in storage.members {
+            if resolve_member_id(&merges, &member.id) == member.id {
+                members.push(member);
+            } else {
+                folded.push(member);
+            }
+        }
+
+        for mut member in folded {
+            let canonical_id = resolve_member_id(&merges, &member.id);
+            if let Some(canonical) = members.iter_mut().find(|m| m.id == canonical_id) {
+                canonical.merged_ids.push(member.id);
+            } else {
+                member.merged_ids.push(std::mem::replace(&mut member.id, canonical_id));
+                members.push(member);
+            }
+        }
+
+        members.sort_by(|a, b| a.id.cmp(&b.id));
+        storage.members = members;
+        Ok(storage)
+    }
+
+    /// Rewrite message authors so merged members appear as their canonical member
+    pub fn apply_member_merges(
+        &self,
+        import_id: &str,
+        messages: &mut [StoredMessage]
+    ) -> io::Result<()> {
+        let merges = self.load_member_merges(import_id)?;
+        if merges.is_empty() {
+            return Ok(());
+        }
+
+        let members = self.load_members(import_id)?;
+        merge_authors(messages, &merges, &members.members);
+        Ok(())
+    }
+
+    /// Per-member message statistics, counting merged members as one person
+    pub fn get_member_stats(&self, import_id: &str) -> io::Result<Vec<MemberStats>> {
+        let messages = message_storage::open_store(self.get_import_dir(import_id)?).load_all_messages()?;
+        let members = self.load_merged_members(import_id)?;
+        let merges = self.load_member_merges(import_id)?;
+
+        let mut stats: HashMap<String, MemberStats> = HashMap::new();
+
+        for msg in messages {
+            let member_id = resolve_member_id(&merges, &msg.author.id);
+            let entry = stats.entry(member_id.clone()).or_insert_with(|| MemberStats {
+                nickname: members.members
+                    .iter()
+                    .find(|m| m.id == member_id)
+                    .map(|m| m.nickname.clone())
+                    .unwrap_or_else(|| msg.author.nickname.clone()),
+                member_id,
+                message_count: 0,
+                first_timestamp: msg.timestamp,
+                last_timestamp: msg.timestamp,
+            });
+
+            entry.message_count += 1;
+            entry.first_timestamp = entry.first_timestamp.min(msg.timestamp);
+            entry.last_timestamp = entry.last_timestamp.max(msg.timestamp);
+        }
+
+        let mut stats: Vec<MemberStats> = stats.into_values().collect();
+        // Most active first
+        stats.sort_by(|a, b| b.message_count.cmp(&a.message_count));
+        Ok(stats)
+    }
+
+    /// Move an import's messages to another storage backend. Returns the number moved.
+    pub fn convert_import_storage(&self, import_id: &str, backend: StorageBackend) -> io::Result<usize> {
+        let import_dir = self.get_import_dir(import_id)?;
+        let moved = message_storage::convert_store(&import_dir, backend)?;
+        info!("Moved {} messages of import {} to {:?} storage", moved, import_id, backend);
+        Ok(moved)
+    }
+
+    /// Recreate an import's search index from its stored messages
+    pub fn rebuild_search_index(&self, import_id: &str) -> io::Result<()> {
+        let import_dir = self.get_import_dir(import_id)?;
+        let mut messages = message_storage::open_store(import_dir.clone()).load_all_messages()?;
+        self.apply_member_merges(import_id, &mut messages)?;
+        self.write_search_index(&import_dir, &messages)
+    }
+
+    /// Rewrite an import's stored messages without the gaps edits left behind. With
+    /// `renumber`, message IDs become sequential too; the saved reading position, thread
+    /// links and search index refer to messages by ID, so they are moved to the new IDs.
+    pub fn compact_import(&self, import_id: &str, renumber: bool) -> io::Result<CompactionReport> {
+        let import_dir = self.get_import_dir(import_id)?;
+        let compaction = message_storage::open_store(import_dir.clone()).compact(renumber)?;
+
+        if !compaction.renumbered.is_empty() {
+            self.update_config(|config| {
+                if let Some(position) = config.conversation_positions.get_mut(import_id) {
+                    if let Some(&new_id) = compaction.renumbered.get(&position.message_id) {
+                        position.message_id = new_id;
+                    }
+                }
+            })?;
+            if let Err(e) = self.link_threads(import_id) {
+                tracing::warn!("Failed to link threads: {}", e);
+            }
+            if import_dir.join("search_index").exists() {
+                self.rebuild_search_index(import_id)?;
             }
+        }
+
+        info!(
+            "Compacted import {}: {} chunks into {}, {} messages renumbered",
+            import_id,
+            compaction.chunks_before,
+            compaction.chunks_after,
+            compaction.renumbered.len()
+        );
+        Ok(CompactionReport {
+            import_id: import_id.to_string(),
+            chunks_before: compaction.chunks_before,
+            chunks_after: compaction.chunks_after,
+            renumbered_messages: compaction.renumbered.len(),
+        })
+    }
+
+    /// Compact every import, skipping those that fail. `keep_ids_of` is compacted
+    /// without renumbering even if `renumber` is set.
+    pub fn compact_all_imports(&self, renumber: bool, keep_ids_of: Option<&str>) -> io::Result<Vec<CompactionReport>> {
+        let metadata = self.load_metadata()?;
+        let mut reports = Vec::new();
+        for entry in &metadata.imports {
+            let renumber = renumber && keep_ids_of != Some(entry.id.as_str());
+            match self.compact_import(&entry.id, renumber) {
+                Ok(report) => reports.push(report),
+                Err(e) => tracing::warn!("Failed to compact import {}: {}", entry.id, e),
+            }
+        }
+        Ok(reports)
+    }
 
-            // Update last_updated timestamp
-            import_data.last_updated = chrono::Utc::now().to_rfc3339();
+    /// Rebuild the search index of every import that contains one of the given members,
+    /// skipping those that fail
+    fn rebuild_search_indexes_for_members(
+        &self,
+        metadata: &ImportMetadata,
+        member_ids: &[String]
+    ) -> io::Result<()> {
+        for entry in &metadata.imports {
+            let Ok(members) = self.load_members(&entry.id) else {
+                continue;
+            };
+            if members.members.iter().any(|m| member_ids.contains(&m.id)) {
+                if let Err(e) = self.rebuild_search_index(&entry.id) {
+                    tracing::warn!("Failed to rebuild search index of import {}: {}", entry.id, e);
+                }
+            }
+        }
+        Ok(())
+    }
 
-            self.save_import_data(&import_dir, &import_data)?;
-            Ok(())
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotFound, "Member not found"))
+    fn write_search_index(&self, import_dir: &Path, messages: &[StoredMessage]) -> io::Result<()> {
+        let index_dir = import_dir.join("search_index");
+        if index_dir.exists() {
+            fs::remove_dir_all(&index_dir)?;
         }
+        fs::create_dir_all(&index_dir)?;
+        let search_index = MessageSearchIndex::create(&index_dir)?;
+        search_index.index_messages(messages)
     }
 
     /// Copy an avatar file into the import's attachments folder
@@ -683,11 +2085,10 @@ impl SapperCore {
     fn extract_avatar(
         &self,
         export: &DiscordExport,
-        _import_id: &str,
-        json_path: &Path,
         import_dir: &Path
     ) -> io::Result<String> {
-        let source_dir = json_path.parent().unwrap_or_else(|| Path::new("."));
+        // Media has already been copied next to the export
+        let source_dir = import_dir.join("attachments");
 
         // Check if it's a DM (guild.id == "0")
         let avatar_source = if export.guild.id == "0" {
@@ -772,14 +2173,15 @@ impl SapperCore {
             .filter(|entry| import_ids.contains(&entry.id))
             .collect();
 
-        // Create filtered metadata
+        // Create filtered metadata (library-wide member merges travel with it)
         let filtered_metadata = ImportMetadata {
             imports: selected_imports.clone(),
+            member_merges: metadata.member_merges,
         };
 
         // Save filtered metadata
         let metadata_json = serde_json
-            ::to_string_pretty(&filtered_metadata)
+            ::to_string_pretty(&self.relativize_metadata(&filtered_metadata))
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         fs::write(dest.join("metadata.json"), metadata_json)?;
 
@@ -821,29 +2223,42 @@ impl SapperCore {
             ::from_str(&source_metadata_contents)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
-        // Load current metadata
+        // Work on a copy; only what the backup adds is applied at the end, since copying
+        // can take a while and the metadata may change meanwhile
         let mut current_metadata = self.load_metadata()?;
+        let existing_count = current_metadata.imports.len();
 
-        // Merge imports (avoid duplicates by file_hash)
-        let existing_hashes: std::collections::HashSet<String> = current_metadata.imports
+        // Keep library-wide member merges from the backup unless they conflict
+        for (member_id, canonical_id) in &source_metadata.member_merges {
+            current_metadata.member_merges
+                .entry(member_id.clone())
+                .or_insert_with(|| canonical_id.clone());
+        }
+
+        // Merge imports (avoid duplicates by file_hash, comparing SHA-256 where possible)
+        let existing_hashes: HashSet<(String, HashAlgorithm)> = current_metadata.imports
             .iter()
-            .map(|e| e.file_hash.clone())
+            .map(|e| self.comparable_hash(Path::new(&e.import_path), e))
             .collect();
 
-        for source_entry in source_metadata.imports {
+        for mut source_entry in source_metadata.imports {
+            let source_import_dir = source.join("imports").join(&source_entry.id);
+            let (file_hash, hash_algorithm) = self.comparable_hash(&source_import_dir, &source_entry);
             // Skip if already exists (same file hash)
-            if existing_hashes.contains(&source_entry.file_hash) {
+            if existing_hashes.contains(&(file_hash.clone(), hash_algorithm)) {
                 continue;
             }
+            source_entry.file_hash = file_hash;
+            source_entry.hash_algorithm = hash_algorithm;
 
             // Generate new ID to avoid conflicts
             let new_id = Uuid::new_v4().to_string();
-            let source_import_dir = source.join("imports").join(&source_entry.id);
             let dest_import_dir = self.sapper_dir.join("imports").join(&new_id);
 
             // Copy import directory
             if source_import_dir.exists() {
                 self.copy_directory(&source_import_dir, &dest_import_dir)?;
+                self.adopt_restored_media(&dest_import_dir)?;
             }
 
             // Rebuild chunks and search index so paths use the new import directory
@@ -851,8 +2266,9 @@ impl SapperCore {
             if export_path.exists() {
                 if let Ok(export_data) = self.parse_export(&export_path) {
                     if let Ok(stored_messages) = self.convert_messages_to_stored(&export_data, &dest_import_dir) {
-                        let storage = MessageStorage::new(dest_import_dir.clone());
-                        let _ = storage.create_chunks(stored_messages.clone());
+                        let _ = message_storage
+                            ::open_store(dest_import_dir.clone())
+                            .write_messages(stored_messages.clone());
 
                         let index_dir = dest_import_dir.join("search_index");
                         if index_dir.exists() {
@@ -876,8 +2292,8 @@ impl SapperCore {
             current_metadata.imports.push(new_entry);
         }
 
-        // Save merged metadata
-        self.save_metadata(&current_metadata)?;
+        let added = current_metadata.imports.split_off(existing_count);
+        self.add_restored_imports(&source_metadata.member_merges, added)?;
 
         // Import config if it exists (don't overwrite, just merge)
         let source_config_path = source.join("config.json");
@@ -911,20 +2327,29 @@ impl SapperCore {
             ::from_str(&source_metadata_contents)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
-        // Load current metadata
+        // Work on a copy; only what the backup adds is applied at the end, since copying
+        // can take a while and the metadata may change meanwhile
         let mut current_metadata = self.load_metadata()?;
+        let existing_count = current_metadata.imports.len();
+
+        // Keep library-wide member merges from the backup unless they conflict
+        for (member_id, canonical_id) in &source_metadata.member_merges {
+            current_metadata.member_merges
+                .entry(member_id.clone())
+                .or_insert_with(|| canonical_id.clone());
+        }
 
-        // Merge imports (avoid duplicates by file_hash)
-        let existing_hashes: std::collections::HashSet<String> = current_metadata.imports
+        // Merge imports (avoid duplicates by file_hash, comparing SHA-256 where possible)
+        let existing_hashes: HashSet<(String, HashAlgorithm)> = current_metadata.imports
             .iter()
-            .map(|e| e.file_hash.clone())
+            .map(|e| self.comparable_hash(Path::new(&e.import_path), e))
             .collect();
 
         let mut successful = Vec::new();
         let mut failed = Vec::new();
         let total_count = source_metadata.imports.len();
 
-        for source_entry in source_metadata.imports {
+        for mut source_entry in source_metadata.imports {
             let conversation_name = format!(
                 "{} in {}",
                 source_entry.channel_name,
@@ -932,7 +2357,11 @@ impl SapperCore {
             );
 
             // Skip if already exists (same file hash)
-            if existing_hashes.contains(&source_entry.file_hash) {
+            let source_import_dir = source.join("imports").join(&source_entry.id);
+            let (file_hash, hash_algorithm) = self.comparable_hash(&source_import_dir, &source_entry);
+            source_entry.file_hash = file_hash;
+            source_entry.hash_algorithm = hash_algorithm;
+            if existing_hashes.contains(&(source_entry.file_hash.clone(), hash_algorithm)) {
                 failed.push(FailedImport {
                     conversation_name,
                     error: "Conversation already imported (duplicate)".to_string(),
@@ -957,8 +2386,8 @@ impl SapperCore {
             }
         }
 
-        // Save merged metadata
-        self.save_metadata(&current_metadata)?;
+        let added = current_metadata.imports.split_off(existing_count);
+        self.add_restored_imports(&source_metadata.member_merges, added)?;
 
         Ok(ImportBackupResult {
             successful: successful.clone(),
@@ -969,6 +2398,20 @@ impl SapperCore {
         })
     }
 
+    /// Save imports restored from a backup along with the backup's library-wide member
+    /// merges, keeping existing merges where they conflict
+    fn add_restored_imports(&self, merges: &HashMap<String, String>, added: Vec<ImportEntry>) -> io::Result<()> {
+        self.update_metadata(|metadata| {
+            for (member_id, canonical_id) in merges {
+                metadata.member_merges
+                    .entry(member_id.clone())
+                    .or_insert_with(|| canonical_id.clone());
+            }
+            metadata.imports.extend(added);
+            Ok(())
+        })
+    }
+
     /// Import a single conversation from backup
     fn import_single_conversation(
         &self,
@@ -984,30 +2427,33 @@ impl SapperCore {
         // Copy import directory
         if source_import_dir.exists() {
             self.copy_directory(&source_import_dir, &dest_import_dir)?;
+            self.adopt_restored_media(&dest_import_dir)?;
         } else {
             return Err(
                 io::Error::new(io::ErrorKind::NotFound, "Import directory not found in backup")
             );
         }
 
-        // Rebuild chunks and search index so paths use the new import directory
-        // (copied chunks contain absolute paths baked with the old UUID)
+        // Rebuild chunks and search index so media paths use the new import directory
+        // (copied chunks point at the old import ID and the backup's blob store)
         let export_path = dest_import_dir.join("export.json");
         if export_path.exists() {
             let export_data = self.parse_export(&export_path)?;
-            let stored_messages = self.convert_messages_to_stored(&export_data, &dest_import_dir)?;
-
-            let storage = MessageStorage::new(dest_import_dir.clone());
-            storage.create_chunks(stored_messages.clone())?;
-
-            // Rebuild search index
-            let index_dir = dest_import_dir.join("search_index");
-            if index_dir.exists() {
-                fs::remove_dir_all(&index_dir)?;
+            let mut stored_messages = self.convert_messages_to_stored(&export_data, &dest_import_dir)?;
+
+            message_storage::open_store(dest_import_dir.clone()).write_messages(stored_messages.clone())?;
+
+            // Rebuild search index, resolving senders through the restored member merges
+            let mut merges = current_metadata.member_merges.clone();
+            let import_data: Option<ImportData> = fs
+                ::read_to_string(dest_import_dir.join("import_data.json"))
+                .ok()
+                .and_then(|contents| serde_json::from_str(&contents).ok());
+            if let Some(import_data) = import_data {
+                merges.extend(import_data.member_merges);
+                merge_authors(&mut stored_messages, &merges, &import_data.members);
             }
-            fs::create_dir_all(&index_dir)?;
-            let search_index = MessageSearchIndex::create(&index_dir)?;
-            search_index.index_messages(&stored_messages)?;
+            self.write_search_index(&dest_import_dir, &stored_messages)?;
         }
 
         // Create new entry with new ID and path
@@ -1021,6 +2467,17 @@ impl SapperCore {
         Ok(())
     }
 
+    /// Restored media arrive as plain copies, and the backup's blob manifest refers to
+    /// another library's store; add them to this library's store instead
+    fn adopt_restored_media(&self, import_dir: &Path) -> io::Result<()> {
+        let manifest_path = import_dir.join(blob_store::MANIFEST_FILE);
+        if manifest_path.exists() {
+            fs::remove_file(manifest_path)?;
+        }
+        BlobStore::new(&self.sapper_dir).adopt_import(import_dir)?;
+        Ok(())
+    }
+
     /// Helper function to recursively copy a directory
     fn copy_directory(&self, src: &Path, dest: &Path) -> io::Result<()> {
         fs::create_dir_all(dest)?;
@@ -1047,40 +2504,13 @@ impl SapperCore {
         Ok(())
     }
 
-    /// Check for missing assets in a JSON export
+    /// Media a JSON export references that isn't beside it, checked the way an import
+    /// looks for it
     pub fn check_missing_assets(&self, json_path: &str) -> io::Result<Vec<String>> {
-        let json_path_buf = PathBuf::from(json_path);
-        let export_data = self.parse_export(&json_path_buf)?;
-        let source_dir = json_path_buf.parent().unwrap_or_else(|| Path::new("."));
-
-        let mut missing_assets = Vec::new();
-
-        // Check guild icon
-        if let Some(icon_url) = &export_data.guild.icon_url {
-            let icon_path = source_dir.join(icon_url);
-            if !icon_path.exists() {
-                missing_assets.push(icon_url.clone());
-            }
-        }
-
-        // Check message attachments and author avatars
-        for message in &export_data.messages {
-            // Check attachments
-            for attachment in &message.attachments {
-                let attachment_path = source_dir.join(&attachment.url);
-                if !attachment_path.exists() {
-                    missing_assets.push(attachment.url.clone());
-                }
-            }
-
-            // Check author avatar
-            let avatar_path = source_dir.join(&message.author.avatar_url);
-            if !avatar_path.exists() && !missing_assets.contains(&message.author.avatar_url) {
-                missing_assets.push(message.author.avatar_url.clone());
-            }
-        }
-
-        Ok(missing_assets)
+        let export_file = ExportFile::open(json_path)?;
+        let export_data = export_parser::parse_export_lenient(&export_file.read_contents()?)?.export;
+        let (_, report) = export_file.resolve_assets(&export_data)?;
+        Ok(report.missing)
     }
 
     /// Copy assets from source folder to destination folder (where JSON is located)
@@ -1168,7 +2598,7 @@ impl SapperCore {
         let export_data = self.parse_export(&export_path)?;
 
         // Extract user-sent messages from existing chunks before rebuilding
-        let storage = MessageStorage::new(import_dir.clone());
+        let storage = message_storage::open_store(import_dir.clone());
         let user_messages = storage.extract_user_messages()?;
 
         // Recreate message storage from export
@@ -1182,18 +2612,8 @@ impl SapperCore {
             stored_messages.push(user_msg);
         }
 
-        // Recreate chunks
-        storage.create_chunks(stored_messages.clone())?;
-
-        // Recreate search index
-        let index_dir = import_dir.join("search_index");
-        // Remove old index
-        if index_dir.exists() {
-            fs::remove_dir_all(&index_dir)?;
-        }
-        fs::create_dir_all(&index_dir)?;
-        let search_index = MessageSearchIndex::create(&index_dir)?;
-        search_index.index_messages(&stored_messages)?;
+        // Recreate message storage
+        storage.write_messages(stored_messages.clone())?;
 
         // Extract and update members
         let members = self.extract_members(&export_data)?;
@@ -1201,6 +2621,14 @@ impl SapperCore {
         // Load existing import_data to preserve user customizations
         let existing_import_data = self.load_import_data(import_id).ok();
 
+        // Recreate search index, with senders resolved through any member merges
+        let mut merges = metadata.member_merges.clone();
+        if let Some(existing) = &existing_import_data {
+            merges.extend(existing.member_merges.clone());
+        }
+        merge_authors(&mut stored_messages, &merges, &members.members);
+        self.write_search_index(&import_dir, &stored_messages)?;
+
         // Create updated import_data
         let mut new_import_data = ImportData {
             import_version: crate::versioning::CURRENT_VERSION.to_string(),
@@ -1217,6 +2645,13 @@ impl SapperCore {
             mutable_member_id: existing_import_data
                 .as_ref()
                 .and_then(|d| d.mutable_member_id.clone()),
+            member_history: members.member_history.clone(),
+            member_merges: existing_import_data
+                .as_ref()
+                .map(|d| d.member_merges.clone())
+                .unwrap_or_default(),
+            thread_links: Vec::new(),
+            asset_report: existing_import_data.as_ref().and_then(|d| d.asset_report.clone()),
         };
 
         // Preserve user customizations (nicknames, avatars) if they exist
@@ -1237,6 +2672,11 @@ impl SapperCore {
         // Save updated import_data
         self.save_import_data(&import_dir, &new_import_data)?;
 
+        // Message IDs may have shifted, so thread starters are looked up again
+        if let Err(e) = self.link_threads(import_id) {
+            tracing::warn!("Failed to link threads: {}", e);
+        }
+
         Ok(())
     }
 
@@ -1257,35 +2697,19 @@ impl SapperCore {
 
     /// Get a preview of a conversation export without importing it
     pub fn get_import_preview(&self, json_path: &str) -> io::Result<crate::models::ImportPreview> {
-        let json_path_buf = PathBuf::from(json_path);
-
-        if !json_path_buf.exists() {
-            return Err(io::Error::new(io::ErrorKind::NotFound, "JSON file not found"));
-        }
-
-        let export_data = self.parse_export(&json_path_buf)?;
-        let json_size = fs::metadata(&json_path_buf)?.len();
-
-        let source_dir = json_path_buf.parent().unwrap_or_else(|| Path::new("."));
-        let mut attachments_size = 0u64;
-
-        for entry in walkdir::WalkDir
-            ::new(source_dir)
-            .into_iter()
-            .filter_map(|e| e.ok()) {
-            if entry.path().is_file() && entry.path() != json_path_buf {
-                attachments_size += entry
-                    .metadata()
-                    .map(|m| m.len())
-                    .unwrap_or(0);
-            }
-        }
+        let export_file = ExportFile::open(json_path)?;
+        let contents = export_file.read_contents()?;
+        let export_data = export_parser::parse_export_lenient(&contents)?.export;
+        let json_size = contents.len() as u64;
+
+        // Only what an import would copy, not everything else in the export's folder
+        let (jobs, _) = export_file.resolve_assets(&export_data)?;
+        let attachments_size = jobs
+            .iter()
+            .map(|job| export_file.job_size(job))
+            .sum();
 
-        let file_name = json_path_buf
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown")
-            .to_string();
+        let file_name = export_file.file_name();
 
         Ok(crate::models::ImportPreview {
             file_name,
@@ -1298,3 +2722,817 @@ impl SapperCore {
         })
     }
 }
+
+/// Written when an import starts and removed when it completes
+pub const IMPORT_JOURNAL_FILE: &str = "import_journal.json";
+/// One line per media file an import finished copying
+const COPY_JOURNAL_FILE: &str = "copy_journal.log";
+const MAX_COPY_WORKERS: usize = 8;
+
+/// A media file to copy into an import, to `relative` under its attachments folder
+struct CopyJob {
+    source: CopySource,
+    relative: String,
+}
+
+enum CopySource {
+    Disk(PathBuf),
+    /// Entry of the package passed along with the jobs
+    Package(String),
+}
+
+enum CopyOutcome {
+    Copied(u64),
+    AlreadyInPlace,
+}
+
+/// Append-only record of the files an import has copied
+struct CopyJournal {
+    import_dir: PathBuf,
+    file: fs::File,
+    done: HashSet<String>,
+}
+
+impl CopyJournal {
+    fn open(import_dir: &Path) -> io::Result<Self> {
+        let path = import_dir.join(COPY_JOURNAL_FILE);
+        let done = fs
+            ::read_to_string(&path)
+            .map(|contents| {
+                contents
+                    .lines()
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { import_dir: import_dir.to_path_buf(), file, done })
+    }
+
+    fn record(&mut self, relative: &str) {
+        use std::io::Write;
+
+        if self.done.insert(relative.to_string()) {
+            // Losing a line only means the file is checked again on resume
+            if let Err(e) = writeln!(self.file, "{}", relative) {
+                tracing::warn!("Could not update copy journal: {}", e);
+            }
+        }
+    }
+
+    /// The import completed; nothing is left to resume
+    fn finish(self) -> io::Result<()> {
+        fs::remove_file(self.import_dir.join(COPY_JOURNAL_FILE)).ok();
+        fs::remove_file(self.import_dir.join(IMPORT_JOURNAL_FILE))
+    }
+}
+
+/// Drop everything but copied media from an import folder before (re)starting it,
+/// so a resumed import rebuilds its storage and search index from scratch
+fn clear_partial_import(import_dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(import_dir)?.filter_map(|e| e.ok()) {
+        let name = entry.file_name();
+        if name == "attachments" || name == COPY_JOURNAL_FILE || name == blob_store::MANIFEST_FILE {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Find the assets an export references in the folders it was exported to. Nothing
+/// else is copied: exports often sit next to unrelated files. Paths leading out of the
+/// export folder are reported as missing rather than followed.
+fn resolve_directory_assets(
+    source_dirs: &[PathBuf],
+    assets: &[String]
+) -> io::Result<(Vec<CopyJob>, AssetCopyReport)> {
+    let mut report = AssetCopyReport::default();
+    let mut jobs: Vec<CopyJob> = Vec::new();
+    let mut media_dirs: HashSet<PathBuf> = HashSet::new();
+    let mut sources: HashSet<PathBuf> = HashSet::new();
+
+    for asset in assets {
+        let found = source_dirs.iter().find_map(|source_dir| {
+            export_parser
+                ::asset_path_candidates(asset)
+                .into_iter()
+                .filter(|relative| export_parser::is_contained_path(relative))
+                .find(|relative| source_dir.join(relative).is_file())
+                .map(|relative| (source_dir.join(&relative), relative))
+        });
+        let Some((source_path, relative)) = found else {
+            report.missing.push(asset.clone());
+            continue;
+        };
+
+        if let Some(parent) = source_path.parent() {
+            media_dirs.insert(parent.to_path_buf());
+        }
+        // Parts of a partitioned export share their media
+        if sources.insert(source_path.clone()) {
+            jobs.push(CopyJob { source: CopySource::Disk(source_path), relative });
+        }
+    }
+
+    // Report what was left in the media folders; the folder holding the JSON itself
+    // may be Downloads or similar, so its other files aren't listed
+    for media_dir in media_dirs.iter().filter(|dir| !source_dirs.contains(dir)) {
+        for entry in fs::read_dir(media_dir)?.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if !path.is_file() || sources.contains(&path) {
+                continue;
+            }
+            let relative = source_dirs
+                .iter()
+                .find_map(|dir| path.strip_prefix(dir).ok())
+                .unwrap_or(&path);
+            report.unreferenced.push(relative.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    report.unreferenced.sort();
+
+    Ok((jobs, report))
+}
+
+/// Zipped counterpart of `resolve_directory_assets`, with asset URLs relative to `root`
+fn resolve_archive_assets(
+    package: &PackageReader,
+    root: &str,
+    assets: &[String]
+) -> (Vec<CopyJob>, AssetCopyReport) {
+    let entries: HashSet<String> = package.file_names().into_iter().collect();
+    let mut report = AssetCopyReport::default();
+    let mut jobs: Vec<CopyJob> = Vec::new();
+    let mut referenced: HashSet<String> = HashSet::new();
+    let mut media_dirs: HashSet<String> = HashSet::new();
+
+    for asset in assets {
+        let found = export_parser
+            ::asset_path_candidates(asset)
+            .into_iter()
+            .filter(|relative| export_parser::is_contained_path(relative))
+            .find(|relative| entries.contains(&format!("{}{}", root, relative)));
+        let Some(relative) = found else {
+            report.missing.push(asset.clone());
+            continue;
+        };
+
+        let entry = format!("{}{}", root, relative);
+        media_dirs.insert(archive_root(&entry));
+        if referenced.insert(entry.clone()) {
+            jobs.push(CopyJob { source: CopySource::Package(entry), relative });
+        }
+    }
+
+    report.unreferenced = entries
+        .iter()
+        .filter(|entry| !referenced.contains(*entry))
+        .filter(|entry| {
+            let dir = archive_root(entry);
+            dir != root && media_dirs.contains(&dir)
+        })
+        .map(|entry| entry[root.len()..].to_string())
+        .collect();
+    report.unreferenced.sort();
+
+    (jobs, report)
+}
+
+/// Copy one media file unless the destination already holds it. A journaled file
+/// with the right size is trusted; otherwise the contents are compared by hash.
+fn copy_job(
+    job: &CopyJob,
+    package: Option<&PackageReader>,
+    dest: &Path,
+    dedup: Option<&MediaDedup>,
+    journaled: bool
+) -> io::Result<CopyOutcome> {
+    let package = || package.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No package to copy from"));
+    let dest_path = dest.join(&job.relative);
+    let source_len = match &job.source {
+        CopySource::Disk(path) => fs::metadata(path)?.len(),
+        CopySource::Package(entry) => package()?.entry_size(entry)?,
+    };
+
+    if fs::metadata(&dest_path).is_ok_and(|meta| meta.len() == source_len) {
+        let matches =
+            journaled ||
+            (match &job.source {
+                CopySource::Disk(path) => hash_reader(fs::File::open(path)?)?,
+                CopySource::Package(entry) => package()?.hash(entry)?,
+            }) == hash_reader(fs::File::open(&dest_path)?)?;
+        if matches {
+            return Ok(CopyOutcome::AlreadyInPlace);
+        }
+    }
+
+    if let Some(parent) = dest_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match &job.source {
+        CopySource::Disk(path) =>
+            match dedup {
+                Some(dedup) => dedup.place(path, &dest_path)?,
+                None => {
+                    fs::copy(path, &dest_path)?;
+                }
+            }
+        CopySource::Package(entry) => {
+            package()?.copy_to(entry, &dest_path)?;
+        }
+    }
+    Ok(CopyOutcome::Copied(source_len))
+}
+
+/// Path of an attachment: its blob when the import's copy is in the blob store,
+/// otherwise the file in the import's attachments folder
+fn media_ref(blob_store: &BlobStore, manifest: &blob_store::BlobManifest, import_dir: &Path, url: &str) -> String {
+    // Media that was never downloaded stays a remote URL
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return url.to_string();
+    }
+
+    let blob = export_parser
+        ::asset_path_candidates(url)
+        .iter()
+        .find_map(|relative| manifest.files.get(relative));
+    let path = match blob {
+        Some(blob) => blob_store.blob_path(blob),
+        // Use the url which contains the actual filename with suffix
+        None => import_dir.join("attachments").join(url),
+    };
+    path.to_string_lossy().to_string()
+}
+
+/// DCE names thread channel types "GuildPublicThread", "GuildPrivateThread", "GuildNewsThread"
+fn is_thread(entry: &ImportEntry) -> bool {
+    entry.channel_type.contains("Thread")
+}
+
+/// DCE exports a thread with its parent channel as the category
+fn is_thread_parent(parent: &ImportEntry, thread: &ImportEntry) -> bool {
+    is_thread(thread) &&
+        !is_thread(parent) &&
+        !parent.channel_id.is_empty() &&
+        parent.guild_id == thread.guild_id &&
+        thread.category_id.as_deref() == Some(parent.channel_id.as_str())
+}
+
+/// The parent message a thread was started from: threads created from a message share
+/// its ID, otherwise the "ThreadCreated" system message pointing at the thread
+fn find_thread_starter(messages: &[StoredMessage], thread: &ImportEntry) -> Option<u64> {
+    messages
+        .iter()
+        .find(|m| m.original_id == thread.channel_id)
+        .or_else(|| {
+            messages.iter().find(|m| {
+                m.message_type == "ThreadCreated" &&
+                    m.reference
+                        .as_ref()
+                        .and_then(|r| r.channel_id.as_deref()) == Some(thread.channel_id.as_str())
+            })
+        })
+        .or_else(|| {
+            messages
+                .iter()
+                .find(|m| m.message_type == "ThreadCreated" && m.content == thread.channel_name)
+        })
+        .map(|m| m.id)
+}
+
+/// Extend a member's history timeline, starting a new entry whenever the value changes
+fn record_history(entries: &mut Vec<MemberHistoryEntry>, value: Option<&str>, timestamp: &str) {
+    match entries.last_mut() {
+        Some(last) if last.value.as_deref() == value => {
+            last.last_seen = timestamp.to_string();
+        }
+        _ => {
+            entries.push(MemberHistoryEntry {
+                value: value.map(|v| v.to_string()),
+                first_seen: timestamp.to_string(),
+                last_seen: timestamp.to_string(),
+            });
+        }
+    }
+}
+
+/// Follow member merges to the canonical member ID (guards against accidental cycles)
+pub fn resolve_member_id(merges: &HashMap<String, String>, member_id: &str) -> String {
+    let mut current = member_id;
+    for _ in 0..merges.len() {
+        match merges.get(current) {
+            Some(next) if next != current => {
+                current = next;
+            }
+            _ => break,
+        }
+    }
+    current.to_string()
+}
+
+/// Replace the author of each message (and of the message it replies to) with its canonical member
+pub fn merge_authors(
+    messages: &mut [StoredMessage],
+    merges: &HashMap<String, String>,
+    members: &[Member]
+) {
+    if merges.is_empty() {
+        return;
+    }
+
+    fn merge_author(author: &mut Author, merges: &HashMap<String, String>, members: &[Member]) {
+        let canonical_id = resolve_member_id(merges, &author.id);
+        if canonical_id == author.id {
+            return;
+        }
+
+        if let Some(member) = members.iter().find(|m| m.id == canonical_id) {
+            *author = Author {
+                id: member.id.clone(),
+                name: member.name.clone(),
+                discriminator: member.discriminator.clone(),
+                nickname: member.nickname.clone(),
+                color: member.color.clone(),
+                is_bot: member.is_bot,
+                roles: member.roles.clone(),
+                avatar_url: member.avatar_url.clone(),
+            };
+        } else {
+            // Canonical member lives in another import; keep the local display data
+            author.id = canonical_id;
+        }
+    }
+
+    for msg in messages.iter_mut() {
+        merge_author(&mut msg.author, merges, members);
+        if let Some(referenced) = msg.referenced_message.as_mut() {
+            merge_author(&mut referenced.author, merges, members);
+        }
+    }
+}
+
+/// Hex SHA-256 of everything `reader` yields, read in blocks
+pub fn hash_reader(mut reader: impl Read) -> io::Result<String> {
+    use sha2::{ Digest, Sha256 };
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hex SHA-256 of `contents`, the same value `hash_reader` gives for a file holding them
+pub fn hash_bytes(contents: &[u8]) -> String {
+    use sha2::{ Digest, Sha256 };
+
+    format!("{:x}", Sha256::digest(contents))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    /// Import a DCE export named `name`, written under `dir` with one message per media
+    /// file, each attaching it
+    pub(crate) fn import_test_export(core: &SapperCore, dir: &Path, name: &str, media: &[(&str, &[u8])]) -> ImportEntry {
+        let json_path = write_test_export(dir, name, media);
+        let cancelled = std::sync::atomic::AtomicBool::new(false);
+        core.import_conversation_with_callbacks(&json_path.to_string_lossy(), None, |_, _, _, _| {}, &cancelled).unwrap()
+    }
+
+    pub(crate) fn write_test_export(dir: &Path, name: &str, media: &[(&str, &[u8])]) -> PathBuf {
+        let export_dir = dir.join(name);
+        let mut messages = Vec::new();
+        for (i, (relative, contents)) in media.iter().enumerate() {
+            let path = export_dir.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            messages.push(
+                serde_json::json!({
+                    "id": (i + 1).to_string(),
+                    "timestamp": format!("2020-01-01T00:00:{:02}+00:00", i),
+                    "author": { "id": "4", "name": "a" },
+                    "attachments": [{ "id": (100 + i).to_string(), "url": relative }]
+                })
+            );
+        }
+        let export =
+            serde_json::json!({
+            "guild": { "id": "1", "name": "G" },
+            "channel": { "id": name, "name": name },
+            "messages": messages
+        });
+        let json_path = export_dir.join(format!("{}.json", name));
+        fs::create_dir_all(&export_dir).unwrap();
+        fs::write(&json_path, export.to_string()).unwrap();
+        json_path
+    }
+
+    /// Import a DCE export in guild "1" whose messages are given as JSON, filling in the
+    /// fields DCE always writes
+    pub(crate) fn import_test_messages(core: &SapperCore, dir: &Path, name: &str, messages: serde_json::Value) -> ImportEntry {
+        let messages: Vec<serde_json::Value> = messages
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| {
+                let mut full =
+                    serde_json::json!({
+                    "type": "Default", "timestampEdited": null, "callEndedTimestamp": null, "isPinned": false,
+                    "content": "", "attachments": [], "embeds": [], "stickers": [], "reactions": [],
+                    "mentions": [], "inlineEmojis": [], "reference": null
+                });
+                for (key, value) in message.as_object().unwrap() {
+                    full[key] = value.clone();
+                }
+                let author = full["author"].as_object_mut().unwrap();
+                let name = author["name"].clone();
+                author.entry("discriminator").or_insert_with(|| "0000".into());
+                author.entry("nickname").or_insert(name);
+                author.entry("color").or_insert(serde_json::Value::Null);
+                author.entry("isBot").or_insert(false.into());
+                author.entry("roles").or_insert_with(|| serde_json::json!([]));
+                author.entry("avatarUrl").or_insert_with(|| "".into());
+                full
+            })
+            .collect();
+        let export =
+            serde_json::json!({
+            "guild": { "id": "1", "name": "G", "iconUrl": null },
+            "channel": { "id": name, "type": "GuildTextChat", "categoryId": null, "category": null, "name": name, "topic": null },
+            "dateRange": { "after": null, "before": null },
+            "exportedAt": "2020-01-02T00:00:00+00:00",
+            "messages": messages
+        });
+        let json_path = dir.join(format!("{}.json", name));
+        fs::write(&json_path, export.to_string()).unwrap();
+        let cancelled = std::sync::atomic::AtomicBool::new(false);
+        core.import_conversation_with_callbacks(&json_path.to_string_lossy(), None, |_, _, _, _| {}, &cancelled).unwrap()
+    }
+
+    #[test]
+    fn test_assets_outside_export_are_missing() {
+        let dir = temp_dir("assets");
+        let export_dir = dir.join("export");
+        fs::create_dir_all(export_dir.join("media")).unwrap();
+        fs::write(dir.join("secret.txt"), b"not media").unwrap();
+        fs::write(export_dir.join("media/cat.png"), b"cat").unwrap();
+
+        let assets = vec![
+            "media/cat.png".to_string(),
+            "../secret.txt".to_string(),
+            "media/..%2F..%2Fsecret.txt".to_string(),
+            dir.join("secret.txt").to_string_lossy().to_string()
+        ];
+        let (jobs, report) = resolve_directory_assets(&[export_dir], &assets).unwrap();
+
+        let relatives: Vec<&str> = jobs
+            .iter()
+            .map(|job| job.relative.as_str())
+            .collect();
+        assert_eq!(relatives, vec!["media/cat.png"]);
+        assert_eq!(report.missing, assets[1..].to_vec());
+    }
+
+    #[test]
+    fn test_preview_counts_only_referenced_assets() {
+        let dir = temp_dir("preview");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let export_dir = dir.join("Downloads");
+        fs::create_dir_all(export_dir.join("media")).unwrap();
+        fs::write(export_dir.join("media/my cat.png"), b"cat").unwrap();
+        fs::write(export_dir.join("unrelated.iso"), vec![0u8; 4096]).unwrap();
+        let json_path = export_dir.join("general.json");
+        fs::write(
+            &json_path,
+            r#"{"guild":{"id":"1","name":"G"},"channel":{"id":"2","name":"general"},"messages":[
+                {"id":"3","timestamp":"2020-01-01T00:00:00+00:00","author":{"id":"4","name":"a"},
+                 "attachments":[{"id":"5","url":"media/my%20cat.png"},{"id":"6","url":"media/gone.png"}]}]}"#
+        ).unwrap();
+        let json_path = json_path.to_string_lossy().to_string();
+
+        assert_eq!(core.check_missing_assets(&json_path).unwrap(), vec!["media/gone.png"]);
+        assert_eq!(core.get_import_preview(&json_path).unwrap().attachments_size, 3);
+    }
+
+    #[test]
+    fn test_interrupted_import_resumes_copying() {
+        use std::sync::atomic::{ AtomicBool, Ordering };
+
+        let dir = temp_dir("resume");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let names: Vec<String> = (0..20).map(|i| format!("media/{}.bin", i)).collect();
+        let contents: Vec<Vec<u8>> = (0..20).map(|i| vec![i as u8; 1000 + i]).collect();
+        let media: Vec<(&str, &[u8])> = names
+            .iter()
+            .zip(&contents)
+            .map(|(name, contents)| (name.as_str(), contents.as_slice()))
+            .collect();
+        let json_path = write_test_export(&dir, "big", &media).to_string_lossy().to_string();
+
+        let cancelled = AtomicBool::new(false);
+        let cancel_partway = |phase: &str, _: &str, current: Option<usize>, _: Option<usize>| {
+            if phase == "copying" && current == Some(5) {
+                cancelled.store(true, Ordering::SeqCst);
+            }
+        };
+        assert!(core.import_conversation_with_callbacks(&json_path, None, cancel_partway, &cancelled).is_err());
+        let interrupted = core.list_interrupted_imports().unwrap();
+        assert_eq!(interrupted.len(), 1);
+        assert!(core.load_metadata().unwrap().imports.is_empty());
+
+        // The same file picks up where the first attempt stopped
+        cancelled.store(false, Ordering::SeqCst);
+        let entry = core.import_conversation_with_callbacks(&json_path, None, |_, _, _, _| {}, &cancelled).unwrap();
+        assert_eq!(entry.id, interrupted[0].import_id);
+        let report = core.get_asset_report(&entry.id).unwrap().unwrap();
+        assert_eq!(report.copied + report.reused, 20);
+        assert!(report.reused >= 5 && report.copied < 20, "{:?}", report);
+        assert!(core.list_interrupted_imports().unwrap().is_empty());
+        let import_dir = PathBuf::from(&entry.import_path);
+        assert!(!import_dir.join(COPY_JOURNAL_FILE).exists() && !import_dir.join(IMPORT_JOURNAL_FILE).exists());
+        for (name, contents) in &media {
+            assert_eq!(fs::read(import_dir.join("attachments").join(name)).unwrap(), *contents);
+        }
+    }
+
+    #[test]
+    fn test_package_import_skips_duplicates_and_collects_failures() {
+        let dir = temp_dir("package");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let package_dir = dir.join("package");
+        fs::create_dir_all(package_dir.join("media")).unwrap();
+        fs::write(package_dir.join("media/cat.png"), b"cat").unwrap();
+        let package = Arc::new(PackageReader::open(&package_dir).unwrap());
+
+        let converted = |name: &str| {
+            let json = fs::read_to_string(write_test_export(&dir, name, &[("media/cat.png", b"cat")])).unwrap();
+            ConvertedExport {
+                export: serde_json::from_str(&json).unwrap(),
+                file_hash: hash_bytes(json.as_bytes()),
+                media_files: vec!["media/cat.png".to_string()],
+            }
+        };
+        let import = |exports: Vec<ConvertedExport>| {
+            let cancelled = std::sync::atomic::AtomicBool::new(false);
+            core.import_converted(exports, ImportSource::Slack, package.clone(), &(|_, _, _, _| {}), &cancelled).unwrap()
+        };
+
+        let first = import(vec![converted("general")]);
+        assert_eq!(first.imported.len(), 1);
+
+        // An interrupted attempt whose attachments folder is a file can't be resumed
+        let broken = converted("broken");
+        let stale_dir = core.sapper_dir.join("imports").join("stale");
+        fs::create_dir_all(&stale_dir).unwrap();
+        fs::write(stale_dir.join("attachments"), b"").unwrap();
+        core.write_import_journal(&stale_dir, &ImportJournal {
+            import_id: "stale".to_string(),
+            file_hash: broken.file_hash.clone(),
+            alias: "broken".to_string(),
+            channel_name: "broken".to_string(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+            copied_files: 0,
+        }).unwrap();
+
+        let second = import(vec![converted("general"), broken, converted("random"), converted("random")]);
+        let names: Vec<&str> = second.imported.iter().map(|e| e.channel_name.as_str()).collect();
+        assert_eq!(names, vec!["random"]);
+        assert_eq!(second.skipped, vec!["general", "random"]);
+        assert_eq!(second.failed.len(), 1);
+        assert_eq!(second.failed[0].conversation_name, "broken");
+        assert_eq!(core.load_metadata().unwrap().imports.len(), 2);
+    }
+
+    #[test]
+    fn test_folder_import_links_thread_starters() {
+        let dir = temp_dir("threads");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let folder = dir.join("server");
+        fs::create_dir_all(&folder).unwrap();
+
+        let write = |channel: serde_json::Value, messages: serde_json::Value| {
+            let name = channel["name"].as_str().unwrap().to_string();
+            let export = serde_json::json!({ "guild": { "id": "1", "name": "G" }, "channel": channel, "messages": messages });
+            fs::write(folder.join(format!("{}.json", name)), export.to_string()).unwrap();
+        };
+        let message = |id: &str, kind: &str, content: &str, reference: serde_json::Value| {
+            serde_json::json!({
+                "id": id, "type": kind, "content": content, "reference": reference,
+                "timestamp": "2020-01-01T00:00:00+00:00", "author": { "id": "4", "name": "a" }
+            })
+        };
+        write(
+            serde_json::json!({ "id": "10", "type": "GuildTextChat", "name": "general" }),
+            serde_json::json!([
+                message("5", "Default", "before", serde_json::Value::Null),
+                message("20", "Default", "started from here", serde_json::Value::Null),
+                message("30", "ThreadCreated", "", serde_json::json!({ "messageId": "", "channelId": "21" })),
+                message("31", "ThreadCreated", "named", serde_json::Value::Null)
+            ])
+        );
+        for (id, name) in [("20", "from message"), ("21", "by reference"), ("22", "named"), ("23", "unknown")] {
+            write(
+                serde_json::json!({ "id": id, "type": "GuildPublicThread", "categoryId": "10", "category": "general", "name": name }),
+                serde_json::json!([message(&format!("{}0", id), "Default", "in thread", serde_json::Value::Null)])
+            );
+        }
+
+        let cancelled = std::sync::atomic::AtomicBool::new(false);
+        let results = core.import_folder_with_callbacks(&folder.to_string_lossy(), |_, _, _, _| {}, &cancelled).unwrap();
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+        let metadata = core.load_metadata().unwrap();
+        let parent = metadata.imports.iter().find(|e| e.channel_id == "10").unwrap();
+        let messages = message_storage::open_store(PathBuf::from(&parent.import_path)).load_all_messages().unwrap();
+        let original_id = |id: Option<u64>| id.map(|id| messages.iter().find(|m| m.id == id).unwrap().original_id.clone());
+
+        let mut links: Vec<(String, Option<String>)> = core
+            .get_channel_threads(&parent.id)
+            .unwrap()
+            .into_iter()
+            .map(|link| (link.thread_channel_id, original_id(link.starter_message_id)))
+            .collect();
+        links.sort();
+        let expected = [("20", Some("20")), ("21", Some("30")), ("22", Some("31")), ("23", None)];
+        assert_eq!(links, expected.map(|(thread, starter)| (thread.to_string(), starter.map(str::to_string))));
+    }
+
+    #[test]
+    fn test_archive_other_json_isnt_an_export() {
+        use std::io::Write;
+
+        let dir = temp_dir("archive");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let export = fs::read(write_test_export(&dir, "general", &[])).unwrap();
+        let zip_path = dir.join("export.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        let entries: [(&str, &[u8]); 3] = [
+            ("general.json", &export),
+            ("metadata.json", br#"{ "exporter": "DiscordChatExporter" }"#),
+            ("general.json_Files/data.json", b"{}"),
+        ];
+        for (name, contents) in entries {
+            writer.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let zip_path = zip_path.to_string_lossy().to_string();
+        assert_eq!(core.list_archive_exports(&zip_path).unwrap(), vec![format!("{}::general.json", zip_path)]);
+        let cancelled = std::sync::atomic::AtomicBool::new(false);
+        let entry = core.import_conversation_with_callbacks(&zip_path, None, |_, _, _, _| {}, &cancelled).unwrap();
+        assert_eq!(entry.channel_id, "general");
+    }
+
+    #[test]
+    fn test_concurrent_import_data_updates_are_kept() {
+        let dir = temp_dir("import-data");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let entry = import_test_export(&core, &dir, "general", &[("a.png", b"a")]);
+
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let (core, import_id) = (&core, &entry.id);
+                scope.spawn(move || {
+                    core.update_import_data(import_id, |import_data| {
+                        import_data.member_merges.insert(format!("alt-{}", i), "4".to_string());
+                        Ok(())
+                    }).unwrap();
+                });
+            }
+            scope.spawn(|| core.set_mutable_conversation(&entry.id, true, Some("4".to_string())).unwrap());
+            scope.spawn(|| core.update_member(&entry.id, "4", Some("renamed".to_string()), None, None).unwrap());
+        });
+
+        let import_data = core.load_import_data(&entry.id).unwrap();
+        assert_eq!(import_data.member_merges.len(), 8);
+        assert!(import_data.mutable_conversation);
+        assert_eq!(import_data.members[0].nickname, "renamed");
+        let err = core.update_member(&entry.id, "nobody", Some("x".to_string()), None, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_member_history_follows_changes() {
+        let dir = temp_dir("history");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let message = |second: u32, nickname: &str, color: Option<&str>| {
+            serde_json::json!({
+                "id": second.to_string(), "timestamp": format!("2020-01-01T00:00:{:02}+00:00", second),
+                "author": { "id": "4", "name": "alice", "nickname": nickname, "color": color, "avatarUrl": "a.png" }
+            })
+        };
+        let entry = import_test_messages(
+            &core,
+            &dir,
+            "general",
+            serde_json::json!([
+                message(1, "Alice", None),
+                message(2, "Alice", None),
+                message(3, "Ally", Some("#ff0000")),
+                message(4, "Alice", Some("#ff0000"))
+            ])
+        );
+
+        let members = core.load_members(&entry.id).unwrap();
+        assert_eq!(members.members[0].nickname, "Alice");
+        let history = &members.member_history[0];
+        assert_eq!(history.member_id, "4");
+        let runs = |entries: &[MemberHistoryEntry]| -> Vec<(Option<String>, String, String)> {
+            entries
+                .iter()
+                .map(|e| (e.value.clone(), e.first_seen[17..19].to_string(), e.last_seen[17..19].to_string()))
+                .collect()
+        };
+        let run = |value: Option<&str>, first: &str, last: &str| (value.map(str::to_string), first.to_string(), last.to_string());
+        assert_eq!(
+            runs(&history.nicknames),
+            vec![run(Some("Alice"), "01", "02"), run(Some("Ally"), "03", "03"), run(Some("Alice"), "04", "04")]
+        );
+        assert_eq!(runs(&history.avatars), vec![run(Some("a.png"), "01", "04")]);
+        assert_eq!(runs(&history.colors), vec![run(None, "01", "02"), run(Some("#ff0000"), "03", "04")]);
+    }
+
+    #[test]
+    fn test_merged_authors_read_as_one_member() {
+        let dir = temp_dir("merge");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let message = |id: &str, author_id: &str, name: &str| {
+            serde_json::json!({
+                "id": id, "timestamp": "2020-01-01T00:00:00+00:00",
+                "author": { "id": author_id, "name": name, "nickname": name }
+            })
+        };
+        let entry = import_test_messages(
+            &core,
+            &dir,
+            "general",
+            serde_json::json!([message("1", "4", "alice"), message("2", "5", "alice_alt"), message("3", "5", "alice_alt")])
+        );
+        let stats = || {
+            let mut stats: Vec<(String, String, usize)> = core
+                .get_member_stats(&entry.id)
+                .unwrap()
+                .into_iter()
+                .map(|s| (s.member_id, s.nickname, s.message_count))
+                .collect();
+            stats.sort();
+            stats
+        };
+
+        // Merging twice leaves the same result as merging once
+        for _ in 0..2 {
+            core.merge_members(Some(&entry.id), "4", &["5".to_string(), "4".to_string()]).unwrap();
+            assert_eq!(stats(), vec![("4".to_string(), "alice".to_string(), 3)]);
+
+            let members = core.load_merged_members(&entry.id).unwrap().members;
+            assert_eq!(members.len(), 1);
+            assert_eq!(members[0].merged_ids, vec!["5"]);
+        }
+        assert_eq!(core.load_member_merges(&entry.id).unwrap().len(), 1);
+
+        core.unmerge_member(Some(&entry.id), "5").unwrap();
+        assert_eq!(
+            stats(),
+            vec![("4".to_string(), "alice".to_string(), 1), ("5".to_string(), "alice_alt".to_string(), 2)]
+        );
+        assert_eq!(core.merge_members(Some(&entry.id), "4", &["4".to_string()]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_legacy_hashes_are_migrated_and_compared() {
+        let dir = temp_dir("hashes");
+        let core = SapperCore::open(dir.join("library")).unwrap();
+        let entry = import_test_export(&core, &dir, "general", &[("a.png", b"a")]);
+        core.update_metadata(|metadata| {
+            metadata.imports[0].file_hash = "12345".to_string();
+            metadata.imports[0].hash_algorithm = HashAlgorithm::Legacy;
+            Ok(())
+        }).unwrap();
+        // The backup keeps the entry as it was before the migration
+        let backup = dir.join("backup").to_string_lossy().to_string();
+        core.export_all(&backup).unwrap();
+
+        assert_eq!(core.migrate_file_hashes().unwrap(), 1);
+        let migrated = core.load_metadata().unwrap().imports.remove(0);
+        assert_eq!(migrated.hash_algorithm, HashAlgorithm::Sha256);
+        let export = fs::File::open(core.get_export_path(&migrated)).unwrap();
+        assert_eq!(migrated.file_hash, hash_reader(export).unwrap());
+        assert_eq!(core.migrate_file_hashes().unwrap(), 0);
+
+        let result = core.import_backup_detailed(&backup).unwrap();
+        assert_eq!((result.success_count, result.failed_count), (0, 1));
+        assert!(result.failed[0].error.contains("duplicate"));
+        let metadata = core.load_metadata().unwrap();
+        assert_eq!(metadata.imports.len(), 1);
+        assert_eq!(metadata.imports[0].id, entry.id);
+    }
+}
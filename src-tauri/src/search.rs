@@ -284,6 +284,11 @@ impl MessageSearchIndex {
         Ok(Self { index, schema, reader })
     }
 
+    /// Number of messages in the index, not counting deleted ones
+    pub fn num_docs(&self) -> u64 {
+        self.reader.searcher().num_docs()
+    }
+
     pub fn index_messages(&self, messages: &[StoredMessage]) -> io::Result<()> {
         let id_field = self.schema.get_field("id").unwrap();
         let timestamp_field = self.schema.get_field("timestamp").unwrap();
@@ -298,14 +303,15 @@ impl MessageSearchIndex {
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
         for msg in messages {
+            let searchable_text = msg.searchable_text();
             writer
                 .add_document(doc!(
                     id_field => msg.id,
                     timestamp_field => msg.timestamp,
                     sender_field => msg.author.nickname.clone(),
                     content_raw_field => msg.content.clone(),
-                    content_stemmed_field => msg.content.clone(),
-                    content_prefix_field => msg.content.clone(),
+                    content_stemmed_field => searchable_text.clone(),
+                    content_prefix_field => searchable_text,
                 ))
                 .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
         }
@@ -515,14 +521,15 @@ impl MessageSearchIndex {
             .writer(50_000_000)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
+        let searchable_text = msg.searchable_text();
         writer
             .add_document(doc!(
                 id_field => msg.id,
                 timestamp_field => msg.timestamp,
                 sender_field => msg.author.nickname.clone(),
                 content_raw_field => msg.content.clone(),
-                content_stemmed_field => msg.content.clone(),
-                content_prefix_field => msg.content.clone(),
+                content_stemmed_field => searchable_text.clone(),
+                content_prefix_field => searchable_text,
             ))
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
@@ -0,0 +1,491 @@
+use crate::models::*;
+use crate::package_reader::PackageReader;
+use crate::sapper_core::{ hash_bytes, ConvertedExport };
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{ BTreeMap, HashMap };
+use std::io;
+use tracing::{ instrument, warn };
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SlackUser {
+    id: String,
+    name: String,
+    real_name: Option<String>,
+    color: Option<String>,
+    is_bot: bool,
+    profile: SlackProfile,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SlackProfile {
+    display_name: String,
+    real_name: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SlackChannel {
+    id: String,
+    name: Option<String>,
+    members: Vec<String>,
+    topic: Option<SlackTopic>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SlackTopic {
+    value: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SlackMessage {
+    subtype: Option<String>,
+    ts: String,
+    user: Option<String>,
+    username: Option<String>,
+    bot_id: Option<String>,
+    text: String,
+    thread_ts: Option<String>,
+    edited: Option<SlackEdited>,
+    reactions: Vec<SlackReaction>,
+    files: Vec<SlackFile>,
+    pinned_to: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SlackEdited {
+    ts: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SlackReaction {
+    name: String,
+    count: u64,
+    users: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SlackFile {
+    id: String,
+    name: Option<String>,
+    title: Option<String>,
+    url_private: Option<String>,
+    size: u64,
+}
+
+/// Which list a conversation came from, deciding how it is grouped
+#[derive(Clone, Copy)]
+enum ConversationKind {
+    Channel,
+    Direct,
+}
+
+/// Regexes for Slack's mrkdwn, built once per export
+struct Mrkdwn {
+    link: Regex,
+    bold: Regex,
+    strike: Regex,
+}
+
+impl Mrkdwn {
+    fn new() -> Self {
+        Mrkdwn {
+            link: Regex::new(r"<([^>|]+)(?:\|([^>]+))?>").unwrap(),
+            bold: Regex::new(r"(^|[\s(])\*([^*\n]+)\*").unwrap(),
+            strike: Regex::new(r"(^|[\s(])~([^~\n]+)~").unwrap(),
+        }
+    }
+}
+
+/// Read a Slack workspace export (zip or folder) into one export per channel.
+/// Channel folders hold one JSON file per day.
+#[instrument(skip_all)]
+pub fn read_slack_export(reader: &PackageReader, workspace_name: &str) -> io::Result<Vec<ConvertedExport>> {
+    let file_names = reader.file_names();
+    let find = |name: &str| {
+        file_names
+            .iter()
+            .filter(|n| n.rsplit('/').next() == Some(name))
+            .min_by_key(|n| n.len())
+            .cloned()
+    };
+    let read_list = |name: &str| -> io::Result<Vec<SlackChannel>> {
+        match find(name) {
+            Some(path) =>
+                serde_json
+                    ::from_str(&reader.read_to_string(&path)?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => Ok(Vec::new()),
+        }
+    };
+
+    let users_path = find("users.json").ok_or_else(||
+        io::Error::new(io::ErrorKind::InvalidData, "Not a Slack export: users.json is missing")
+    )?;
+    let users: Vec<SlackUser> = serde_json
+        ::from_str(&reader.read_to_string(&users_path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let authors: HashMap<String, Author> = users
+        .iter()
+        .map(|u| (u.id.clone(), slack_author(u)))
+        .collect();
+    // Channel folders sit next to users.json
+    let root = users_path
+        .rsplit_once('/')
+        .map(|(dir, _)| format!("{}/", dir))
+        .unwrap_or_default();
+
+    let mut conversations: Vec<(SlackChannel, ConversationKind)> = Vec::new();
+    for list in ["channels.json", "groups.json"] {
+        conversations.extend(
+            read_list(list)?
+                .into_iter()
+                .map(|c| (c, ConversationKind::Channel))
+        );
+    }
+    for list in ["dms.json", "mpims.json"] {
+        conversations.extend(
+            read_list(list)?
+                .into_iter()
+                .map(|c| (c, ConversationKind::Direct))
+        );
+    }
+
+    // Day files grouped by their channel folder
+    let mut day_files: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for name in &file_names {
+        let Some(relative) = name.strip_prefix(root.as_str()) else {
+            continue;
+        };
+        if let Some((folder, file)) = relative.split_once('/') {
+            if file.ends_with(".json") && !file.contains('/') {
+                day_files.entry(folder.to_string()).or_default().push(name.clone());
+            }
+        }
+    }
+
+    let mrkdwn = Mrkdwn::new();
+    let channel_names: HashMap<String, String> = conversations
+        .iter()
+        .filter_map(|(c, _)| c.name.clone().map(|n| (c.id.clone(), n)))
+        .collect();
+
+    let mut exports = Vec::new();
+    for (channel, kind) in conversations {
+        // Channels are exported under their name, DMs under their ID
+        let folder = channel.name.clone().unwrap_or_else(|| channel.id.clone());
+        let Some(mut files) = day_files.remove(&folder).or_else(|| day_files.remove(&channel.id)) else {
+            continue;
+        };
+        files.sort();
+
+        let mut raw_all = Vec::new();
+        let mut slack_messages = Vec::new();
+        for file in &files {
+            let raw = reader.read_bytes(file)?;
+            match serde_json::from_slice::<Vec<SlackMessage>>(&raw) {
+                Ok(day) => slack_messages.extend(day),
+                Err(e) => warn!("Skipping unreadable Slack day file {}: {}", file, e),
+            }
+            raw_all.extend_from_slice(&raw);
+        }
+        if slack_messages.is_empty() {
+            continue;
+        }
+        slack_messages.sort_by(|a, b| slack_seconds(&a.ts).total_cmp(&slack_seconds(&b.ts)));
+
+        let context = ConvertContext {
+            authors: &authors,
+            channel_names: &channel_names,
+            mrkdwn: &mrkdwn,
+            channel_id: &channel.id,
+        };
+        let messages = slack_messages
+            .into_iter()
+            .map(|m| context.convert_message(m))
+            .collect();
+
+        let (guild, name, channel_type) = match kind {
+            ConversationKind::Channel =>
+                (
+                    Guild {
+                        id: hash_bytes(workspace_name.as_bytes()),
+                        name: workspace_name.to_string(),
+                        icon_url: None,
+                    },
+                    folder,
+                    "GuildTextChat",
+                ),
+            ConversationKind::Direct => {
+                let names: Vec<String> = channel.members
+                    .iter()
+                    .filter_map(|id| authors.get(id).map(|a| a.nickname.clone()))
+                    .collect();
+                let name = channel.name
+                    .clone()
+                    .filter(|_| names.is_empty())
+                    .unwrap_or_else(|| names.join(", "));
+                let channel_type = if channel.members.len() > 2 {
+                    "DirectGroupTextChat"
+                } else {
+                    "DirectTextChat"
+                };
+                (
+                    Guild { id: "0".to_string(), name: "Direct Messages".to_string(), icon_url: None },
+                    name,
+                    channel_type,
+                )
+            }
+        };
+
+        exports.push(ConvertedExport {
+            export: DiscordExport {
+                guild,
+                channel: Channel {
+                    id: channel.id.clone(),
+                    channel_type: channel_type.to_string(),
+                    category_id: None,
+                    category: None,
+                    name,
+                    topic: channel.topic.map(|t| t.value).filter(|t| !t.is_empty()),
+                },
+                date_range: DateRange::default(),
+                exported_at: String::new(),
+                messages,
+            },
+            file_hash: hash_bytes(&raw_all),
+            // Slack exports link files on Slack's servers
+            media_files: Vec::new(),
+        });
+    }
+
+    Ok(exports)
+}
+
+fn slack_author(user: &SlackUser) -> Author {
+    let display_name = [
+        user.profile.display_name.as_str(),
+        user.profile.real_name.as_str(),
+        user.real_name.as_deref().unwrap_or(""),
+    ]
+        .into_iter()
+        .find(|n| !n.is_empty())
+        .unwrap_or(&user.name)
+        .to_string();
+
+    Author {
+        id: user.id.clone(),
+        name: user.name.clone(),
+        discriminator: "0000".to_string(),
+        nickname: display_name,
+        color: user.color.as_ref().map(|c| format!("#{}", c.trim_start_matches('#'))),
+        is_bot: user.is_bot,
+        ..Default::default()
+    }
+}
+
+fn slack_seconds(ts: &str) -> f64 {
+    ts.parse().unwrap_or(0.0)
+}
+
+/// "1600000000.000100" -> RFC 3339
+fn slack_timestamp(ts: &str) -> String {
+    let (secs, micros) = ts.split_once('.').unwrap_or((ts, "0"));
+    let secs = secs.parse::<i64>().unwrap_or(0);
+    let micros = format!("{:0<6}", micros)[..6].parse::<u32>().unwrap_or(0);
+    chrono::DateTime
+        ::from_timestamp(secs, micros * 1000)
+        .map(|dt| dt.to_rfc3339())
+        .unwrap_or_default()
+}
+
+struct ConvertContext<'a> {
+    authors: &'a HashMap<String, Author>,
+    channel_names: &'a HashMap<String, String>,
+    mrkdwn: &'a Mrkdwn,
+    channel_id: &'a str,
+}
+
+impl ConvertContext<'_> {
+    fn author(&self, user_id: &str) -> Author {
+        self.authors.get(user_id).cloned().unwrap_or_else(|| Author {
+            id: user_id.to_string(),
+            name: user_id.to_string(),
+            discriminator: "0000".to_string(),
+            nickname: user_id.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn user(&self, user_id: &str) -> User {
+        let author = self.author(user_id);
+        User {
+            id: author.id,
+            name: author.name,
+            discriminator: author.discriminator,
+            nickname: author.nickname,
+            color: author.color,
+            is_bot: author.is_bot,
+            ..Default::default()
+        }
+    }
+
+    fn convert_message(&self, msg: SlackMessage) -> Message {
+        let author = match (&msg.user, &msg.bot_id) {
+            (Some(user), _) => self.author(user),
+            (None, Some(bot_id)) => {
+                let name = msg.username.clone().unwrap_or_else(|| bot_id.clone());
+                Author {
+                    id: bot_id.clone(),
+                    name: name.clone(),
+                    discriminator: "0000".to_string(),
+                    nickname: name,
+                    is_bot: true,
+                    ..Default::default()
+                }
+            }
+            (None, None) => self.author("unknown"),
+        };
+
+        // Thread replies point at the thread's parent message
+        let reference = msg.thread_ts
+            .as_ref()
+            .filter(|thread_ts| **thread_ts != msg.ts)
+            .map(|thread_ts| MessageReference {
+                message_id: thread_ts.clone(),
+                channel_id: Some(self.channel_id.to_string()),
+            });
+
+        let message_type = match msg.subtype.as_deref() {
+            Some("channel_join") | Some("group_join") => "RecipientAdd",
+            Some("channel_leave") | Some("group_leave") => "RecipientRemove",
+            Some("channel_name") | Some("group_name") => "ChannelNameChange",
+            Some("pinned_item") => "ChannelPinnedMessage",
+            _ if reference.is_some() => "Reply",
+            _ => "Default",
+        };
+
+        let mentions = self.mrkdwn.link
+            .captures_iter(&msg.text)
+            .filter_map(|c| c[1].strip_prefix('@').map(|id| self.user(id)))
+            .collect();
+
+        let attachments = msg.files
+            .iter()
+            .filter_map(|file| {
+                let url = file.url_private.clone()?;
+                Some(Attachment {
+                    id: file.id.clone(),
+                    url,
+                    file_name: file.name.clone().or_else(|| file.title.clone()).unwrap_or_default(),
+                    file_size_bytes: file.size,
+                })
+            })
+            .collect();
+
+        let reactions = msg.reactions
+            .iter()
+            .map(|r| Reaction {
+                emoji: Emoji { name: r.name.clone(), code: r.name.clone(), ..Default::default() },
+                count: r.count,
+                users: r.users
+                    .iter()
+                    .map(|id| self.user(id))
+                    .collect(),
+                ..Default::default()
+            })
+            .collect();
+
+        Message {
+            id: msg.ts.clone(),
+            message_type: message_type.to_string(),
+            timestamp: slack_timestamp(&msg.ts),
+            timestamp_edited: msg.edited.as_ref().map(|e| slack_timestamp(&e.ts)),
+            is_pinned: !msg.pinned_to.is_empty(),
+            content: self.mrkdwn_to_markdown(&msg.text),
+            author,
+            attachments,
+            reactions,
+            mentions,
+            reference,
+            ..Default::default()
+        }
+    }
+
+    /// Convert Slack mrkdwn to the Discord markdown the viewer renders
+    fn mrkdwn_to_markdown(&self, text: &str) -> String {
+        let linked = self.mrkdwn.link.replace_all(text, |caps: &regex::Captures| {
+            let target = &caps[1];
+            let label = caps.get(2).map(|m| m.as_str());
+            if let Some(user_id) = target.strip_prefix('@') {
+                format!("@{}", self.author(user_id).nickname)
+            } else if let Some(channel_id) = target.strip_prefix('#') {
+                let name = label
+                    .map(|l| l.to_string())
+                    .or_else(|| self.channel_names.get(channel_id).cloned())
+                    .unwrap_or_else(|| channel_id.to_string());
+                format!("#{}", name)
+            } else if let Some(special) = target.strip_prefix('!') {
+                format!("@{}", label.unwrap_or(special))
+            } else {
+                match label {
+                    Some(label) => format!("[{}]({})", label, target),
+                    None => target.to_string(),
+                }
+            }
+        });
+        let bolded = self.mrkdwn.bold.replace_all(&linked, "$1**$2**");
+        let struck = self.mrkdwn.strike.replace_all(&bolded, "$1~~$2~~");
+
+        struck.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_reply_and_mrkdwn() {
+        let mut authors = HashMap::new();
+        authors.insert("U1".to_string(), Author {
+            id: "U1".to_string(),
+            name: "alice".to_string(),
+            nickname: "Alice".to_string(),
+            ..Default::default()
+        });
+        let channel_names = HashMap::from([("C1".to_string(), "general".to_string())]);
+        let mrkdwn = Mrkdwn::new();
+        let context = ConvertContext {
+            authors: &authors,
+            channel_names: &channel_names,
+            mrkdwn: &mrkdwn,
+            channel_id: "C1",
+        };
+
+        let msg: SlackMessage = serde_json
+            ::from_value(
+                serde_json::json!({
+                "ts": "1600000001.000200", "thread_ts": "1600000000.000100", "user": "U1",
+                "text": "hi <@U1>, *see* <#C1> and <https://x.dev|this> &amp; more",
+                "reactions": [{ "name": "thumbsup", "count": 1, "users": ["U1"] }]
+            })
+            )
+            .unwrap();
+        let message = context.convert_message(msg);
+
+        assert_eq!(message.message_type, "Reply");
+        assert_eq!(message.reference.unwrap().message_id, "1600000000.000100");
+        assert_eq!(message.content, "hi @Alice, **see** #general and [this](https://x.dev) & more");
+        assert_eq!(message.mentions[0].nickname, "Alice");
+        assert_eq!(message.reactions[0].users[0].id, "U1");
+        assert_eq!(message.timestamp, "2020-09-13T12:26:41.000200+00:00");
+    }
+}
@@ -0,0 +1,435 @@
+use crate::message_storage::{
+    library_root_of,
+    relativize_media_refs,
+    renumber_messages,
+    resolve_media_refs,
+    Compaction,
+    MessageStore,
+    StoredMessage,
+};
+use crate::models::StorageBackend;
+use rusqlite::{ params, Connection, OptionalExtension };
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+/// An import's messages when it uses the SQLite backend
+pub const SQLITE_FILE: &str = "messages.db";
+
+/// Bumped when the schema changes. Databases from a newer version aren't opened, as this
+/// one can't tell what it would break.
+const SCHEMA_VERSION: i64 = 2;
+
+/// Messages are kept whole as JSON, with the columns lookups need pulled out beside them
+const SCHEMA: &str =
+    "
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        author_id TEXT NOT NULL,
+        is_pinned INTEGER NOT NULL,
+        is_user_message INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_timestamp ON messages (timestamp);
+    CREATE INDEX IF NOT EXISTS messages_author ON messages (author_id);
+    CREATE INDEX IF NOT EXISTS messages_pinned ON messages (is_pinned) WHERE is_pinned = 1;
+    CREATE TABLE IF NOT EXISTS counters (
+        name TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );
+";
+
+/// One SQLite database per import, so edits, deletes, pinned and date lookups touch only
+/// the rows involved instead of whole chunk files
+pub struct SqliteStorage {
+    import_dir: PathBuf,
+}
+
+impl SqliteStorage {
+    pub fn new(import_dir: PathBuf) -> Self {
+        Self { import_dir }
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        open_database(&self.import_dir.join(SQLITE_FILE))
+    }
+
+    /// Write `messages` to a new database beside the import's current one and swap it in
+    pub fn build(import_dir: &Path, messages: Vec<StoredMessage>, next_message_id: u64) -> io::Result<()> {
+        let path = import_dir.join(SQLITE_FILE);
+        let temp_path = import_dir.join(format!("{}.tmp", SQLITE_FILE));
+        if temp_path.exists() {
+            fs::remove_file(&temp_path)?;
+        }
+
+        let library_root = library_root_of(import_dir);
+        let mut conn = open_database(&temp_path)?;
+        let tx = conn.transaction().map_err(io::Error::other)?;
+        {
+            let mut insert = tx
+                .prepare(
+                    "INSERT INTO messages (id, timestamp, author_id, is_pinned, is_user_message, data)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+                )
+                .map_err(io::Error::other)?;
+            for mut message in messages {
+                relativize_media_refs(&library_root, &mut message);
+                insert
+                    .execute(
+                        params![
+                            message.id as i64,
+                            message.timestamp as i64,
+                            message.author.id,
+                            message.is_pinned,
+                            message.is_user_message,
+                            to_json(&message)?
+                        ]
+                    )
+                    .map_err(io::Error::other)?;
+            }
+        }
+        set_next_id(&tx, next_message_id as i64)?;
+        tx.commit().map_err(io::Error::other)?;
+        conn.close().map_err(|(_, e)| io::Error::other(e))?;
+
+        fs::File::open(&temp_path)?.sync_all()?;
+        fs::rename(&temp_path, &path)
+    }
+
+    /// Delete an import's database
+    pub fn remove(import_dir: &Path) -> io::Result<()> {
+        for suffix in ["", "-journal", "-wal", "-shm"] {
+            let path = import_dir.join(format!("{}{}", SQLITE_FILE, suffix));
+            if let Err(e) = fs::remove_file(path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// SQLite's own consistency check. None if the database is sound.
+    pub fn check(&self) -> io::Result<Option<String>> {
+        let conn = self.connect()?;
+        let result: String = conn
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+            .map_err(io::Error::other)?;
+        Ok((result != "ok").then_some(result))
+    }
+
+    fn read_message(&self, data: &str) -> io::Result<StoredMessage> {
+        let mut message: StoredMessage = serde_json
+            ::from_str(data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        resolve_media_refs(&library_root_of(&self.import_dir), &mut message);
+        Ok(message)
+    }
+
+    fn query_messages(&self, sql: &str, params: impl rusqlite::Params) -> io::Result<Vec<StoredMessage>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(sql).map_err(io::Error::other)?;
+        let rows = stmt
+            .query_map(params, |row| row.get::<_, String>(0))
+            .map_err(io::Error::other)?;
+
+        let mut messages = Vec::new();
+        for data in rows {
+            messages.push(self.read_message(&data.map_err(io::Error::other)?)?);
+        }
+        Ok(messages)
+    }
+
+    /// The user message `message_id`, or why it can't be changed
+    fn user_message(&self, conn: &Connection, message_id: u64, action: &str) -> io::Result<StoredMessage> {
+        let data: Option<String> = conn
+            .query_row("SELECT data FROM messages WHERE id = ?1", [message_id as i64], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?;
+        let Some(data) = data else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Message {} not found", message_id)));
+        };
+        let message = self.read_message(&data)?;
+        if !message.is_user_message {
+            return Err(
+                io::Error::new(io::ErrorKind::PermissionDenied, format!("Cannot {} non-user messages", action))
+            );
+        }
+        Ok(message)
+    }
+}
+
+impl MessageStore for SqliteStorage {
+    fn backend(&self) -> StorageBackend {
+        StorageBackend::Sqlite
+    }
+
+    fn write_messages(&self, messages: Vec<StoredMessage>) -> io::Result<()> {
+        Self::build(&self.import_dir, messages, 0)
+    }
+
+    fn total_messages(&self) -> io::Result<usize> {
+        let conn = self.connect()?;
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))
+            .map_err(io::Error::other)?;
+        Ok(count as usize)
+    }
+
+    fn get_pinned_message_ids(&self) -> io::Result<Vec<u64>> {
+        let conn = self.connect()?;
+        let mut stmt = conn
+            .prepare("SELECT id FROM messages WHERE is_pinned = 1 ORDER BY id")
+            .map_err(io::Error::other)?;
+        let ids = stmt
+            .query_map([], |row| row.get::<_, i64>(0))
+            .map_err(io::Error::other)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io::Error::other)?;
+        Ok(
+            ids
+                .into_iter()
+                .map(|id| id as u64)
+                .collect()
+        )
+    }
+
+    fn next_message_id(&self) -> io::Result<u64> {
+        Ok(next_id(&self.connect()?)? as u64)
+    }
+
+    fn load_messages_range(&self, start_idx: usize, count: usize) -> io::Result<Vec<StoredMessage>> {
+        self.query_messages(
+            "SELECT data FROM messages ORDER BY id LIMIT ?1 OFFSET ?2",
+            [count as i64, start_idx as i64]
+        )
+    }
+
+    fn append_message(&self, message: StoredMessage) -> io::Result<StoredMessage> {
+        let mut conn = self.connect()?;
+        let tx = conn.transaction().map_err(io::Error::other)?;
+
+        let next_id = next_id(&tx)?;
+        let mut message = message;
+        message.id = next_id as u64;
+
+        let mut stored = message.clone();
+        relativize_media_refs(&library_root_of(&self.import_dir), &mut stored);
+        tx.execute(
+            "INSERT INTO messages (id, timestamp, author_id, is_pinned, is_user_message, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                next_id,
+                stored.timestamp as i64,
+                stored.author.id,
+                stored.is_pinned,
+                stored.is_user_message,
+                to_json(&stored)?
+            ]
+        ).map_err(io::Error::other)?;
+        set_next_id(&tx, next_id + 1)?;
+        tx.commit().map_err(io::Error::other)?;
+
+        Ok(message)
+    }
+
+    fn edit_message(&self, message_id: u64, new_content: String) -> io::Result<StoredMessage> {
+        let conn = self.connect()?;
+        let mut message = self.user_message(&conn, message_id, "edit")?;
+        message.content = new_content;
+        message.timestamp_edited = Some(chrono::Utc::now().to_rfc3339());
+
+        let mut stored = message.clone();
+        relativize_media_refs(&library_root_of(&self.import_dir), &mut stored);
+        conn.execute("UPDATE messages SET data = ?1 WHERE id = ?2", params![to_json(&stored)?, message_id as i64]).map_err(
+            io::Error::other
+        )?;
+
+        Ok(message)
+    }
+
+    fn delete_message(&self, message_id: u64) -> io::Result<()> {
+        let mut conn = self.connect()?;
+        self.user_message(&conn, message_id, "delete")?;
+        let tx = conn.transaction().map_err(io::Error::other)?;
+        tx.execute("DELETE FROM messages WHERE id = ?1", [message_id as i64]).map_err(io::Error::other)?;
+        set_next_id(&tx, message_id as i64 + 1)?;
+        tx.commit().map_err(io::Error::other)
+    }
+
+    fn extract_user_messages(&self) -> io::Result<Vec<StoredMessage>> {
+        self.query_messages("SELECT data FROM messages WHERE is_user_message = 1 ORDER BY id", [])
+    }
+
+    fn update_messages(&self, update: &mut dyn FnMut(&mut StoredMessage)) -> io::Result<()> {
+        let messages = self.load_all_messages()?;
+        let library_root = library_root_of(&self.import_dir);
+
+        let mut conn = self.connect()?;
+        let tx = conn.transaction().map_err(io::Error::other)?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    "UPDATE messages SET timestamp = ?1, author_id = ?2, is_pinned = ?3, data = ?4 WHERE id = ?5"
+                )
+                .map_err(io::Error::other)?;
+            for mut message in messages {
+                update(&mut message);
+                relativize_media_refs(&library_root, &mut message);
+                stmt.execute(
+                    params![
+                        message.timestamp as i64,
+                        message.author.id,
+                        message.is_pinned,
+                        to_json(&message)?,
+                        message.id as i64
+                    ]
+                ).map_err(io::Error::other)?;
+            }
+        }
+        tx.commit().map_err(io::Error::other)
+    }
+
+    /// Rows have no chunks to rebalance; this only gives freed pages back to the disk,
+    /// after rebuilding the database if IDs are renumbered
+    fn compact(&self, renumber: bool) -> io::Result<Compaction> {
+        let mut compaction = Compaction::default();
+        if renumber {
+            let mut messages = self.load_all_messages()?;
+            compaction.renumbered = renumber_messages(&mut messages);
+            if !compaction.renumbered.is_empty() {
+                Self::build(&self.import_dir, messages, 0)?;
+            }
+        }
+        self.connect()?.execute_batch("VACUUM").map_err(io::Error::other)?;
+        Ok(compaction)
+    }
+
+    fn index_at_timestamp(&self, timestamp: u64) -> io::Result<usize> {
+        let conn = self.connect()?;
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM messages WHERE timestamp < ?1", [timestamp as i64], |row| row.get(0))
+            .map_err(io::Error::other)?;
+        Ok(count as usize)
+    }
+}
+
+fn open_database(path: &Path) -> io::Result<Connection> {
+    let conn = Connection::open(path).map_err(io::Error::other)?;
+    let version: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(io::Error::other)?;
+    if version > SCHEMA_VERSION {
+        return Err(
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Message database is from a newer version of Sapper (schema {})", version)
+            )
+        );
+    }
+    if version < SCHEMA_VERSION {
+        conn.execute_batch(SCHEMA).map_err(io::Error::other)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION).map_err(io::Error::other)?;
+    }
+    Ok(conn)
+}
+
+/// IDs aren't reused after a delete, even of the last message
+fn next_id(conn: &Connection) -> io::Result<i64> {
+    conn.query_row(
+        "SELECT MAX(
+            (SELECT COALESCE(MAX(id) + 1, 0) FROM messages),
+            (SELECT COALESCE(MAX(value), 0) FROM counters WHERE name = 'next_id')
+        )",
+        [],
+        |row| row.get(0)
+    ).map_err(io::Error::other)
+}
+
+/// Raise the lowest ID the next appended message may get
+fn set_next_id(conn: &Connection, next_id: i64) -> io::Result<()> {
+    conn.execute(
+        "INSERT INTO counters (name, value) VALUES ('next_id', ?1)
+         ON CONFLICT (name) DO UPDATE SET value = MAX(value, excluded.value)",
+        [next_id]
+    ).map_err(io::Error::other)?;
+    Ok(())
+}
+
+fn to_json(message: &StoredMessage) -> io::Result<String> {
+    serde_json::to_string(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use crate::message_storage::tests::message;
+    use crate::message_storage::{ convert_store, open_store, MessageStorage };
+
+    fn as_json(messages: &[StoredMessage]) -> serde_json::Value {
+        serde_json::to_value(messages).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_from_json_and_back() {
+        let dir = temp_dir("sqlite");
+        let import_dir = dir.join("imports").join("a");
+        fs::create_dir_all(&import_dir).unwrap();
+
+        let mut messages: Vec<StoredMessage> = (0..10).map(|i| message(i * 10)).collect();
+        for imported in &mut messages[..8] {
+            imported.is_user_message = false;
+        }
+        messages[3].is_pinned = true;
+        messages[7].is_pinned = true;
+        MessageStorage::new(import_dir.clone()).create_chunks(messages.clone(), 0).unwrap();
+
+        assert_eq!(convert_store(&import_dir, StorageBackend::Sqlite).unwrap(), 10);
+        let store = open_store(import_dir.clone());
+        assert_eq!(store.backend(), StorageBackend::Sqlite);
+        assert_eq!(as_json(&store.load_all_messages().unwrap()), as_json(&messages));
+        assert_eq!(store.get_pinned_message_ids().unwrap(), vec![30, 70]);
+        assert_eq!(store.index_at_timestamp(45).unwrap(), 5);
+
+        // Imported messages can't be changed
+        assert_eq!(store.edit_message(0, "x".to_string()).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(store.delete_message(70).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+
+        // Deleting the newest message doesn't free its ID
+        store.delete_message(90).unwrap();
+        assert_eq!(store.append_message(message(95)).unwrap().id, 91);
+        let stored = store.load_all_messages().unwrap();
+        assert_eq!(stored.len(), 10);
+        store.append_message(message(96)).unwrap();
+        store.delete_message(92).unwrap();
+
+        // Nor does converting the conversation
+        assert_eq!(convert_store(&import_dir, StorageBackend::Json).unwrap(), 10);
+        let store = open_store(import_dir.clone());
+        assert_eq!(store.backend(), StorageBackend::Json);
+        assert!(!import_dir.join(SQLITE_FILE).exists());
+        assert_eq!(as_json(&store.load_all_messages().unwrap()), as_json(&stored));
+        assert_eq!(store.get_pinned_message_ids().unwrap(), vec![30, 70]);
+        assert_eq!(store.next_message_id().unwrap(), 93);
+        store.append_message(message(97)).unwrap();
+        store.delete_message(93).unwrap();
+
+        convert_store(&import_dir, StorageBackend::Sqlite).unwrap();
+        assert_eq!(open_store(import_dir.clone()).append_message(message(98)).unwrap().id, 94);
+    }
+
+    #[test]
+    fn test_newer_schema_is_refused() {
+        let dir = temp_dir("sqlite");
+        SqliteStorage::build(&dir, vec![message(0)], 0).unwrap();
+        Connection::open(dir.join(SQLITE_FILE))
+            .unwrap()
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+
+        let err = SqliteStorage::new(dir.to_path_buf()).total_messages().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,444 @@
+use crate::models::*;
+use crate::package_reader::PackageReader;
+use crate::sapper_core::{ hash_bytes, ConvertedExport };
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::io;
+use tracing::instrument;
+
+/// Telegram Desktop writes this instead of a path when media wasn't exported
+const NOT_INCLUDED_PREFIX: &str = "(File not included";
+
+/// Full-account exports list every chat under `chats.list`
+#[derive(Deserialize)]
+struct TelegramAccountExport {
+    chats: TelegramChatList,
+    #[serde(default)]
+    left_chats: Option<TelegramChatList>,
+}
+
+#[derive(Deserialize)]
+struct TelegramChatList {
+    #[serde(default)]
+    list: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct TelegramChat {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(rename = "type", default)]
+    chat_type: String,
+    #[serde(default)]
+    id: i64,
+    #[serde(default)]
+    messages: Vec<TelegramMessage>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TelegramMessage {
+    id: i64,
+    #[serde(rename = "type")]
+    message_type: String,
+    date: String,
+    date_unixtime: String,
+    edited: Option<String>,
+    edited_unixtime: Option<String>,
+    from: Option<String>,
+    from_id: Option<String>,
+    actor: Option<String>,
+    actor_id: Option<String>,
+    action: Option<String>,
+    reply_to_message_id: Option<i64>,
+    text: serde_json::Value,
+    text_entities: Vec<TextEntity>,
+    photo: Option<String>,
+    photo_file_size: Option<u64>,
+    file: Option<String>,
+    file_name: Option<String>,
+    file_size: Option<u64>,
+    thumbnail: Option<String>,
+    media_type: Option<String>,
+    sticker_emoji: Option<String>,
+    reactions: Vec<TelegramReaction>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+struct TextEntity {
+    #[serde(rename = "type")]
+    kind: String,
+    text: String,
+    href: Option<String>,
+    language: Option<String>,
+    user_id: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TelegramReaction {
+    count: u64,
+    emoji: Option<String>,
+    recent: Vec<TelegramReactor>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TelegramReactor {
+    from: Option<String>,
+    from_id: Option<String>,
+}
+
+/// Read a Telegram Desktop `result.json`, either a single chat or a full account export.
+/// Media paths stay relative to the export root so they can be copied as-is.
+#[instrument(skip_all)]
+pub fn read_telegram_export(reader: &PackageReader) -> io::Result<Vec<ConvertedExport>> {
+    let result_path = reader
+        .file_names()
+        .into_iter()
+        .filter(|n| n.to_lowercase().ends_with("result.json"))
+        .min_by_key(|n| n.len())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "result.json not found"))?;
+    let raw = reader.read_bytes(&result_path)?;
+    // Media paths are relative to the folder holding result.json
+    let root = result_path
+        .rsplit_once('/')
+        .map(|(dir, _)| format!("{}/", dir))
+        .unwrap_or_default();
+
+    let value: serde_json::Value = serde_json
+        ::from_slice(&raw)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let chat_values = if value.get("chats").is_some() {
+        let account: TelegramAccountExport = serde_json
+            ::from_value(value)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut chats = account.chats.list;
+        if let Some(left) = account.left_chats {
+            chats.extend(left.list);
+        }
+        chats
+    } else {
+        vec![value]
+    };
+    let single_chat = chat_values.len() == 1;
+
+    let mut exports = Vec::new();
+    for chat_value in chat_values {
+        // Hash each chat on its own so re-exports of one chat are recognizable
+        let file_hash = if single_chat {
+            hash_bytes(&raw)
+        } else {
+            hash_bytes(chat_value.to_string().as_bytes())
+        };
+        let chat: TelegramChat = serde_json
+            ::from_value(chat_value)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if chat.messages.is_empty() {
+            continue;
+        }
+
+        let (export, media_files) = convert_chat(chat, &root);
+        exports.push(ConvertedExport { export, file_hash, media_files });
+    }
+
+    Ok(exports)
+}
+
+fn convert_chat(chat: TelegramChat, root: &str) -> (DiscordExport, Vec<String>) {
+    let chat_id = chat.id.to_string();
+    let mut media_files = BTreeSet::new();
+
+    let channel_type = match chat.chat_type.as_str() {
+        "personal_chat" | "bot_chat" | "saved_messages" => "DirectTextChat",
+        "public_channel" | "private_channel" => "GuildNews",
+        _ => "DirectGroupTextChat",
+    };
+
+    let messages = chat.messages
+        .into_iter()
+        .map(|msg| convert_message(msg, &chat_id, root, &mut media_files))
+        .collect();
+
+    let export = DiscordExport {
+        guild: Guild { id: "0".to_string(), name: "Telegram".to_string(), icon_url: None },
+        channel: Channel {
+            id: chat_id.clone(),
+            channel_type: channel_type.to_string(),
+            category_id: None,
+            category: None,
+            name: chat.name.unwrap_or_else(|| chat_id.clone()),
+            topic: None,
+        },
+        date_range: DateRange::default(),
+        exported_at: String::new(),
+        messages,
+    };
+
+    (export, media_files.into_iter().collect())
+}
+
+fn convert_message(
+    msg: TelegramMessage,
+    chat_id: &str,
+    root: &str,
+    media_files: &mut BTreeSet<String>
+) -> Message {
+    let is_service = msg.message_type == "service";
+    let (author_name, author_id) = if is_service {
+        (msg.actor.clone(), msg.actor_id.clone())
+    } else {
+        (msg.from.clone(), msg.from_id.clone())
+    };
+    let author_name = author_name.unwrap_or_else(|| "Deleted Account".to_string());
+    let author = Author {
+        id: author_id.unwrap_or_else(|| author_name.clone()),
+        name: author_name.clone(),
+        discriminator: "0000".to_string(),
+        nickname: author_name,
+        ..Default::default()
+    };
+
+    let entities = if msg.text_entities.is_empty() {
+        entities_from_text(&msg.text)
+    } else {
+        msg.text_entities.clone()
+    };
+    let mut content = entities_to_markdown(&entities);
+
+    let mentions = entities
+        .iter()
+        .filter(|e| e.kind == "mention_name")
+        .filter_map(|e| {
+            let user_id = match e.user_id.as_ref()? {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            Some(User {
+                id: format!("user{}", user_id),
+                name: e.text.clone(),
+                nickname: e.text.clone(),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    let message_type = match msg.action.as_deref() {
+        Some("pin_message") => "ChannelPinnedMessage",
+        Some("invite_members") | Some("join_group_by_link") => "RecipientAdd",
+        Some("remove_members") => "RecipientRemove",
+        Some("phone_call") | Some("group_call") => "Call",
+        Some("edit_group_title") => "ChannelNameChange",
+        Some("edit_group_photo") => "ChannelIconChange",
+        _ if !is_service && msg.reply_to_message_id.is_some() => "Reply",
+        _ => "Default",
+    };
+    if is_service && message_type == "Default" {
+        // No Discord equivalent; show the action as an italic line
+        if let Some(action) = &msg.action {
+            content = format!("*{}*", action.replace('_', " "));
+        }
+    }
+
+    let mut attachments = Vec::new();
+    let mut stickers = Vec::new();
+    let local_path = |path: &Option<String>| {
+        path.as_ref()
+            .filter(|p| !p.starts_with(NOT_INCLUDED_PREFIX))
+            .map(|p| format!("{}{}", root, p))
+    };
+
+    if let Some(photo) = local_path(&msg.photo) {
+        media_files.insert(photo.clone());
+        attachments.push(Attachment {
+            id: String::new(),
+            file_name: file_name_of(&photo),
+            url: photo,
+            file_size_bytes: msg.photo_file_size.unwrap_or(0),
+        });
+    }
+
+    if msg.media_type.as_deref() == Some("sticker") {
+        // Animated .tgs stickers can't be shown, fall back to their thumbnail
+        let source = local_path(&msg.file)
+            .filter(|f| !f.ends_with(".tgs"))
+            .or_else(|| local_path(&msg.thumbnail));
+        if let Some(source) = source {
+            media_files.insert(source.clone());
+            let format = if source.ends_with(".webm") { "Video" } else { "Png" };
+            stickers.push(Sticker {
+                name: msg.sticker_emoji.clone().unwrap_or_default(),
+                format: format.to_string(),
+                source_url: source,
+                ..Default::default()
+            });
+        }
+    } else if let Some(file) = local_path(&msg.file) {
+        media_files.insert(file.clone());
+        attachments.push(Attachment {
+            id: String::new(),
+            file_name: msg.file_name.clone().unwrap_or_else(|| file_name_of(&file)),
+            url: file,
+            file_size_bytes: msg.file_size.unwrap_or(0),
+        });
+    }
+
+    let reactions = msg.reactions
+        .iter()
+        .map(|r| Reaction {
+            emoji: Emoji {
+                name: r.emoji.clone().unwrap_or_default(),
+                code: r.emoji.clone().unwrap_or_default(),
+                ..Default::default()
+            },
+            count: r.count,
+            users: r.recent
+                .iter()
+                .map(|u| User {
+                    id: u.from_id.clone().unwrap_or_default(),
+                    name: u.from.clone().unwrap_or_default(),
+                    nickname: u.from.clone().unwrap_or_default(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        })
+        .collect();
+
+    Message {
+        id: msg.id.to_string(),
+        message_type: message_type.to_string(),
+        timestamp: telegram_timestamp(&msg.date_unixtime, &msg.date),
+        timestamp_edited: msg.edited
+            .as_deref()
+            .map(|edited| telegram_timestamp(msg.edited_unixtime.as_deref().unwrap_or(""), edited)),
+        content,
+        author,
+        attachments,
+        stickers,
+        reactions,
+        mentions,
+        reference: msg.reply_to_message_id.map(|id| MessageReference {
+            message_id: id.to_string(),
+            channel_id: Some(chat_id.to_string()),
+        }),
+        ..Default::default()
+    }
+}
+
+/// Older exports have no `text_entities`; `text` is a string or a list of strings and entities
+fn entities_from_text(text: &serde_json::Value) -> Vec<TextEntity> {
+    match text {
+        serde_json::Value::String(s) =>
+            vec![TextEntity { kind: "plain".to_string(), text: s.clone(), ..Default::default() }],
+        serde_json::Value::Array(parts) =>
+            parts
+                .iter()
+                .filter_map(|part| {
+                    match part {
+                        serde_json::Value::String(s) =>
+                            Some(TextEntity {
+                                kind: "plain".to_string(),
+                                text: s.clone(),
+                                ..Default::default()
+                            }),
+                        other => serde_json::from_value(other.clone()).ok(),
+                    }
+                })
+                .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Render Telegram text entities with the Discord markdown the viewer understands
+fn entities_to_markdown(entities: &[TextEntity]) -> String {
+    let mut content = String::new();
+    for entity in entities {
+        let text = &entity.text;
+        let rendered = match entity.kind.as_str() {
+            "bold" => format!("**{}**", text),
+            "italic" => format!("*{}*", text),
+            "underline" => format!("__{}__", text),
+            "strikethrough" => format!("~~{}~~", text),
+            "spoiler" => format!("||{}||", text),
+            "code" => format!("`{}`", text),
+            "pre" =>
+                format!("```{}\n{}\n```", entity.language.as_deref().unwrap_or(""), text),
+            "text_link" =>
+                match &entity.href {
+                    Some(href) => format!("[{}]({})", text, href),
+                    None => text.clone(),
+                }
+            "blockquote" =>
+                text
+                    .lines()
+                    .map(|line| format!("> {}", line))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            _ => text.clone(),
+        };
+        content.push_str(&rendered);
+    }
+    content
+}
+
+/// Prefer the unix timestamp; `date` is local time without an offset
+fn telegram_timestamp(unixtime: &str, date: &str) -> String {
+    if let Some(dt) = unixtime
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+    {
+        return dt.to_rfc3339();
+    }
+    chrono::NaiveDateTime
+        ::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+        .map(|naive| naive.and_utc().to_rfc3339())
+        .unwrap_or_else(|_| date.to_string())
+}
+
+fn file_name_of(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entities_to_markdown() {
+        let text = serde_json::json!([
+            "see ",
+            { "type": "bold", "text": "this" },
+            " and ",
+            { "type": "text_link", "text": "that", "href": "https://example.com" },
+        ]);
+        assert_eq!(
+            entities_to_markdown(&entities_from_text(&text)),
+            "see **this** and [that](https://example.com)"
+        );
+    }
+
+    #[test]
+    fn test_convert_reply_and_media() {
+        let msg: TelegramMessage = serde_json
+            ::from_value(
+                serde_json::json!({
+                "id": 2, "type": "message", "date": "2021-01-01T00:00:00", "date_unixtime": "1609459200",
+                "from": "Alice", "from_id": "user1", "reply_to_message_id": 1,
+                "photo": "photos/photo_1.jpg", "text": "hi"
+            })
+            )
+            .unwrap();
+        let mut media = BTreeSet::new();
+        let message = convert_message(msg, "42", "", &mut media);
+
+        assert_eq!(message.message_type, "Reply");
+        assert_eq!(message.reference.unwrap().message_id, "1");
+        assert_eq!(message.timestamp, "2021-01-01T00:00:00+00:00");
+        assert_eq!(message.attachments[0].url, "photos/photo_1.jpg");
+        assert!(media.contains("photos/photo_1.jpg"));
+    }
+}
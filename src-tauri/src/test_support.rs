@@ -0,0 +1,33 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{ Path, PathBuf };
+
+/// A new folder under the system temp folder, deleted with everything in it when dropped
+pub struct TempDir(PathBuf);
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Folder for one test; `name` only makes it easier to find while the test runs
+pub fn temp_dir(name: &str) -> TempDir {
+    let dir = std::env::temp_dir().join(format!("sapper-{}-{}", name, uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    TempDir(dir)
+}
@@ -0,0 +1,267 @@
+use crate::blob_store::BlobStore;
+use crate::durable;
+use crate::gc;
+use crate::message_storage::{ resolve_library_path, to_library_relative };
+use crate::models::{ ImportEntry, TrashedImport };
+use crate::sapper_core::SapperCore;
+use serde::{ Deserialize, Serialize };
+use std::fs;
+use std::io;
+use std::path::{ Component, Path, PathBuf };
+use tracing::{ info, warn };
+
+/// Deleted imports, each in a folder named by its ID
+pub const TRASH_DIR: &str = "trash";
+/// Kept inside a trashed import's folder, so the folder alone is enough to restore it
+const TRASH_RECORD_FILE: &str = "trash_entry.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrashRecord {
+    /// With paths relative to the library root, as in metadata.json
+    entry: ImportEntry,
+    deleted_at: String,
+}
+
+impl SapperCore {
+    fn trash_dir(&self) -> PathBuf {
+        self.sapper_dir.join(TRASH_DIR)
+    }
+
+    /// Move an import's folder to the trash and drop it from the import list. Its media
+    /// stays referenced in the blob store until it's purged.
+    pub(crate) fn move_to_trash(&self, entry: &ImportEntry) -> io::Result<()> {
+        let import_dir = PathBuf::from(&entry.import_path);
+        if !import_dir.is_dir() {
+            warn!("Import {} has no folder to keep, forgetting it", entry.id);
+            return self.forget_import(&entry.id);
+        }
+
+        let mut stored = entry.clone();
+        for path in [&mut stored.import_path, &mut stored.avatar_path] {
+            if let Some(relative) = to_library_relative(&self.sapper_dir, path) {
+                *path = relative;
+            }
+        }
+        let record = TrashRecord { entry: stored, deleted_at: chrono::Utc::now().to_rfc3339() };
+        let record_path = import_dir.join(TRASH_RECORD_FILE);
+        durable::write_json_atomic(&record_path, &record)?;
+
+        fs::create_dir_all(self.trash_dir())?;
+        let trashed_dir = self.trash_dir().join(&entry.id);
+        if let Err(e) = fs::rename(&import_dir, &trashed_dir) {
+            fs::remove_file(&record_path).ok();
+            return Err(e);
+        }
+
+        if let Err(e) = self.forget_import(&entry.id) {
+            // Put it back rather than leave it listed but gone
+            if fs::rename(&trashed_dir, &import_dir).is_ok() {
+                fs::remove_file(&record_path).ok();
+            }
+            return Err(e);
+        }
+
+        info!("Moved import {} to the trash", entry.id);
+        Ok(())
+    }
+
+    fn forget_import(&self, import_id: &str) -> io::Result<()> {
+        self.update_metadata(|metadata| {
+            metadata.imports.retain(|e| e.id != import_id);
+            Ok(())
+        })
+    }
+
+    /// Folders in the trash, whose media still counts as used
+    pub(crate) fn trash_folders(&self) -> io::Result<Vec<PathBuf>> {
+        let Ok(entries) = fs::read_dir(self.trash_dir()) else {
+            return Ok(Vec::new());
+        };
+        Ok(
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|path| path.is_dir())
+                .collect()
+        )
+    }
+
+    /// Folder of a trashed import. IDs come from the frontend, so one that isn't a plain
+    /// folder name (empty, "..", containing a separator) is refused rather than joined.
+    fn trashed_dir(&self, import_id: &str) -> io::Result<PathBuf> {
+        let mut components = Path::new(import_id).components();
+        let is_name = matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        if !is_name || import_id.contains(['/', '\\']) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid import ID {:?}", import_id)));
+        }
+        Ok(self.trash_dir().join(import_id))
+    }
+
+    fn load_trash_record(&self, trashed_dir: &Path) -> io::Result<TrashRecord> {
+        let contents = fs::read_to_string(trashed_dir.join(TRASH_RECORD_FILE))?;
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Deleted imports, most recently deleted first
+    pub fn list_trash(&self) -> io::Result<Vec<TrashedImport>> {
+        let retention_days = self.load_config()?.trash_retention_days;
+
+        let mut trashed = Vec::new();
+        for trashed_dir in self.trash_folders()? {
+            let record = match self.load_trash_record(&trashed_dir) {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!("Skipping unreadable trash entry {:?}: {}", trashed_dir.file_name(), e);
+                    continue;
+                }
+            };
+
+            let purge_at = (retention_days > 0)
+                .then(|| chrono::DateTime::parse_from_rfc3339(&record.deleted_at).ok())
+                .flatten()
+                .map(|deleted_at| (deleted_at + chrono::Duration::days(retention_days.into())).to_rfc3339());
+            let size_bytes = gc::dir_stats(&trashed_dir).1.saturating_sub(BlobStore::linked_bytes(&trashed_dir));
+
+            trashed.push(TrashedImport {
+                entry: record.entry,
+                deleted_at: record.deleted_at,
+                purge_at,
+                size_bytes,
+            });
+        }
+
+        trashed.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+        Ok(trashed)
+    }
+
+    /// Move a deleted import back into the library
+    pub fn restore_import(&self, import_id: &str) -> io::Result<ImportEntry> {
+        let trashed_dir = self.trashed_dir(import_id)?;
+        let record = self.load_trash_record(&trashed_dir)?;
+
+        let import_dir = self.sapper_dir.join("imports").join(import_id);
+        if import_dir.exists() {
+            return Err(
+                io::Error::new(io::ErrorKind::AlreadyExists, "A conversation with this ID is already in the library")
+            );
+        }
+
+        let mut entry = record.entry;
+        entry.import_path = import_dir.to_string_lossy().to_string();
+        entry.avatar_path = resolve_library_path(&self.sapper_dir, &entry.avatar_path);
+        // Moved back and listed in one step, so the folder never looks orphaned
+        self.update_metadata(|metadata| {
+            fs::rename(&trashed_dir, &import_dir)?;
+            metadata.imports.push(entry.clone());
+            Ok(())
+        })?;
+        fs::remove_file(import_dir.join(TRASH_RECORD_FILE))?;
+
+        if let Err(e) = self.link_threads(&entry.id) {
+            warn!("Failed to link threads: {}", e);
+        }
+        info!("Restored import {} from the trash", entry.id);
+        Ok(entry)
+    }
+
+    /// Permanently delete one trashed import, or all of them. Returns how many were deleted.
+    pub fn purge_trash(&self, import_id: Option<&str>) -> io::Result<usize> {
+        let folders = match import_id {
+            Some(id) => {
+                let trashed_dir = self.trashed_dir(id)?;
+                if !trashed_dir.is_dir() {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "Not in the trash"));
+                }
+                vec![trashed_dir]
+            }
+            None => self.trash_folders()?,
+        };
+
+        let blob_store = BlobStore::new(&self.sapper_dir);
+        for trashed_dir in &folders {
+            blob_store.release_import(trashed_dir)?;
+            fs::remove_dir_all(trashed_dir)?;
+        }
+        info!("Purged {} imports from the trash", folders.len());
+        Ok(folders.len())
+    }
+
+    /// Purge trashed imports older than the retention period. Returns how many were purged.
+    pub fn purge_expired_trash(&self) -> io::Result<usize> {
+        let retention_days = self.load_config()?.trash_retention_days;
+        if retention_days == 0 {
+            return Ok(0);
+        }
+
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(retention_days.into());
+        let mut purged = 0;
+        for trashed_dir in self.trash_folders()? {
+            let Ok(record) = self.load_trash_record(&trashed_dir) else {
+                continue;
+            };
+            let expired = chrono::DateTime
+                ::parse_from_rfc3339(&record.deleted_at)
+                .is_ok_and(|deleted_at| deleted_at < cutoff);
+            if expired {
+                self.purge_trash(Some(&record.entry.id))?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blob_store::BlobStore;
+    use crate::sapper_core::tests::import_test_export;
+    use crate::test_support::temp_dir;
+    use crate::sapper_core::SapperCore;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    fn refcounts(library: &Path) -> BTreeMap<String, u64> {
+        serde_json::from_str(&fs::read_to_string(library.join("blobs/refcounts.json")).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_trash_restore_and_purge() {
+        let dir = temp_dir("trash");
+        let library = dir.join("library");
+        let core = SapperCore::open(library.clone()).unwrap();
+        let kept = import_test_export(&core, &dir, "kept", &[("media/cat.png", b"cat")]);
+        let deleted = import_test_export(&core, &dir, "deleted", &[("media/cat.png", b"cat")]);
+        let manifest = BlobStore::load_manifest(Path::new(&deleted.import_path)).unwrap();
+        let blob = manifest.files["media/cat.png"].clone();
+        assert_eq!(refcounts(&library)[&blob], 2);
+
+        core.delete_import(&deleted.id).unwrap();
+        let trash = core.list_trash().unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].entry.id, deleted.id);
+        assert!(core.load_metadata().unwrap().imports.iter().all(|e| e.id != deleted.id));
+        // Still counted while it can be restored
+        assert_eq!(refcounts(&library)[&blob], 2);
+
+        let restored = core.restore_import(&deleted.id).unwrap();
+        assert_eq!(restored.import_path, deleted.import_path);
+        assert!(core.list_trash().unwrap().is_empty());
+        assert!(Path::new(&deleted.import_path).join("attachments/media/cat.png").exists());
+
+        core.delete_import(&deleted.id).unwrap();
+        for bad_id in ["", "..", "../imports", "a/b"] {
+            assert_eq!(core.purge_trash(Some(bad_id)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(core.restore_import(bad_id).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(core.list_trash().unwrap().len(), 1);
+
+        assert_eq!(core.purge_trash(Some(&deleted.id)).unwrap(), 1);
+        assert!(core.list_trash().unwrap().is_empty());
+        assert_eq!(refcounts(&library)[&blob], 1);
+        assert!(BlobStore::new(&library).blob_path(&blob).exists());
+        assert!(Path::new(&kept.import_path).join("attachments/media/cat.png").exists());
+    }
+}
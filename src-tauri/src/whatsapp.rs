@@ -0,0 +1,358 @@
+use crate::models::*;
+use crate::package_reader::PackageReader;
+use crate::sapper_core::{ hash_bytes, ConvertedExport };
+use regex::Regex;
+use std::collections::{ BTreeSet, HashSet };
+use std::io;
+use std::path::Path;
+use tracing::instrument;
+
+/// "[31/12/20, 23:59:59] " (iOS) or "31/12/2020, 23:59 - " (Android); locales vary the
+/// separators, field order, seconds and AM/PM suffix
+const HEADER_PATTERN: &str = r"^(?:\[(?P<d1>\d{1,4})[./-](?P<d2>\d{1,2})[./-](?P<d3>\d{1,4}),?\s+(?P<time>\d{1,2}[:.]\d{2}(?:[:.]\d{2})?)\s*(?P<ampm>[AaPp]\.?\s?[Mm]\.?)?\]\s|(?P<a1>\d{1,4})[./-](?P<a2>\d{1,2})[./-](?P<a3>\d{1,4}),?\s+(?P<atime>\d{1,2}[:.]\d{2}(?:[:.]\d{2})?)\s*(?P<aampm>[AaPp]\.?\s?[Mm]\.?)?\s[-–]\s)";
+
+/// iOS: "<attached: 00000012-PHOTO-2020-12-31-23-59-59.jpg>"
+const IOS_ATTACHMENT_PATTERN: &str = r"<attached: ([^>]+)>";
+
+/// Android: "IMG-20201231-WA0001.jpg (file attached)"
+const ANDROID_ATTACHMENT_PATTERN: &str = r"(?m)^(\S[^\n]*?\.\w+) \(file attached\)$";
+
+const EDITED_MARKER: &str = "<This message was edited>";
+const SYSTEM_AUTHOR_ID: &str = "whatsapp-system";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+/// A header line split into its parts, before the date order is known
+struct RawLine {
+    date: (u32, u32, u32),
+    time: String,
+    ampm: Option<String>,
+    body: String,
+}
+
+/// Read a WhatsApp "Export chat" folder or zip. `chat_name_hint` is the name of what the
+/// user picked (zip, folder or the chat .txt itself) and is used to name the chat.
+#[instrument(skip_all)]
+pub fn read_whatsapp_export(reader: &PackageReader, chat_name_hint: &str) -> io::Result<ConvertedExport> {
+    let file_names = reader.file_names();
+    let chat_path = file_names
+        .iter()
+        .find(|n| n.as_str() == chat_name_hint)
+        .or_else(|| file_names.iter().find(|n| n.rsplit('/').next() == Some("_chat.txt")))
+        .or_else(|| file_names.iter().find(|n| n.to_lowercase().ends_with(".txt")))
+        .cloned()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No WhatsApp chat .txt found"))?;
+    let raw = reader.read_bytes(&chat_path)?;
+    let text = String::from_utf8_lossy(&raw);
+
+    // Media sits next to the chat file
+    let root = chat_path
+        .rsplit_once('/')
+        .map(|(dir, _)| format!("{}/", dir))
+        .unwrap_or_default();
+    let available: HashSet<&str> = file_names
+        .iter()
+        .map(|n| n.as_str())
+        .collect();
+
+    let chat_name = chat_name_from(chat_name_hint, &chat_path);
+    let mut media_files = BTreeSet::new();
+    let messages = parse_chat(&text, &root, &available, &mut media_files);
+    if messages.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "No WhatsApp messages found"));
+    }
+
+    let authors: HashSet<&str> = messages
+        .iter()
+        .filter(|m| m.author.id != SYSTEM_AUTHOR_ID)
+        .map(|m| m.author.id.as_str())
+        .collect();
+    let channel_type = if authors.len() <= 2 { "DirectTextChat" } else { "DirectGroupTextChat" };
+
+    let export = DiscordExport {
+        guild: Guild { id: "0".to_string(), name: "WhatsApp".to_string(), icon_url: None },
+        channel: Channel {
+            id: hash_bytes(chat_name.as_bytes()),
+            channel_type: channel_type.to_string(),
+            category_id: None,
+            category: None,
+            name: chat_name,
+            topic: None,
+        },
+        date_range: DateRange::default(),
+        exported_at: String::new(),
+        messages,
+    };
+
+    Ok(ConvertedExport {
+        export,
+        file_hash: hash_bytes(&raw),
+        media_files: media_files.into_iter().collect(),
+    })
+}
+
+/// "WhatsApp Chat - Alice.zip" / "WhatsApp Chat with Alice.txt" -> "Alice"
+fn chat_name_from(hint: &str, chat_path: &str) -> String {
+    let file_stem = |name: &str| {
+        Path::new(name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(name)
+            .to_string()
+    };
+    let mut name = file_stem(hint);
+    if name.is_empty() || name == "_chat" {
+        name = file_stem(chat_path);
+    }
+    for prefix in ["WhatsApp Chat - ", "WhatsApp Chat with ", "WhatsApp-Chat mit "] {
+        if let Some(stripped) = name.strip_prefix(prefix) {
+            return stripped.to_string();
+        }
+    }
+    name
+}
+
+fn parse_chat(
+    text: &str,
+    root: &str,
+    available: &HashSet<&str>,
+    media_files: &mut BTreeSet<String>
+) -> Vec<Message> {
+    let header_regex = Regex::new(HEADER_PATTERN).unwrap();
+    let attachment_regexes = [
+        Regex::new(IOS_ATTACHMENT_PATTERN).unwrap(),
+        Regex::new(ANDROID_ATTACHMENT_PATTERN).unwrap(),
+    ];
+    let mut lines: Vec<RawLine> = Vec::new();
+
+    for line in text.lines() {
+        // Exports are sprinkled with direction marks and narrow no-break spaces.
+        // Marks inside the text are kept: iOS uses them to flag system lines.
+        let line: String = line
+            .trim_start_matches(['\u{200e}', '\u{200f}', '\u{feff}'])
+            .chars()
+            .map(|c| if c == '\u{202f}' || c == '\u{a0}' { ' ' } else { c })
+            .collect();
+
+        match header_regex.captures(&line) {
+            Some(caps) => {
+                let get = |a: &str, b: &str| {
+                    caps.name(a)
+                        .or_else(|| caps.name(b))
+                        .map(|m| m.as_str().to_string())
+                };
+                let num = |a: &str, b: &str| {
+                    get(a, b)
+                        .and_then(|s| s.parse::<u32>().ok())
+                        .unwrap_or(0)
+                };
+                lines.push(RawLine {
+                    date: (num("d1", "a1"), num("d2", "a2"), num("d3", "a3")),
+                    time: get("time", "atime").unwrap_or_default(),
+                    ampm: get("ampm", "aampm"),
+                    body: line[caps.get(0).unwrap().end()..].to_string(),
+                });
+            }
+            None => {
+                // Continuation of a multi-line message
+                if let Some(last) = lines.last_mut() {
+                    last.body.push('\n');
+                    last.body.push_str(&line);
+                }
+            }
+        }
+    }
+
+    let order = detect_date_order(&lines);
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(idx, line)| {
+            convert_line(idx, line, order, root, available, &attachment_regexes, media_files)
+        })
+        .collect()
+}
+
+/// Decide day/month order from values that can only be a day (> 12)
+fn detect_date_order(lines: &[RawLine]) -> DateOrder {
+    if lines.iter().any(|l| l.date.0 > 31) {
+        return DateOrder::YearMonthDay;
+    }
+    if lines.iter().any(|l| l.date.0 > 12) {
+        return DateOrder::DayMonthYear;
+    }
+    if lines.iter().any(|l| l.date.1 > 12) {
+        return DateOrder::MonthDayYear;
+    }
+    // Still ambiguous: 12-hour clocks are mostly US locales
+    if lines.iter().any(|l| l.ampm.is_some()) {
+        DateOrder::MonthDayYear
+    } else {
+        DateOrder::DayMonthYear
+    }
+}
+
+fn line_timestamp(line: &RawLine, order: DateOrder) -> String {
+    let (a, b, c) = line.date;
+    let (year, month, day) = match order {
+        DateOrder::DayMonthYear => (c, b, a),
+        DateOrder::MonthDayYear => (c, a, b),
+        DateOrder::YearMonthDay => (a, b, c),
+    };
+    let year = if year < 100 { year + 2000 } else { year };
+
+    let mut parts = line.time.split([':', '.']).map(|p| p.parse::<u32>().unwrap_or(0));
+    let mut hour = parts.next().unwrap_or(0);
+    let minute = parts.next().unwrap_or(0);
+    let second = parts.next().unwrap_or(0);
+    if let Some(ampm) = &line.ampm {
+        let pm = ampm.to_lowercase().starts_with('p');
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    // WhatsApp writes local time without an offset
+    chrono::NaiveDate
+        ::from_ymd_opt(year as i32, month, day)
+        .and_then(|d| d.and_hms_opt(hour, minute, second))
+        .map(|dt| dt.and_utc().to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn convert_line(
+    idx: usize,
+    line: RawLine,
+    order: DateOrder,
+    root: &str,
+    available: &HashSet<&str>,
+    attachment_regexes: &[Regex],
+    media_files: &mut BTreeSet<String>
+) -> Message {
+    let timestamp = line_timestamp(&line, order);
+
+    // Android system lines ("Alice added Bob") have no "Name: " prefix; iOS ones are
+    // attributed to the chat and start with a direction mark
+    let (author_name, content, is_system) = match line.body.split_once(": ") {
+        Some((name, text)) if !name.contains('\n') => {
+            let marked = text.starts_with('\u{200e}') && !text.contains("<attached:");
+            (name.to_string(), text.to_string(), marked)
+        }
+        _ => ("WhatsApp".to_string(), line.body.clone(), true),
+    };
+    let mut content = content.replace(['\u{200e}', '\u{200f}'], "");
+
+    let mut timestamp_edited = None;
+    if let Some(stripped) = content.trim_end().strip_suffix(EDITED_MARKER) {
+        content = stripped.trim_end().to_string();
+        timestamp_edited = Some(timestamp.clone());
+    }
+
+    let mut attachment_names: Vec<String> = Vec::new();
+    for re in attachment_regexes {
+        attachment_names.extend(re.captures_iter(&content).map(|c| c[1].trim().to_string()));
+        content = re.replace_all(&content, "").trim().to_string();
+    }
+
+    let attachments = attachment_names
+        .into_iter()
+        .map(|name| {
+            let path = format!("{}{}", root, name);
+            if available.contains(path.as_str()) {
+                media_files.insert(path.clone());
+            }
+            Attachment {
+                id: String::new(),
+                url: path,
+                file_name: name,
+                file_size_bytes: 0,
+            }
+        })
+        .collect();
+
+    let author = if is_system {
+        Author {
+            id: SYSTEM_AUTHOR_ID.to_string(),
+            name: "WhatsApp".to_string(),
+            nickname: "WhatsApp".to_string(),
+            is_bot: true,
+            ..Default::default()
+        }
+    } else {
+        // No user IDs in the export, the display name is all there is
+        Author {
+            id: author_name.clone(),
+            name: author_name.clone(),
+            discriminator: "0000".to_string(),
+            nickname: author_name,
+            ..Default::default()
+        }
+    };
+
+    Message {
+        id: (idx + 1).to_string(),
+        message_type: "Default".to_string(),
+        timestamp,
+        timestamp_edited,
+        content: if is_system { format!("*{}*", content) } else { content },
+        author,
+        attachments,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> (Vec<Message>, BTreeSet<String>) {
+        let available: HashSet<&str> = ["IMG-20201231-WA0001.jpg", "00000012-PHOTO.jpg"]
+            .into_iter()
+            .collect();
+        let mut media = BTreeSet::new();
+        let messages = parse_chat(text, "", &available, &mut media);
+        (messages, media)
+    }
+
+    #[test]
+    fn test_ios_format() {
+        let (messages, media) = parse(
+            "[31.12.20, 23:59:58] Group: \u{200e}Messages and calls are end-to-end encrypted.\n\
+             [31.12.20, 23:59:59] Alice: first line\nsecond line\n\
+             [01.01.21, 00:00:01] Bob: \u{200e}<attached: 00000012-PHOTO.jpg>"
+        );
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].content, "first line\nsecond line");
+        assert_eq!(messages[1].timestamp, "2020-12-31T23:59:59+00:00");
+        assert_eq!(messages[2].attachments[0].file_name, "00000012-PHOTO.jpg");
+        assert!(messages[2].content.is_empty());
+        assert!(media.contains("00000012-PHOTO.jpg"));
+    }
+
+    #[test]
+    fn test_android_format() {
+        let (messages, media) = parse(
+            "12/31/20, 11:59\u{202f}PM - Alice added Bob\n\
+             12/31/20, 11:59\u{202f}PM - Bob: IMG-20201231-WA0001.jpg (file attached)\ncaption\n\
+             1/1/21, 9:05 AM - Alice: hello <This message was edited>"
+        );
+        assert_eq!(messages[0].author.id, SYSTEM_AUTHOR_ID);
+        assert_eq!(messages[1].timestamp, "2020-12-31T23:59:00+00:00");
+        assert_eq!(messages[1].content, "caption");
+        assert!(media.contains("IMG-20201231-WA0001.jpg"));
+        assert_eq!(messages[2].content, "hello");
+        assert!(messages[2].timestamp_edited.is_some());
+    }
+
+    #[test]
+    fn test_chat_name() {
+        assert_eq!(chat_name_from("WhatsApp Chat - Alice.zip", "_chat.txt"), "Alice");
+        assert_eq!(chat_name_from("", "WhatsApp Chat with Bob.txt"), "Bob");
+    }
+}
@@ -145,6 +145,7 @@ function AppContent() {
       await checkChangelog();
     } catch (error) {
       console.error("Failed to initialize:", error);
+      toast.error(`Failed to open library: ${error}`);
     } finally {
       setLoading(false);
     }
@@ -250,15 +251,25 @@ function AppContent() {
         multiple: true,
         filters: [
           {
-            name: "JSON",
-            extensions: ["json"],
+            name: "Exports",
+            extensions: ["json", "zip"],
           },
         ],
       });
 
       if (!selected || selected.length === 0) return;
 
-      const paths = Array.isArray(selected) ? selected : [selected];
+      const selectedPaths = Array.isArray(selected) ? selected : [selected];
+
+      // Expand zip archives into the exports they contain
+      const paths = [];
+      for (const path of selectedPaths) {
+        if (path.toLowerCase().endsWith(".zip")) {
+          paths.push(...(await invoke("list_archive_exports", { path })));
+        } else {
+          paths.push(path);
+        }
+      }
 
       const filesWithPreviews = [];
       for (const path of paths) {
@@ -758,7 +769,7 @@ function AppContent() {
         isOpen={!!confirmDeleteImport}
         onClose={() => setConfirmDeleteImport(null)}
         title="Unimport Conversation"
-        message="Are you sure you want to unimport this conversation? The original folder you imported from will remain, but our copy will be permanently erased."
+        message="Are you sure you want to unimport this conversation? It will be moved to the trash, where you can restore it from Settings until it is deleted."
         confirmText="Unimport"
         cancelText="Cancel"
         onConfirm={confirmDelete}
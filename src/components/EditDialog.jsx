@@ -242,7 +242,7 @@ function EditDialog({ importEntry, onClose, onSave, onUnimport }) {
         isOpen={confirmDialogOpen}
         onClose={() => setConfirmDialogOpen(false)}
         title="Unimport Conversation"
-        message="Are you sure you want to unimport this conversation? The original folder you imported from will remain, but our copy will be permanently erased."
+        message="Are you sure you want to unimport this conversation? It will be moved to the trash, where you can restore it from Settings until it is deleted."
         confirmText="Unimport"
         cancelText="Cancel"
         onConfirm={confirmUnimport}
@@ -247,6 +247,8 @@ function MessageAttachments({ mediaRefs, onImageClick, directImageUrl, directIsV
 
   function getAttachmentUrl(filePath) {
     if (!filePath) return "";
+    // Imports without downloaded media keep the remote URL
+    if (/^https?:\/\//.test(filePath)) return filePath;
     return convertFileSrc(filePath);
   }
 
@@ -1,5 +1,5 @@
 import { useState, useEffect } from "react";
-import { X, FolderOpen, Download, Upload, FileText, Bell, AlertTriangle, Bug, Settings2, Palette, Database, Wrench, HardDrive, ChevronRight, Trash2 } from "lucide-react";
+import { X, FolderOpen, Download, Upload, FileText, Bell, AlertTriangle, Bug, Settings2, Palette, Database, Wrench, HardDrive, ChevronRight, Trash2, RotateCcw } from "lucide-react";
 import { themes, saveTheme } from "../themes";
 import { invoke } from "@tauri-apps/api/core";
 import { openPath } from "@tauri-apps/plugin-opener";
@@ -26,10 +26,21 @@ function Settings({ isOpen, onClose, currentTheme, onThemeChange, imports, onImp
   const [appVersion, setAppVersion] = useState("");
   const [notificationsEnabled, setNotificationsEnabled] = useState(true);
   const [skipLargeImportWarning, setSkipLargeImportWarning] = useState(false);
+  const [useSqliteStorage, setUseSqliteStorage] = useState(false);
   const [diskUsage, setDiskUsage] = useState(null);
   const [loadingDiskUsage, setLoadingDiskUsage] = useState(false);
   const [clearingLogs, setClearingLogs] = useState(false);
   const [confirmClearLogs, setConfirmClearLogs] = useState(false);
+  const [libraries, setLibraries] = useState(null);
+  const [integrityReport, setIntegrityReport] = useState(null);
+  const [checkingLibrary, setCheckingLibrary] = useState(false);
+  const [switchingLibrary, setSwitchingLibrary] = useState(false);
+  const [trash, setTrash] = useState(null);
+  const [trashRetentionDays, setTrashRetentionDays] = useState(30);
+  const [confirmEmptyTrash, setConfirmEmptyTrash] = useState(false);
+  const [garbageReport, setGarbageReport] = useState(null);
+  const [collectingGarbage, setCollectingGarbage] = useState(false);
+  const [compacting, setCompacting] = useState(false);
 
   useEffect(() => {
     const fetchSettings = async () => {
@@ -39,6 +50,8 @@ function Settings({ isOpen, onClose, currentTheme, onThemeChange, imports, onImp
         const config = await invoke("get_config");
         setNotificationsEnabled(config.notificationsEnabled ?? true);
         setSkipLargeImportWarning(config.skipLargeImportWarning ?? false);
+        setUseSqliteStorage(config.storageBackend === "sqlite");
+        setTrashRetentionDays(config.trashRetentionDays ?? 30);
       } catch (error) {
         console.error("Failed to fetch settings:", error);
       }
@@ -48,6 +61,9 @@ function Settings({ isOpen, onClose, currentTheme, onThemeChange, imports, onImp
       fetchSettings();
     } else {
       setConfirmClearLogs(false);
+      setConfirmEmptyTrash(false);
+      setTrash(null);
+      setGarbageReport(null);
     }
   }, [isOpen]);
 
@@ -55,8 +71,198 @@ function Settings({ isOpen, onClose, currentTheme, onThemeChange, imports, onImp
     if (isOpen && activeTab === "data" && dataSubTab === "storage" && !diskUsage) {
       fetchDiskUsage();
     }
+    if (isOpen && activeTab === "data" && dataSubTab === "storage" && !libraries) {
+      fetchLibraries();
+    }
+    if (isOpen && activeTab === "data" && dataSubTab === "storage" && !trash) {
+      fetchTrash();
+    }
   }, [isOpen, activeTab, dataSubTab]);
 
+  const fetchLibraries = async () => {
+    try {
+      setLibraries(await invoke("get_libraries"));
+    } catch (error) {
+      console.error("Failed to fetch libraries:", error);
+    }
+  };
+
+  const handleSwitchLibrary = async (path) => {
+    if (switchingLibrary || path === libraries?.active) return;
+    setSwitchingLibrary(true);
+    try {
+      await invoke("switch_library", { path });
+      toast.success("Switched library");
+      setDiskUsage(null);
+      fetchDiskUsage();
+      await fetchLibraries();
+      if (onImportComplete) {
+        onImportComplete();
+      }
+    } catch (error) {
+      console.error("Failed to switch library:", error);
+      toast.error(`Failed to switch library: ${error}`);
+    }
+    setSwitchingLibrary(false);
+  };
+
+  const handleAddLibrary = async () => {
+    try {
+      const selected = await openDialog({
+        directory: true,
+        multiple: false,
+        title: "Select a folder for the library",
+      });
+      if (!selected) return;
+      await invoke("add_library", { path: selected, name: null });
+      await fetchLibraries();
+    } catch (error) {
+      console.error("Failed to add library:", error);
+      toast.error(`Failed to add library: ${error}`);
+    }
+  };
+
+  const handleVerifyLibrary = async () => {
+    setCheckingLibrary(true);
+    try {
+      setIntegrityReport(await invoke("verify_library"));
+    } catch (error) {
+      console.error("Failed to verify library:", error);
+      toast.error(`Failed to verify library: ${error}`);
+    }
+    setCheckingLibrary(false);
+  };
+
+  const handleRepairLibrary = async () => {
+    const repairs = integrityReport.issues
+      .filter((issue) => issue.repair)
+      .map((issue) => ({ action: issue.repair, importId: issue.importId }));
+    setCheckingLibrary(true);
+    try {
+      const report = await invoke("repair_library", { repairs });
+      setIntegrityReport(report);
+      if (report.issues.length === 0) {
+        toast.success("Library repaired");
+      } else {
+        toast.info(`${report.issues.length} issue(s) remain`);
+      }
+      if (onImportComplete) {
+        onImportComplete();
+      }
+    } catch (error) {
+      console.error("Failed to repair library:", error);
+      toast.error(`Failed to repair library: ${error}`);
+    }
+    setCheckingLibrary(false);
+  };
+
+  const fetchTrash = async () => {
+    try {
+      setTrash(await invoke("get_trash"));
+    } catch (error) {
+      console.error("Failed to fetch trash:", error);
+    }
+  };
+
+  const handleRestoreImport = async (importId) => {
+    try {
+      await invoke("restore_import", { importId });
+      toast.success("Conversation restored");
+      await fetchTrash();
+      fetchDiskUsage();
+      if (onImportComplete) {
+        onImportComplete();
+      }
+    } catch (error) {
+      console.error("Failed to restore conversation:", error);
+      toast.error(`Failed to restore conversation: ${error}`);
+    }
+  };
+
+  const handlePurgeTrash = async (importId) => {
+    if (!importId && !confirmEmptyTrash) {
+      setConfirmEmptyTrash(true);
+      return;
+    }
+    setConfirmEmptyTrash(false);
+    try {
+      await invoke("purge_trash", { importId });
+      await fetchTrash();
+      fetchDiskUsage();
+    } catch (error) {
+      console.error("Failed to purge trash:", error);
+      toast.error(`Failed to delete: ${error}`);
+    }
+  };
+
+  const handleCollectGarbage = async (dryRun) => {
+    setCollectingGarbage(true);
+    try {
+      const report = await invoke("collect_garbage", { dryRun });
+      if (dryRun) {
+        setGarbageReport(report);
+      } else {
+        setGarbageReport(null);
+        toast.success(`Reclaimed ${formatBytes(report.totalBytes)}`);
+        fetchDiskUsage();
+      }
+    } catch (error) {
+      console.error("Failed to collect garbage:", error);
+      toast.error(`Failed to clean up: ${error}`);
+    }
+    setCollectingGarbage(false);
+  };
+
+  const handleCompactStorage = async () => {
+    setCompacting(true);
+    try {
+      const reports = await invoke("compact_imports", { importId: null, renumber: false });
+      const removed = reports.reduce((sum, report) => sum + report.chunksBefore - report.chunksAfter, 0);
+      const renumbered = reports.filter((report) => report.renumberedMessages > 0).length;
+      if (removed > 0 || renumbered > 0) {
+        toast.success(`Compacted ${reports.length} conversations, ${removed} fewer chunks`);
+      } else {
+        toast.success("Message storage is already compact");
+      }
+      if (onImportComplete) {
+        onImportComplete();
+      }
+    } catch (error) {
+      console.error("Failed to compact message storage:", error);
+      toast.error(`Failed to compact: ${error}`);
+    }
+    setCompacting(false);
+  };
+
+  const garbageLabels = {
+    orphanImportFolder: "Leftover import folders",
+    staleTempFile: "Unfinished writes",
+    unusedCache: "Unused cache",
+    unreferencedAttachment: "Media no message uses",
+  };
+
+  const handleTrashRetentionChange = async (days) => {
+    setTrashRetentionDays(days);
+    try {
+      const config = await invoke("get_config");
+      config.trashRetentionDays = days;
+      await invoke("update_config", { config });
+      await fetchTrash();
+    } catch (err) {
+      console.error("Failed to save trash retention setting:", err);
+    }
+  };
+
+  const handleRemoveLibrary = async (path) => {
+    try {
+      await invoke("remove_library", { path });
+      await fetchLibraries();
+    } catch (error) {
+      console.error("Failed to remove library:", error);
+      toast.error(`Failed to remove library: ${error}`);
+    }
+  };
+
   const handleClearLogs = async () => {
     if (!confirmClearLogs) {
       setConfirmClearLogs(true);
@@ -349,6 +555,16 @@ function Settings({ isOpen, onClose, currentTheme, onThemeChange, imports, onImp
             <span>Conversations</span>
             <span>{formatBytes(diskUsage.importsBytes)}</span>
           </div>
+          <div className="disk-breakdown-row">
+            <span>Shared media</span>
+            <span>{formatBytes(diskUsage.blobsBytes)}</span>
+          </div>
+          {diskUsage.trashBytes > 0 && (
+            <div className="disk-breakdown-row">
+              <span>Trash</span>
+              <span>{formatBytes(diskUsage.trashBytes)}</span>
+            </div>
+          )}
           <div className="disk-breakdown-row">
             <span>Cache</span>
             <span>{formatBytes(diskUsage.cacheBytes)}</span>
@@ -421,6 +637,56 @@ function Settings({ isOpen, onClose, currentTheme, onThemeChange, imports, onImp
 
       {dataSubTab === "storage" && (
         <>
+          <div className="settings-section">
+            <h3>Library</h3>
+            <p className="settings-description">
+              Conversations are stored in a library folder. Keep separate libraries, for example
+              an archive on an external drive, and switch between them here.
+            </p>
+            {libraries && libraries.source !== "config" && libraries.source !== "default" && (
+              <p className="settings-description">
+                {libraries.source === "portable"
+                  ? "Portable mode: the library is stored next to the app."
+                  : `Sapper was started with a library chosen by ${libraries.source === "cli" ? "the --library flag" : "SAPPER_LIBRARY"}.`}
+              </p>
+            )}
+            {libraries && (
+              <div className="export-mode-selector">
+                {libraries.libraries.map((lib) => (
+                  <label key={lib.path} className="export-mode-option library-option">
+                    <input
+                      type="radio"
+                      name="library"
+                      checked={lib.path === libraries.active}
+                      disabled={switchingLibrary}
+                      onChange={() => handleSwitchLibrary(lib.path)}
+                    />
+                    <span>
+                      {lib.name}
+                      <span className="library-path">{lib.path}</span>
+                    </span>
+                    {lib.path !== libraries.active && (
+                      <button
+                        className="library-remove-button"
+                        title="Forget this library (its files are kept)"
+                        onClick={(e) => {
+                          e.preventDefault();
+                          handleRemoveLibrary(lib.path);
+                        }}
+                      >
+                        <Trash2 size={14} />
+                      </button>
+                    )}
+                  </label>
+                ))}
+              </div>
+            )}
+            <button className="open-logs-button" onClick={handleAddLibrary}>
+              <FolderOpen size={18} />
+              Add Library
+            </button>
+          </div>
+
           <div className="settings-section">
             <h3>Import Behavior</h3>
             <div className="settings-toggle-list">
@@ -450,13 +716,153 @@ function Settings({ isOpen, onClose, currentTheme, onThemeChange, imports, onImp
                   }}
                 />
               </label>
+              <label className="settings-toggle">
+                <div className="toggle-info">
+                  <Database size={18} />
+                  <div>
+                    <span className="toggle-label">Store New Imports in SQLite</span>
+                    <span className="toggle-description">
+                      Faster edits and date jumps in very large conversations
+                    </span>
+                  </div>
+                </div>
+                <input
+                  type="checkbox"
+                  checked={useSqliteStorage}
+                  onChange={async (e) => {
+                    const val = e.target.checked;
+                    setUseSqliteStorage(val);
+                    try {
+                      const config = await invoke("get_config");
+                      config.storageBackend = val ? "sqlite" : "json";
+                      await invoke("update_config", { config });
+                    } catch (err) {
+                      console.error("Failed to save storage backend setting:", err);
+                    }
+                  }}
+                />
+              </label>
             </div>
           </div>
 
+          <div className="settings-section">
+            <h3>Trash</h3>
+            <p className="settings-description">
+              Unimported conversations are kept here so they can be restored
+            </p>
+            <div className="export-mode-selector">
+              {[
+                [7, "Delete after 7 days"],
+                [30, "Delete after 30 days"],
+                [90, "Delete after 90 days"],
+                [0, "Keep until the trash is emptied"],
+              ].map(([days, label]) => (
+                <label key={days} className="export-mode-option">
+                  <input
+                    type="radio"
+                    name="trashRetention"
+                    checked={trashRetentionDays === days}
+                    onChange={() => handleTrashRetentionChange(days)}
+                  />
+                  <span>{label}</span>
+                </label>
+              ))}
+            </div>
+            {trash && trash.length > 0 && (
+              <div className="trash-list">
+                {trash.map((item) => (
+                  <div key={item.entry.id} className="trash-item">
+                    <div className="trash-item-info">
+                      <span className="disk-conv-name" title={item.entry.alias}>{item.entry.alias}</span>
+                      <span className="library-path">
+                        Deleted {new Date(item.deletedAt).toLocaleDateString()}
+                        {item.purgeAt && `, removed ${new Date(item.purgeAt).toLocaleDateString()}`}
+                        {" · "}{formatBytes(item.sizeBytes)}
+                      </span>
+                    </div>
+                    <button
+                      className="library-remove-button trash-restore-button"
+                      title="Restore"
+                      onClick={() => handleRestoreImport(item.entry.id)}
+                    >
+                      <RotateCcw size={14} />
+                    </button>
+                    <button
+                      className="library-remove-button"
+                      title="Delete forever"
+                      onClick={() => handlePurgeTrash(item.entry.id)}
+                    >
+                      <Trash2 size={14} />
+                    </button>
+                  </div>
+                ))}
+              </div>
+            )}
+            {trash && trash.length > 0 && (
+              <button
+                className={`clear-logs-button ${confirmEmptyTrash ? "confirm" : ""}`}
+                onClick={() => handlePurgeTrash(null)}
+                style={{ marginTop: "0.75rem" }}
+              >
+                <Trash2 size={14} />
+                {confirmEmptyTrash ? "Confirm?" : "Empty Trash"}
+              </button>
+            )}
+          </div>
+
           <div className="settings-section">
             <h3>Disk Space Usage</h3>
             {renderDiskUsage()}
           </div>
+
+          <div className="settings-section">
+            <h3>Clean Up</h3>
+            <p className="settings-description">
+              Find files nothing uses anymore, left by cancelled imports, crashes or older versions
+            </p>
+            {garbageReport && (
+              garbageReport.items.length === 0 ? (
+                <p className="settings-description">Nothing to clean up.</p>
+              ) : (
+                <div className="disk-breakdown">
+                  {Object.entries(garbageLabels).map(([kind, label]) => {
+                    const items = garbageReport.items.filter((item) => item.kind === kind);
+                    if (items.length === 0) return null;
+                    const files = items.reduce((sum, item) => sum + item.files, 0);
+                    const bytes = items.reduce((sum, item) => sum + item.bytes, 0);
+                    return (
+                      <div key={kind} className="disk-breakdown-row">
+                        <span>{label} ({files.toLocaleString()} files)</span>
+                        <span>{formatBytes(bytes)}</span>
+                      </div>
+                    );
+                  })}
+                </div>
+              )
+            )}
+            {garbageReport && garbageReport.totalBytes > 0 ? (
+              <button className="open-logs-button" onClick={() => handleCollectGarbage(false)} disabled={collectingGarbage} style={{ marginTop: "0.75rem" }}>
+                <Trash2 size={18} />
+                {collectingGarbage ? "Cleaning..." : `Reclaim ${formatBytes(garbageReport.totalBytes)}`}
+              </button>
+            ) : (
+              <button className="open-logs-button" onClick={() => handleCollectGarbage(true)} disabled={collectingGarbage} style={{ marginTop: "0.75rem" }}>
+                <HardDrive size={18} />
+                {collectingGarbage ? "Scanning..." : "Find Reclaimable Space"}
+              </button>
+            )}
+          </div>
+
+          <div className="settings-section">
+            <h3>Compact Message Storage</h3>
+            <p className="settings-description">
+              Rewrite message files left fragmented by deleted messages. Messages keep their IDs, so saved positions and replies stay as they are.
+            </p>
+            <button className="open-logs-button" onClick={handleCompactStorage} disabled={compacting}>
+              <Database size={18} />
+              {compacting ? "Compacting..." : "Compact Now"}
+            </button>
+          </div>
         </>
       )}
 
@@ -587,6 +993,40 @@ function Settings({ isOpen, onClose, currentTheme, onThemeChange, imports, onImp
           Open Log Folder
         </button>
       </div>
+
+      <div className="settings-section">
+        <h3>Library Health</h3>
+        <p className="settings-description">
+          Check stored conversations, media and search indexes for damage, and repair what can be repaired.
+        </p>
+        {integrityReport && (
+          <div className="integrity-report">
+            <p className="settings-description">
+              Checked {integrityReport.importsChecked} conversation(s) and {integrityReport.messagesChecked} messages:{" "}
+              {integrityReport.issues.length === 0 ? "no problems found." : `${integrityReport.issues.length} issue(s) found.`}
+            </p>
+            {integrityReport.issues.map((issue, idx) => (
+              <div key={idx} className="integrity-issue">
+                <AlertTriangle size={14} />
+                <div>
+                  <span className="integrity-issue-detail">{issue.detail}</span>
+                  {issue.path && <span className="library-path">{issue.path}</span>}
+                </div>
+              </div>
+            ))}
+          </div>
+        )}
+        <button className="open-logs-button" onClick={handleVerifyLibrary} disabled={checkingLibrary} style={{ marginTop: "1rem" }}>
+          <HardDrive size={20} />
+          {checkingLibrary ? "Checking..." : "Verify Library"}
+        </button>
+        {integrityReport?.issues.some((issue) => issue.repair) && (
+          <button className="open-logs-button" onClick={handleRepairLibrary} disabled={checkingLibrary} style={{ marginTop: "0.5rem" }}>
+            <Wrench size={20} />
+            Repair Issues
+          </button>
+        )}
+      </div>
     </div>
   );
 